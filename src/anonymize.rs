@@ -1,74 +1,325 @@
-use anyhow::Result;
-use chrono::Utc;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
 use colored::Colorize;
-use dialoguer::Confirm;
+use dialoguer::{Confirm, Select};
+use git2::Oid;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
 
-use crate::GitAnon;
-use crate::git::GitOps;
+use crate::git::{
+    AuthorDatePolicy, CommitterDatePolicy, GitOps, RewriteOptions, RewrittenBranches,
+    TimestampStrategy,
+};
+use crate::interrupt;
+use crate::journal::{JournaledBranch, JournaledTag, Operation};
+use crate::mapping::{
+    AuthorMap, CommitIdentity, CommitMap, IdentityMapping, Plan, PlannedIdentity,
+};
+use crate::metadata::MetadataFile;
+use crate::output::{Outcome, Reporter};
+use crate::report::Report;
+use crate::scrub::{ForbiddenIdentities, IdentityScrubber};
+use crate::strip::{IGNORE_FILE, StripRules};
+use crate::warnings::Warnings;
+use crate::{
+    AnonymizeOptions, AnonymousIdentity, BackupConflict, BackupOptions, CleanOptions,
+    DEFAULT_SQUASH_MESSAGE, GitAnon, PushOptions, ReflogMode, SquashOptions,
+};
 
 impl GitAnon {
-    pub fn squash(&self, message: Option<String>, no_confirm: bool, dry_run: bool) -> Result<()> {
-        let git = GitOps::open(&self.repo_path)?;
+    pub fn squash(&self, no_confirm: bool, dry_run: bool, options: &SquashOptions) -> Result<()> {
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        self.verify_objects(&git)?;
         let branch = git.current_branch()?;
 
-        if git.has_uncommitted_changes()? {
-            anyhow::bail!("Uncommitted changes detected. Please commit or stash them first.");
+        if !self.check_clean_tree(&git, no_confirm || dry_run, true)? {
+            return Ok(());
+        }
+
+        let into = options.into.as_deref();
+        if let Some(into) = into {
+            validate_into_branch(into, &branch)?;
         }
+        let collapsed = match options.keep_last {
+            Some(keep) => git
+                .summarize_range(None, &format!("HEAD~{keep}"))
+                .map_err(|_| {
+                    anyhow::anyhow!("--keep-last {keep} keeps the whole history; nothing to squash")
+                })?,
+            None => git.summarize_range(options.from.as_deref(), "HEAD")?,
+        };
+        let strip = match options.keep_last {
+            Some(_) => {
+                if !git.strip_rules(&[])?.is_empty() {
+                    self.warnings.warn(format!(
+                        "{IGNORE_FILE} is not applied with --keep-last; the kept commits would bring the files back"
+                    ));
+                }
+                StripRules::default()
+            }
+            None => git.strip_rules(&options.strip)?,
+        };
+
+        self.warn_large_blobs(&git)?;
 
-        let message = message.unwrap_or_else(|| "Initial commit".to_string());
+        let message = render_squash_message(
+            options.message.as_deref().unwrap_or(DEFAULT_SQUASH_MESSAGE),
+            &branch,
+            || Ok(collapsed.commits),
+        )?;
+        let reviewed = self.reviewed_plan()?;
+        let backup = &planned_backup(&options.backup, reviewed.as_ref());
+        let backup_target = match into.is_some() || backup.disabled {
+            true => None,
+            false => Some(resolve_backup(&git, backup, || {
+                format!("backup-{}-{}", branch, Utc::now().timestamp())
+            })?),
+        };
+        self.check_plan(reviewed.as_ref(), dry_run, || {
+            self.build_plan(
+                &git,
+                "squash",
+                &branch,
+                None,
+                None,
+                backup_target.as_ref().and_then(Backup::branch),
+            )
+        })?;
+
+        let contributors = if dry_run || !no_confirm || self.reporter.is_json() {
+            git.distinct_identity_count(None, None)?
+        } else {
+            0
+        };
 
         if dry_run {
-            let backup_branch = format!("backup-{}-{}", branch, Utc::now().timestamp());
-            println!("{}", "[DRY RUN] Squash operation preview:".blue().bold());
-            println!("  Current branch: {}", branch.yellow());
-            println!("  New commit message: {}", message.cyan());
-            println!(
+            self.reporter
+                .say("[DRY RUN] Squash operation preview:".blue().bold());
+            self.reporter
+                .say(format_args!("  Current branch: {}", branch.yellow()));
+            self.reporter
+                .say(format_args!("  New commit message: {}", message.cyan()));
+            self.reporter.say(format_args!(
                 "  Anonymous identity: {} <{}>",
                 self.identity.name, self.identity.email
-            );
-            println!("  Backup branch name: {}", backup_branch.green());
-            println!(
-                "  {} All commits would be squashed into a single anonymous commit",
-                "→".blue()
-            );
-            println!("  {} A backup branch would be created", "→".blue());
+            ));
+            self.reporter.say(format_args!(
+                "  {} {} commits would be collapsed, made between {} and {}",
+                "→".blue(),
+                collapsed.commits,
+                format_timestamp(collapsed.oldest),
+                format_timestamp(collapsed.newest)
+            ));
+            self.reporter.say(format_args!(
+                "  {} {} distinct contributors would be collapsed into one identity",
+                "→".blue(),
+                contributors
+            ));
+            self.report_stripped(&git.paths_to_strip(&strip)?, true);
+            match into {
+                Some(into) => {
+                    self.reporter.say(format_args!(
+                        "  {} The squashed commit would be written to branch {}",
+                        "→".blue(),
+                        into.green()
+                    ));
+                    self.reporter.say(format_args!(
+                        "  {} {} would be left untouched",
+                        "→".blue(),
+                        branch
+                    ));
+                }
+                None => {
+                    if let Some(backup_target) = &backup_target {
+                        self.reporter.say(format_args!(
+                            "  Backup {}: {}",
+                            backup_target.kind(),
+                            backup_target.to_string().green()
+                        ));
+                    }
+                    match (options.keep_last, &options.from) {
+                        (Some(keep), _) => self.reporter.say(format_args!(
+                            "  {} All but the last {} commits would be squashed into one base commit; those {} would be anonymized on top",
+                            "→".blue(),
+                            keep,
+                            keep
+                        )),
+                        (None, Some(from)) => self.reporter.say(format_args!(
+                            "  {} Commits after {} would be squashed into a single anonymous commit on top of it",
+                            "→".blue(),
+                            from.cyan()
+                        )),
+                        (None, None) => self.reporter.say(format_args!(
+                            "  {} All commits would be squashed into a single anonymous commit",
+                            "→".blue()
+                        )),
+                    }
+                    match &backup_target {
+                        Some(backup_target) => self.reporter.say(format_args!(
+                            "  {} A backup {} would be created",
+                            "→".blue(),
+                            backup_target.kind()
+                        )),
+                        None => self
+                            .reporter
+                            .say(format_args!("  {} No backup would be kept", "→".blue())),
+                    }
+                }
+            }
+            self.reporter.emit(&Outcome {
+                contributors: Some(contributors),
+                backup: backup_target.as_ref().map(Backup::to_string),
+                ..self.outcome("squash", &branch, true, collapsed.commits)
+            });
             return Ok(());
         }
 
         if !no_confirm {
-            println!(
-                "{}",
-                "WARNING: This will squash ALL commits into a single anonymous commit!"
-                    .red()
-                    .bold()
-            );
-            println!("Current branch: {}", branch.yellow());
-            println!("New commit message: {}", message.cyan());
-            println!(
+            match into {
+                Some(into) => self.reporter.say(format_args!(
+                    "Squashing {} into a single anonymous commit on {}",
+                    branch.yellow(),
+                    into.green()
+                )),
+                None => {
+                    let warning = match (options.keep_last, &options.from) {
+                        (Some(keep), _) => format!(
+                            "WARNING: This will squash all but the last {keep} commits into one anonymous commit!"
+                        ),
+                        (None, Some(from)) => format!(
+                            "WARNING: This will squash every commit after {from} into one anonymous commit!"
+                        ),
+                        (None, None) => {
+                            "WARNING: This will squash ALL commits into a single anonymous commit!"
+                                .to_string()
+                        }
+                    };
+                    self.reporter.say(warning.red().bold());
+                    if backup_target.is_none() {
+                        self.reporter.say(
+                            "No backup will be kept; the original history will only be in the reflog"
+                                .red(),
+                        );
+                    }
+                    self.reporter
+                        .say(format_args!("Current branch: {}", branch.yellow()));
+                }
+            }
+            self.reporter
+                .say(format_args!("New commit message: {}", message.cyan()));
+            self.reporter.say(format_args!(
                 "Anonymous identity: {} <{}>",
                 self.identity.name, self.identity.email
-            );
-            println!();
+            ));
+            self.reporter.say(format_args!(
+                "Commits collapsed: {} ({} to {})",
+                collapsed.commits.to_string().yellow(),
+                format_timestamp(collapsed.oldest),
+                format_timestamp(collapsed.newest)
+            ));
+            self.reporter.say(format_args!(
+                "Distinct contributors collapsed: {}",
+                contributors.to_string().yellow()
+            ));
+            self.reporter.say("");
 
             if !Confirm::new()
                 .with_prompt("Continue?")
                 .default(false)
                 .interact()?
             {
-                println!("Aborted.");
+                self.reporter.say("Aborted.");
                 return Ok(());
             }
         }
 
-        let backup_branch = format!("backup-{}-{}", branch, Utc::now().timestamp());
-        println!("Creating backup branch: {}", backup_branch.green());
-        git.create_backup_branch(&backup_branch)?;
+        let original = match &backup_target {
+            Some(backup_target) => create_backup(
+                self.reporter,
+                &git,
+                backup_target,
+                &branch,
+                backup.on_conflict == BackupConflict::Overwrite,
+            )?,
+            None => git.branch_tip(&branch)?.to_string(),
+        };
 
-        println!("Squashing all commits...");
-        git.squash_all_commits(&self.identity, &message, &branch)?;
+        let target = into.unwrap_or(&branch);
+        self.journal(&git, "squash", &[target], backup_target.as_ref())?;
+        let (tree, stripped) = match strip.is_empty() {
+            true => (None, Vec::new()),
+            false => {
+                let (tree, stripped) = git.strip_tree(git.head_tree()?.id(), &strip)?;
+                (Some(tree).filter(|_| !stripped.is_empty()), stripped)
+            }
+        };
+        let mapping = match (options.keep_last, &options.from) {
+            (Some(keep), _) => {
+                self.reporter
+                    .say(format_args!("Squashing all but the last {keep} commits..."));
+                git.squash_keeping_last(&self.identity, &message, target, keep, options.timestamps)
+                    .map_err(|e| self.interrupted(&git, true, backup_target.as_ref(), e))?
+            }
+            (None, from) => {
+                match from {
+                    Some(from) => self
+                        .reporter
+                        .say(format_args!("Squashing the commits after {from}...")),
+                    None => self.reporter.say("Squashing all commits..."),
+                }
+                git.squash_range(
+                    &self.identity,
+                    &message,
+                    target,
+                    from.as_deref(),
+                    "HEAD",
+                    tree,
+                    options.timestamps,
+                )?
+            }
+        };
+        self.record_mapping(&mapping);
+        self.check_forbidden(&git, &mapping)?;
+        if into.is_none() {
+            git.untrack_paths(&stripped)?;
+        }
+        self.report_stripped(&stripped, false);
+        verify_snapshot(self.reporter, &git, target, &original, &stripped)?;
 
-        println!("{} Successfully squashed all commits!", "✓".green());
-        println!("Backup saved to branch: {}", backup_branch.yellow());
+        match &options.from {
+            Some(from) => self.reporter.say(format_args!(
+                "{} Successfully squashed the commits after {from}!",
+                "✓".green()
+            )),
+            None => self.reporter.say(format_args!(
+                "{} Successfully squashed all commits!",
+                "✓".green()
+            )),
+        }
+        match (&backup_target, into) {
+            (Some(backup_target), _) => self.reporter.say(format_args!(
+                "Backup saved to {}: {}",
+                backup_target.kind(),
+                backup_target.to_string().yellow()
+            )),
+            (None, Some(_)) => self.reporter.say(format_args!(
+                "Squashed commit written to {}; {} is unchanged",
+                target.yellow(),
+                branch
+            )),
+            (None, None) => {}
+        }
+        self.reporter.emit(&Outcome {
+            contributors: Some(contributors),
+            backup: backup_target.as_ref().map(Backup::to_string),
+            ..self.outcome("squash", target, false, mapping.len())
+        });
 
         Ok(())
     }
@@ -76,143 +327,2387 @@ impl GitAnon {
     pub fn push(
         &self,
         remote: &str,
-        branch: Option<String>,
-        force: bool,
+        branches: Vec<String>,
+        no_confirm: bool,
         dry_run: bool,
+        options: &PushOptions,
     ) -> Result<()> {
-        let git = GitOps::open(&self.repo_path)?;
+        if options.all_branches || branches.len() > 1 {
+            return self.push_branches(remote, branches, no_confirm, dry_run, options);
+        }
+        let branch = branches.into_iter().next();
+        let force = options.force;
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        self.verify_objects(&git)?;
+        check_push_remotes(&git, remote, options)?;
         let current_branch = git.current_branch()?;
         let branch = branch.unwrap_or(current_branch);
+        if let Some(into) = &options.into {
+            validate_into_branch(into, &branch)?;
+        }
 
-        if git.has_uncommitted_changes()? {
-            anyhow::bail!("Uncommitted changes detected. Please commit or stash them first.");
+        let since_commit = match &options.since_tag {
+            Some(tag) => Some(git.resolve_tag_boundary(tag)?.to_string()),
+            None => git
+                .get_remote_tracking_branch(remote, &branch)?
+                .map(|oid| oid.to_string()),
+        };
+        if options.count_only {
+            let count = git.count_commits_to_anonymize(Some(&branch), since_commit.as_deref())?;
+            match self.reporter.is_json() {
+                true => self.reporter.emit(&Outcome {
+                    remote: Some(remote.to_string()),
+                    ..self.outcome("push", &branch, true, count as usize)
+                }),
+                false => self.reporter.say(count),
+            }
+            return Ok(());
+        }
+
+        if !self.check_clean_tree(&git, no_confirm || dry_run, true)? {
+            return Ok(());
+        }
+
+        if options.check_remote_empty {
+            self.reporter.say(format_args!(
+                "Checking that {remote} has no {branch} branch..."
+            ));
+            if git.remote_has_branch(remote, &branch)? {
+                anyhow::bail!(
+                    "{remote} already has a {branch} branch; refusing to publish over existing history"
+                );
+            }
+        }
+
+        self.reporter.say("Checking for commits to anonymize...");
+        let count = git.count_commits_to_anonymize(Some(&branch), since_commit.as_deref())?;
+        let mut rewrite = options.rewrite.clone();
+        if let Some(range) = &options.range {
+            rewrite.only_commits = Some(git.resolve_range(range)?);
+        }
+
+        if let Some(path) = &options.email_map_out {
+            let commits = git.plan_commits(Some(&branch), since_commit.as_deref())?;
+            let originals: BTreeSet<&str> = commits
+                .iter()
+                .filter(|commit| {
+                    let only = rewrite.only_commits.as_ref();
+                    Oid::from_str(&commit.oid)
+                        .is_ok_and(|oid| only.is_none_or(|only| only.contains(&oid)))
+                })
+                .flat_map(|commit| [commit.author.as_str(), commit.committer.as_str()])
+                .collect();
+            AuthorMap::template(originals.iter().copied()).write(path)?;
+            self.reporter.say(format_args!(
+                "Wrote {} identities to {}; fill in replacements and pass it to --author-map",
+                originals.len(),
+                path.display().to_string().cyan()
+            ));
+            return Ok(());
+        }
+
+        if let Some(path) = &options.commit_map_out {
+            let commits = git.plan_commits(Some(&branch), since_commit.as_deref())?;
+            let map = CommitMap {
+                commits: commits
+                    .into_iter()
+                    .map(|commit| {
+                        let identity = rewrite
+                            .author_map
+                            .get(&commit.author)
+                            .unwrap_or(&self.identity);
+                        CommitIdentity {
+                            identity: format!("{} <{}>", identity.name, identity.email),
+                            oid: commit.oid,
+                            original: commit.author,
+                        }
+                    })
+                    .collect(),
+            };
+            map.write(path)?;
+            self.reporter.say(format_args!(
+                "Wrote {} commits to {}; edit the identities and pass it to --commit-map",
+                map.commits.len(),
+                path.display().to_string().cyan()
+            ));
+            return Ok(());
         }
 
-        println!("Checking for commits to anonymize...");
+        if !rewrite.commit_map.is_empty() {
+            let in_range: HashSet<Oid> = git
+                .plan_commits(Some(&branch), since_commit.as_deref())?
+                .iter()
+                .filter_map(|commit| Oid::from_str(&commit.oid).ok())
+                .collect();
+            let unknown: Vec<String> = rewrite
+                .commit_map
+                .keys()
+                .filter(|oid| !in_range.contains(oid))
+                .map(|oid| oid.to_string())
+                .collect();
+            if !unknown.is_empty() {
+                anyhow::bail!(
+                    "The commit map lists commits that are not being anonymized: {}",
+                    unknown.join(", ")
+                );
+            }
+        }
 
-        let remote_oid = git.get_remote_tracking_branch(remote, &branch)?;
-        let since_commit = remote_oid.map(|oid| oid.to_string());
-        let count = git.count_commits_to_anonymize(since_commit.as_deref())?;
+        let reviewed = self.reviewed_plan()?;
+        self.check_plan(reviewed.as_ref(), dry_run, || {
+            self.build_plan(
+                &git,
+                "push",
+                &branch,
+                Some(remote),
+                since_commit.as_deref(),
+                None,
+            )
+        })?;
 
         if count == 0 {
-            println!("Already up to date with {remote}/{branch}");
+            match &options.since_tag {
+                Some(tag) => self
+                    .reporter
+                    .say(format_args!("No commits after tag {tag}")),
+                None => self
+                    .reporter
+                    .say(format_args!("Already up to date with {remote}/{branch}")),
+            }
+            self.reporter.emit(&Outcome {
+                remote: Some(remote.to_string()),
+                ..self.outcome("push", &branch, dry_run, 0)
+            });
             return Ok(());
         }
 
+        self.warn_large_blobs(&git)?;
+        self.warn_encrypted_content(&git)?;
+        let pseudonyms = git.pseudonym_map(
+            Some(&branch),
+            since_commit.as_deref(),
+            &rewrite,
+            &self.identity,
+        )?;
+
         if dry_run {
-            println!("{}", "[DRY RUN] Push operation preview:".blue().bold());
-            println!("  Target remote: {}", remote.yellow());
-            println!("  Target branch: {}", branch.yellow());
-            println!(
+            self.reporter
+                .say("[DRY RUN] Push operation preview:".blue().bold());
+            self.reporter
+                .say(format_args!("  Target remote: {}", remote.yellow()));
+            self.reporter
+                .say(format_args!("  Target branch: {}", branch.yellow()));
+            self.reporter.say(format_args!(
                 "  Force push: {}",
                 if force { "yes".red() } else { "no".green() }
-            );
-            println!(
+            ));
+            self.reporter.say(format_args!(
                 "  Anonymous identity: {} <{}>",
                 self.identity.name, self.identity.email
-            );
-            println!("  {} {} commits would be anonymized", "→".blue(), count);
-            println!(
+            ));
+            self.reporter.say(format_args!(
+                "  {} {} commits would be anonymized",
+                "→".blue(),
+                count
+            ));
+            if let Some(range) = &options.range {
+                self.reporter.say(format_args!(
+                    "  {} Only commits in {} would be anonymized",
+                    "→".blue(),
+                    range.cyan()
+                ));
+            }
+            if let Some(pattern) = &options.rewrite.pathspec {
+                self.reporter.say(format_args!(
+                    "  {} Only commits touching {} would be anonymized",
+                    "→".blue(),
+                    pattern.as_str().cyan()
+                ));
+            }
+            if !options.rewrite.author_map.is_empty() {
+                self.reporter.say(format_args!(
+                    "  {} {} identities would get replacements from the author map",
+                    "→".blue(),
+                    options.rewrite.author_map.len()
+                ));
+            }
+            if !options.rewrite.commit_map.is_empty() {
+                self.reporter.say(format_args!(
+                    "  {} {} commits would get identities from the commit map",
+                    "→".blue(),
+                    options.rewrite.commit_map.len()
+                ));
+            }
+            if options.rewrite.keep_authors {
+                self.reporter.say(format_args!(
+                    "  {} Original authors would be kept; only committers are replaced",
+                    "→".blue()
+                ));
+            }
+            print_pseudonyms(self.reporter, &pseudonyms, true);
+            self.reporter.say(format_args!(
+                "  {} {} distinct identities would be replaced",
+                "→".blue(),
+                git.distinct_identity_count(Some(&branch), since_commit.as_deref())?
+            ));
+            self.reporter.say(format_args!(
                 "  {} Commits would be pushed to {}/{}",
                 "→".blue(),
                 remote,
                 branch
-            );
+            ));
+            if let Some(mode) = options.reflog {
+                let action = match mode {
+                    ReflogMode::Scrub => "scrubbed of original identities",
+                    ReflogMode::Clear => "cleared",
+                };
+                self.reporter.say(format_args!(
+                    "  {} Reflogs of the branch and HEAD would be {action}",
+                    "→".blue()
+                ));
+            }
+            if let Some(backup_remote) = &options.backup_remote {
+                self.reporter.say(format_args!(
+                    "  {} The original {} would first be pushed to {}",
+                    "→".blue(),
+                    branch,
+                    backup_remote.yellow()
+                ));
+            }
+            if let Some(into) = &options.into {
+                self.reporter.say(format_args!(
+                    "  {} Anonymized history would be kept on local branch {}",
+                    "→".blue(),
+                    into.green()
+                ));
+            }
+            if options.include_tags {
+                let commits: HashSet<Oid> = git
+                    .plan_commits(Some(&branch), since_commit.as_deref())?
+                    .iter()
+                    .filter_map(|commit| Oid::from_str(&commit.oid).ok())
+                    .collect();
+                self.reporter.say(format_args!(
+                    "  {} {} tags would be moved to the anonymized commits and pushed",
+                    "→".blue(),
+                    git.tags_targeting(&commits)?.len()
+                ));
+            }
+            self.reporter.emit(&Outcome {
+                remote: Some(remote.to_string()),
+                ..self.outcome("push", &branch, true, count as usize)
+            });
             return Ok(());
         }
 
-        let anonymized_count =
-            git.anonymize_commits(&self.identity, &branch, since_commit.as_deref())?;
+        if let Some(backup_remote) = &options.backup_remote {
+            self.push_backup_first(&git, backup_remote, &[branch.as_str()])?;
+        }
+        let local_branch = options.into.as_deref().unwrap_or(&branch);
+        print_pseudonyms(self.reporter, &pseudonyms, false);
+        self.journal(&git, "push", &[local_branch], None)?;
+        let mapping = git
+            .anonymize_branch_into(
+                &self.identity,
+                &branch,
+                local_branch,
+                since_commit.as_deref(),
+                &rewrite,
+            )
+            .map_err(|e| self.interrupted(&git, true, None, e))?;
+        self.record_mapping(&mapping);
+        self.check_forbidden(&git, &mapping)?;
+
+        let tags = match options.include_tags {
+            true => self.retarget_tags(&git, &mapping)?,
+            false => Vec::new(),
+        };
+        let refs: Vec<(String, String)> = [(local_branch.to_string(), branch.clone())]
+            .into_iter()
+            .chain(tag_refs(&tags))
+            .collect();
+        self.check_push_tips(&git, &refs, &mapping, options.allow_real_tip)?;
 
-        println!("Pushing to {remote}...");
-        git.push_to_remote(remote, &branch, force)?;
+        if let Some(mode) = options.reflog {
+            let mut rewritten = 0;
+            for refname in [format!("refs/heads/{local_branch}"), "HEAD".to_string()] {
+                rewritten += git.rewrite_reflog(&refname, &self.identity, mode, &rewrite)?;
+            }
+            self.reporter
+                .say(format_args!("Rewrote {rewritten} reflog entries"));
+        }
 
-        println!(
+        let remotes = push_targets(remote, options);
+        self.reporter
+            .say(format_args!("Pushing to {}...", remotes.join(", ")));
+        self.push_to_remotes(&git, &remotes, &refs, force, options.parallel_push)?;
+
+        self.reporter.say(format_args!(
             "{} Successfully pushed {} anonymized commits to {}",
             "✓".green(),
-            anonymized_count,
-            remote
-        );
+            mapping.len(),
+            remotes.join(", ")
+        ));
+        self.reporter.emit(&Outcome {
+            remote: Some(remotes.join(",")),
+            ..self.outcome("push", &branch, false, mapping.len())
+        });
 
         Ok(())
     }
 
-    pub fn clean(&self, no_confirm: bool, dry_run: bool) -> Result<()> {
-        let git = GitOps::open(&self.repo_path)?;
+    /// `push --all-branches`, or `push` with several branches: rewrites them
+    /// with one shared commit map, so history the branches share stays
+    /// shared, and pushes them all at once.
+    fn push_branches(
+        &self,
+        remote: &str,
+        branches: Vec<String>,
+        no_confirm: bool,
+        dry_run: bool,
+        options: &PushOptions,
+    ) -> Result<()> {
+        if self.plan_out.is_some() || self.plan_in.is_some() {
+            anyhow::bail!("--plan-out and --plan-in only support pushing a single branch");
+        }
+        let single_branch_only = [
+            (options.into.is_some(), "--into"),
+            (options.count_only, "--count-only"),
+            (options.check_remote_empty, "--check-remote-empty"),
+            (options.since_tag.is_some(), "--since-tag"),
+            (options.range.is_some(), "--range"),
+            (options.email_map_out.is_some(), "--email-map-out"),
+            (options.commit_map_out.is_some(), "--commit-map-out"),
+        ];
+        if let Some((_, flag)) = single_branch_only.iter().find(|(set, _)| *set) {
+            anyhow::bail!("{flag} only supports pushing a single branch");
+        }
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        self.verify_objects(&git)?;
+        check_push_remotes(&git, remote, options)?;
+
+        let branches = match options.all_branches {
+            true => git.local_branches()?,
+            false => {
+                if let Some(missing) = branches.iter().find(|branch| !git.branch_exists(branch)) {
+                    anyhow::bail!("Branch {missing} does not exist");
+                }
+                branches
+            }
+        };
+        let branches = branches
+            .into_iter()
+            .map(|branch| {
+                let since = git.get_remote_tracking_branch(remote, &branch)?;
+                Ok((branch, since.map(|oid| oid.to_string())))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if branches.is_empty() {
+            self.reporter.say("No local branches to anonymize");
+            self.reporter.emit(&Outcome {
+                remote: Some(remote.to_string()),
+                ..self.outcome("push", "", dry_run, 0)
+            });
+            return Ok(());
+        }
+
+        if !self.check_clean_tree(&git, no_confirm || dry_run, true)? {
+            return Ok(());
+        }
+
+        self.reporter.say("Checking for commits to anonymize...");
+        let branch_names: Vec<&str> = branches.iter().map(|(branch, _)| branch.as_str()).collect();
+        let counts = git.count_branch_commits(&branches)?;
+        if counts.iter().all(|(_, count)| *count == 0) {
+            self.reporter.say(format_args!(
+                "All {} branches are up to date with {remote}",
+                branches.len()
+            ));
+            self.reporter.emit(&Outcome {
+                remote: Some(remote.to_string()),
+                ..self.outcome("push", &branch_names.join(","), dry_run, 0)
+            });
+            return Ok(());
+        }
+
+        git.check_branch_identities(&self.identity, &options.branch_identities, &branches)?;
+        self.warn_large_blobs(&git)?;
+        self.warn_encrypted_content(&git)?;
 
         if dry_run {
-            println!("{}", "[DRY RUN] Clean operation preview:".blue().bold());
-            let branch = git.current_branch()?;
-            let backup_branch = format!("pre-clean-backup-{}", Utc::now().timestamp());
-            println!("  Current branch: {}", branch.yellow());
-            println!("  Backup branch name: {}", backup_branch.green());
-            println!(
+            self.reporter
+                .say("[DRY RUN] Push operation preview:".blue().bold());
+            self.reporter
+                .say(format_args!("  Target remote: {}", remote.yellow()));
+            self.reporter.say(format_args!(
+                "  Force push: {}",
+                if options.force {
+                    "yes".red()
+                } else {
+                    "no".green()
+                }
+            ));
+            self.reporter.say(format_args!(
                 "  Anonymous identity: {} <{}>",
                 self.identity.name, self.identity.email
+            ));
+            for (branch, count) in &counts {
+                self.reporter.say(format_args!(
+                    "  {} {}: {} commits would be anonymized",
+                    "→".blue(),
+                    branch.yellow(),
+                    count
+                ));
+                if let Some(identity) = options.branch_identities.get(branch) {
+                    self.reporter.say(format_args!(
+                        "    as {} <{}>",
+                        identity.name, identity.email
+                    ));
+                }
+            }
+            if let Some(backup_remote) = &options.backup_remote {
+                self.reporter.say(format_args!(
+                    "  {} The original branches would first be pushed to {}",
+                    "→".blue(),
+                    backup_remote.yellow()
+                ));
+            }
+            self.reporter.emit(&Outcome {
+                remote: Some(remote.to_string()),
+                ..self.outcome(
+                    "push",
+                    &branch_names.join(","),
+                    true,
+                    counts.iter().map(|(_, count)| count).sum(),
+                )
+            });
+            return Ok(());
+        }
+
+        if let Some(backup_remote) = &options.backup_remote {
+            self.push_backup_first(&git, backup_remote, &branch_names)?;
+        }
+        self.journal(&git, "push", &branch_names, None)?;
+        let RewrittenBranches { mapping, counts } = git
+            .anonymize_branches(
+                &self.identity,
+                &options.branch_identities,
+                &branches,
+                &options.rewrite,
+            )
+            .map_err(|e| self.interrupted(&git, true, None, e))?;
+        self.record_mapping(&mapping);
+        self.check_forbidden(&git, &mapping)?;
+
+        let tags = match options.include_tags {
+            true => self.retarget_tags(&git, &mapping)?,
+            false => Vec::new(),
+        };
+        let refs: Vec<(String, String)> = branches
+            .iter()
+            .map(|(branch, _)| (branch.clone(), branch.clone()))
+            .chain(tag_refs(&tags))
+            .collect();
+        self.check_push_tips(&git, &refs, &mapping, options.allow_real_tip)?;
+
+        if let Some(mode) = options.reflog {
+            let mut rewritten = 0;
+            let refnames = branches
+                .iter()
+                .map(|(branch, _)| {
+                    let identity = options.branch_identities.get(branch);
+                    (format!("refs/heads/{branch}"), identity)
+                })
+                .chain([("HEAD".to_string(), None)]);
+            for (refname, identity) in refnames {
+                let identity = identity.unwrap_or(&self.identity);
+                rewritten += git.rewrite_reflog(&refname, identity, mode, &options.rewrite)?;
+            }
+            self.reporter
+                .say(format_args!("Rewrote {rewritten} reflog entries"));
+        }
+
+        let remotes = push_targets(remote, options);
+        self.reporter
+            .say(format_args!("Pushing to {}...", remotes.join(", ")));
+        self.push_to_remotes(&git, &remotes, &refs, options.force, options.parallel_push)?;
+
+        self.reporter.say(format_args!(
+            "{} Successfully pushed {} anonymized commits to {}",
+            "✓".green(),
+            mapping.len(),
+            remotes.join(", ")
+        ));
+        for (branch, count) in &counts {
+            self.reporter
+                .say(format_args!("  {}: {} commits", branch.yellow(), count));
+        }
+        self.reporter.emit(&Outcome {
+            remote: Some(remotes.join(",")),
+            ..self.outcome("push", &branch_names.join(","), false, mapping.len())
+        });
+
+        Ok(())
+    }
+
+    /// Refuses to push a tip that still has an identity the rewrite replaced
+    /// elsewhere, even with `--force`, putting the branches and tags of the
+    /// run just journaled back where they were. `--range` and `--pathspec`
+    /// can leave such a tip untouched on purpose; `--allow-real-tip` then
+    /// pushes it with a warning.
+    fn check_push_tips(
+        &self,
+        git: &GitOps,
+        refs: &[(String, String)],
+        mapping: &HashMap<Oid, Oid>,
+        allow: bool,
+    ) -> Result<()> {
+        let Some(tip) = git.real_push_tip(refs, mapping)? else {
+            return Ok(());
+        };
+        if !allow {
+            if let Some(operation) = git.journal().pop()? {
+                restore_branches(git, &operation, "rolled back", !operation.dirty)?;
+            }
+            anyhow::bail!(
+                "Refusing to push {tip}; the branches were rolled back\n\
+                 Anonymize it too, or pass --allow-real-tip to push it anyway"
             );
-            println!("  {} All commits would be squashed into one", "→".blue());
-            println!("  {} All git history would be removed", "→".blue());
-            println!("  {} Git submodules would be removed", "→".blue());
-            println!("  {} Git reflog would be cleaned", "→".blue());
-            println!(
-                "  {} Aggressive garbage collection would be performed",
+        }
+        self.warnings
+            .warn(format!("Pushing {tip} (--allow-real-tip)"));
+        Ok(())
+    }
+
+    /// Pushes the still un-anonymized `branches` to `backup_remote` under
+    /// their own names, so the real history is safe elsewhere before the
+    /// public push. Not forced: a backup that has diverged is not clobbered.
+    fn push_backup_first(
+        &self,
+        git: &GitOps,
+        backup_remote: &str,
+        branches: &[&str],
+    ) -> Result<()> {
+        self.reporter.say(format_args!(
+            "Backing up the original history to {backup_remote}..."
+        ));
+        let refs: Vec<(String, String)> = branches
+            .iter()
+            .map(|branch| (branch.to_string(), branch.to_string()))
+            .collect();
+        git.push_refs_as(backup_remote, &refs, false)
+            .with_context(|| {
+                format!(
+                    "Backup push to {backup_remote} failed; nothing was anonymized or published"
+                )
+            })?;
+        self.reporter.say(format_args!(
+            "{} Original history backed up to {}",
+            "✓".green(),
+            backup_remote
+        ));
+        Ok(())
+    }
+
+    /// Pushes `refs` to each of `remotes`: one after another, or with
+    /// `parallel` all at once, each on its own repository handle since
+    /// libgit2 handles can't be shared between threads. With several remotes
+    /// every one is tried and reported as it finishes, and the push fails
+    /// afterwards if any of them did.
+    fn push_to_remotes(
+        &self,
+        git: &GitOps,
+        remotes: &[&str],
+        refs: &[(String, String)],
+        force: bool,
+        parallel: bool,
+    ) -> Result<()> {
+        if let [remote] = remotes {
+            return git.push_refs_as(remote, refs, force);
+        }
+
+        let mut failed = Vec::new();
+        let mut report = |remote: &str, result: Result<()>| match result {
+            Ok(()) => self
+                .reporter
+                .say(format_args!("  {} {remote}", "✓".green())),
+            Err(e) => {
+                self.reporter
+                    .say(format_args!("  {} {remote}: {e:#}", "✗".red()));
+                failed.push(remote.to_string());
+            }
+        };
+        if parallel {
+            let (repo_path, identity_file, git_config) =
+                (&self.repo_path, &self.identity_file, &self.git_config);
+            let (sender, receiver) = mpsc::channel();
+            thread::scope(|scope| {
+                for &remote in remotes {
+                    let sender = sender.clone();
+                    scope.spawn(move || {
+                        let result = GitOps::open(repo_path)
+                            .map(|git| git.with_identity_file(identity_file.clone()))
+                            .and_then(|git| git.with_config_overrides(git_config))
+                            .and_then(|git| git.push_refs_as(remote, refs, force));
+                        // The receiver outlives every sender.
+                        let _ = sender.send((remote, result));
+                    });
+                }
+                drop(sender);
+                for (remote, result) in receiver {
+                    report(remote, result);
+                }
+            });
+        } else {
+            for &remote in remotes {
+                report(remote, git.push_refs_as(remote, refs, force));
+            }
+        }
+
+        match failed.is_empty() {
+            true => Ok(()),
+            false => anyhow::bail!("Failed to push to {}", failed.join(", ")),
+        }
+    }
+
+    pub fn clean(&self, no_confirm: bool, dry_run: bool, options: &CleanOptions) -> Result<()> {
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        self.verify_objects(&git)?;
+        let reviewed = self.reviewed_plan()?;
+        let backup = &planned_backup(&options.backup, reviewed.as_ref());
+        let no_gc = options.no_gc;
+        let backup_target = match backup.disabled {
+            true => None,
+            false => Some(resolve_backup(&git, backup, || {
+                format!("pre-clean-backup-{}", Utc::now().timestamp())
+            })?),
+        };
+        self.check_plan(reviewed.as_ref(), dry_run, || {
+            let branch = git.current_branch()?;
+            self.build_plan(
+                &git,
+                "clean",
+                &branch,
+                None,
+                None,
+                backup_target.as_ref().and_then(Backup::branch),
+            )
+        })?;
+
+        self.warn_large_blobs(&git)?;
+        self.warn_encrypted_content(&git)?;
+        let metadata_files = git.find_metadata_files()?;
+        report_metadata_files(&self.warnings, &metadata_files, options.rewrite_metadata);
+        let strip = git.strip_rules(&options.strip)?;
+        let stashes = git.stash_count()?;
+        if stashes > 0 {
+            if options.drop_stashes {
+                self.warnings.warn(format!(
+                    "{stashes} stash entries will be dropped; any work saved in them will be lost"
+                ));
+            } else {
+                self.warnings.warn(format!(
+                    "{stashes} stash entries keep the original identities and are not anonymized; \
+                     pass --drop-stashes to remove them"
+                ));
+            }
+        }
+
+        if dry_run {
+            self.reporter
+                .say("[DRY RUN] Clean operation preview:".blue().bold());
+            let branch = git.current_branch()?;
+            self.reporter
+                .say(format_args!("  Current branch: {}", branch.yellow()));
+            if let Some(backup_target) = &backup_target {
+                self.reporter.say(format_args!(
+                    "  Backup {}: {}",
+                    backup_target.kind(),
+                    backup_target.to_string().green()
+                ));
+            }
+            self.reporter.say(format_args!(
+                "  Anonymous identity: {} <{}>",
+                self.identity.name, self.identity.email
+            ));
+            self.reporter.say(format_args!(
+                "  {} All commits would be squashed into one",
                 "→".blue()
-            );
-            println!("  {} A backup branch would be created", "→".blue());
-            println!("  {}", "WARNING: This would be IRREVERSIBLE!".red().bold());
+            ));
+            self.report_stripped(&git.paths_to_strip(&strip)?, true);
+            self.reporter.say(format_args!(
+                "  {} All git history would be removed",
+                "→".blue()
+            ));
+            self.reporter.say(format_args!(
+                "  {} Git submodules would be removed",
+                "→".blue()
+            ));
+            self.reporter
+                .say(format_args!("  {} Git reflog would be cleaned", "→".blue()));
+            if options.include_tags {
+                self.reporter.say(format_args!(
+                    "  {} Tags on the current branch would be moved to the new commit",
+                    "→".blue()
+                ));
+            }
+            if options.drop_stashes && stashes > 0 {
+                self.reporter.say(format_args!(
+                    "  {} {} stash entries would be dropped",
+                    "→".blue(),
+                    stashes
+                ));
+            }
+            if no_gc {
+                self.reporter.say(format_args!(
+                    "  {} Garbage collection would be skipped; original objects remain until a gc runs",
+                    "→".blue()
+                ));
+            } else {
+                self.reporter.say(format_args!(
+                    "  {} Garbage collection would run: git {}",
+                    "→".blue(),
+                    options.gc_mode.git_args().join(" ")
+                ));
+            }
+            match &backup_target {
+                Some(backup_target) => self.reporter.say(format_args!(
+                    "  {} A backup {} would be created",
+                    "→".blue(),
+                    backup_target.kind()
+                )),
+                None => self
+                    .reporter
+                    .say(format_args!("  {} No backup would be kept", "→".blue())),
+            }
+            self.reporter.say(format_args!(
+                "  {}",
+                "WARNING: This would be IRREVERSIBLE!".red().bold()
+            ));
+            self.reporter.emit(&Outcome {
+                backup: backup_target.as_ref().map(Backup::to_string),
+                ..self.outcome(
+                    "clean",
+                    &branch,
+                    true,
+                    git.count_commits_to_anonymize(None, None)? as usize,
+                )
+            });
             return Ok(());
         }
 
         if !no_confirm {
-            println!(
-                "{}",
+            self.reporter.say(
                 "WARNING: This will COMPLETELY ANONYMIZE the repository!"
                     .red()
-                    .bold()
+                    .bold(),
             );
-            println!("This includes:");
-            println!("  - Squashing all commits into one");
-            println!("  - Removing all git history");
-            println!("  - Removing git submodules");
-            println!("  - Cleaning git reflog");
-            println!();
+            self.reporter.say("This includes:");
+            self.reporter.say("  - Squashing all commits into one");
+            self.reporter.say("  - Removing all git history");
+            self.reporter.say("  - Removing git submodules");
+            self.reporter.say("  - Cleaning git reflog");
+            if backup_target.is_none() {
+                self.reporter
+                    .say("  - Keeping no backup of the original history");
+            }
+            if options.drop_stashes && stashes > 0 {
+                self.reporter
+                    .say(format_args!("  - Dropping {stashes} stash entries"));
+            }
+            self.reporter.say("");
 
             if !Confirm::new()
                 .with_prompt("This action is IRREVERSIBLE. Continue?")
                 .default(false)
                 .interact()?
             {
-                println!("Aborted.");
+                self.reporter.say("Aborted.");
                 return Ok(());
             }
         }
 
         let branch = git.current_branch()?;
-        let backup_branch = format!("pre-clean-backup-{}", Utc::now().timestamp());
+        let metadata_rewrite = if options.rewrite_metadata && !metadata_files.is_empty() {
+            Some(git.rewrite_metadata_tree(&self.identity)?)
+        } else {
+            None
+        };
 
-        println!("Creating final backup branch: {}", backup_branch.green());
-        git.create_backup_branch(&backup_branch)?;
+        let original = match &backup_target {
+            Some(backup_target) => create_backup(
+                self.reporter,
+                &git,
+                backup_target,
+                &branch,
+                backup.on_conflict == BackupConflict::Overwrite,
+            )?,
+            None => git.branch_tip(&branch)?.to_string(),
+        };
+        self.journal(&git, "clean", &[&branch], backup_target.as_ref())?;
 
-        println!("Squashing all commits...");
-        git.squash_all_commits(&self.identity, "Initial commit", &branch)?;
+        let message = render_squash_message(
+            options.message.as_deref().unwrap_or(DEFAULT_SQUASH_MESSAGE),
+            &branch,
+            || Ok(git.count_commits_to_anonymize(None, None)? as usize),
+        )?;
+        let (mut tree, mut rewritten) = match metadata_rewrite {
+            Some((tree, rewritten)) => (Some(tree), rewritten),
+            None => (None, Vec::new()),
+        };
+        let mut stripped = Vec::new();
+        if !strip.is_empty() {
+            let base = match tree {
+                Some(tree) => tree,
+                None => git.head_tree()?.id(),
+            };
+            let (stripped_tree, paths) = git.strip_tree(base, &strip)?;
+            if !paths.is_empty() {
+                tree = Some(stripped_tree);
+                rewritten.retain(|path| !paths.contains(path));
+                stripped = paths;
+            }
+        }
 
-        println!("Cleaning git history...");
-        let cleanup_commands = [
-            &["reflog", "expire", "--expire=now", "--all"] as &[&str],
-            &["gc", "--prune=now", "--aggressive"] as &[&str],
-        ];
+        self.reporter.say("Squashing all commits...");
+        let mapping = match tree {
+            Some(tree) => git.squash_all_commits_onto_tree(
+                &self.identity,
+                &message,
+                &branch,
+                tree,
+                TimestampStrategy::Now,
+            )?,
+            None => {
+                git.squash_all_commits(&self.identity, &message, &branch, TimestampStrategy::Now)?
+            }
+        };
+        git.checkout_head_paths(&rewritten)?;
+        for path in &rewritten {
+            self.reporter
+                .say(format_args!("Rewrote names in {}", path.yellow()));
+        }
+        git.untrack_paths(&stripped)?;
+        self.report_stripped(&stripped, false);
+        self.record_mapping(&mapping);
+        self.check_forbidden(&git, &mapping)?;
+        verify_snapshot(
+            self.reporter,
+            &git,
+            &branch,
+            &original,
+            &[rewritten, stripped].concat(),
+        )?;
+        if options.include_tags {
+            self.retarget_tags(&git, &mapping)?;
+        }
+
+        if options.drop_stashes && stashes > 0 {
+            self.reporter
+                .say(format_args!("Dropping {stashes} stash entries..."));
+            git.drop_stashes()?;
+        }
+
+        self.reporter.say("Cleaning git history...");
+        let mut cleanup_commands = vec![&["reflog", "expire", "--expire=now", "--all"] as &[&str]];
+        if !no_gc {
+            cleanup_commands.push(options.gc_mode.git_args());
+        }
+
+        git.profiler().time("history cleanup", || -> Result<()> {
+            for cmd in cleanup_commands {
+                git.git_command().args(cmd).output()?;
+            }
+            Ok(())
+        })?;
+
+        self.reporter
+            .say(format_args!("{} Repository fully anonymized!", "✓".green()));
+        if let Some(backup_target) = &backup_target {
+            self.reporter.say(format_args!(
+                "Backup saved to {}: {}",
+                backup_target.kind(),
+                backup_target.to_string().yellow()
+            ));
+        }
+        if no_gc {
+            self.reporter.say(
+                "Garbage collection skipped: original objects remain until `git gc --prune=now` runs."
+                    .yellow(),
+            );
+        }
+        self.reporter.emit(&Outcome {
+            backup: backup_target.as_ref().map(Backup::to_string),
+            ..self.outcome("clean", &git.current_branch()?, false, mapping.len())
+        });
+
+        Ok(())
+    }
+
+    /// Rewrites every commit signed with `old_email` to the current identity,
+    /// leaving other signatures untouched.
+    pub fn rotate_identity(
+        &self,
+        old_email: &str,
+        no_confirm: bool,
+        dry_run: bool,
+        backup: &BackupOptions,
+    ) -> Result<()> {
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        self.verify_objects(&git)?;
+        let branch = git.current_branch()?;
+
+        if !self.check_clean_tree(&git, no_confirm || dry_run, true)? {
+            return Ok(());
+        }
+
+        let count = git.count_commits_with_email(old_email)?;
+        if count == 0 {
+            self.reporter
+                .say(format_args!("No commits on {branch} use {old_email}"));
+            return Ok(());
+        }
+
+        let backup_target = resolve_backup(&git, backup, || {
+            format!("backup-{}-{}", branch, Utc::now().timestamp())
+        })?;
+        if self.collects_report() {
+            *self.report.borrow_mut() = Some(Report {
+                operation: "rotate-identity".to_string(),
+                branch: branch.clone(),
+                dry_run,
+                commits_rewritten: count,
+                identities_replaced: vec![old_email.to_string()],
+                ..Report::default()
+            });
+        }
+
+        if dry_run {
+            self.reporter
+                .say("[DRY RUN] Rotate identity preview:".blue().bold());
+            self.reporter
+                .say(format_args!("  Current branch: {}", branch.yellow()));
+            self.reporter
+                .say(format_args!("  Old identity email: {}", old_email.red()));
+            self.reporter.say(format_args!(
+                "  New identity: {} <{}>",
+                self.identity.name, self.identity.email
+            ));
+            self.reporter.say(format_args!(
+                "  Backup {}: {}",
+                backup_target.kind(),
+                backup_target.to_string().green()
+            ));
+            self.reporter.say(format_args!(
+                "  {} {} commits would be rewritten",
+                "→".blue(),
+                count
+            ));
+            return Ok(());
+        }
+
+        if !no_confirm {
+            self.reporter.say(format_args!(
+                "Rotating {} commits from {} to {} <{}>",
+                count,
+                old_email.red(),
+                self.identity.name,
+                self.identity.email
+            ));
+            self.reporter.say("");
+
+            if !Confirm::new()
+                .with_prompt("Continue?")
+                .default(false)
+                .interact()?
+            {
+                self.reporter.say("Aborted.");
+                return Ok(());
+            }
+        }
+
+        create_backup(
+            self.reporter,
+            &git,
+            &backup_target,
+            &branch,
+            backup.on_conflict == BackupConflict::Overwrite,
+        )?;
+
+        // Only the identity changes; restamping would date the rotation, and
+        // commits before the first one with the old identity stay as they are.
+        let options = RewriteOptions {
+            only_email: Some(old_email.to_string()),
+            author_date: AuthorDatePolicy::Keep,
+            committer_date: CommitterDatePolicy::Keep,
+            keep_unchanged: true,
+            ..RewriteOptions::default()
+        };
+        self.journal(&git, "rotate-identity", &[&branch], Some(&backup_target))?;
+        let mapping = git
+            .anonymize_commits(&self.identity, &branch, None, None, &options)
+            .map_err(|e| self.interrupted(&git, true, Some(&backup_target), e))?;
+        self.record_mapping(&mapping);
+        self.check_forbidden(&git, &mapping)?;
+
+        self.reporter.say(format_args!(
+            "{} Rotated {} commits to the new identity",
+            "✓".green(),
+            count
+        ));
+        self.reporter.say(format_args!(
+            "Backup saved to {}: {}",
+            backup_target.kind(),
+            backup_target.to_string().yellow()
+        ));
+
+        Ok(())
+    }
+
+    /// Anonymizes the commits of a branch (the current one by default) in
+    /// `since..until` in place, without pushing. Commits after `until` are
+    /// re-parented but keep their identity.
+    pub fn anonymize(
+        &self,
+        no_confirm: bool,
+        dry_run: bool,
+        options: &AnonymizeOptions,
+    ) -> Result<()> {
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        self.verify_objects(&git)?;
+        let branch = match &options.branch {
+            Some(branch) => branch.clone(),
+            None => git.current_branch()?,
+        };
+
+        if !self.check_clean_tree(&git, no_confirm || dry_run, true)? {
+            return Ok(());
+        }
+
+        let since = options.since.as_deref();
+        let until = options.until.as_deref();
+        let selected = git.resolve_anonymize_range(Some(&branch), since, until)?;
+        let count = selected.len();
+        if count == 0 {
+            self.reporter.say("No commits to anonymize");
+            return Ok(());
+        }
+        let reparented = git
+            .resolve_anonymize_range(Some(&branch), since, None)?
+            .len()
+            - count;
+        let range = match (since, until) {
+            (None, None) => format!("all of {branch}"),
+            (since, until) => format!("{}..{}", since.unwrap_or(""), until.unwrap_or(&branch)),
+        };
+
+        let backup = &options.backup;
+        let backup_target = resolve_backup(&git, backup, || {
+            format!("backup-{}-{}", branch, Utc::now().timestamp())
+        })?;
+        if self.collects_report() {
+            *self.report.borrow_mut() = Some(Report {
+                operation: "anonymize".to_string(),
+                branch: branch.clone(),
+                dry_run,
+                commits_rewritten: count,
+                identities_replaced: git.identities_of(&selected),
+                tags: git
+                    .tags_targeting(&selected.iter().copied().collect())?
+                    .into_iter()
+                    .map(|tag| tag.name)
+                    .collect(),
+                ..Report::default()
+            });
+        }
+
+        if dry_run {
+            self.reporter
+                .say("[DRY RUN] Anonymize operation preview:".blue().bold());
+            self.reporter
+                .say(format_args!("  Branch: {}", branch.yellow()));
+            self.reporter.say(format_args!("  Range: {}", range.cyan()));
+            self.reporter.say(format_args!(
+                "  Anonymous identity: {} <{}>",
+                self.identity.name, self.identity.email
+            ));
+            self.reporter.say(format_args!(
+                "  Backup {}: {}",
+                backup_target.kind(),
+                backup_target.to_string().green()
+            ));
+            self.reporter.say(format_args!(
+                "  {} {} commits would be anonymized",
+                "→".blue(),
+                count
+            ));
+            if reparented > 0 {
+                self.reporter.say(format_args!(
+                    "  {} {} later commits would be re-parented with their identities kept",
+                    "→".blue(),
+                    reparented
+                ));
+            }
+            if let Some(mode) = options.reflog {
+                let action = match mode {
+                    ReflogMode::Scrub => "scrubbed of original identities",
+                    ReflogMode::Clear => "cleared",
+                };
+                self.reporter.say(format_args!(
+                    "  {} Reflogs of the branch and HEAD would be {action}",
+                    "→".blue()
+                ));
+            }
+            self.reporter.emit(&Outcome {
+                backup: Some(backup_target.to_string()),
+                ..self.outcome("anonymize", &branch, true, count)
+            });
+            return Ok(());
+        }
+
+        if !no_confirm {
+            self.reporter.say(format_args!(
+                "Anonymizing {} commits in {} on {} as {} <{}>",
+                count,
+                range.cyan(),
+                branch.yellow(),
+                self.identity.name,
+                self.identity.email
+            ));
+            self.reporter.say("");
+
+            if !Confirm::new()
+                .with_prompt("Continue?")
+                .default(false)
+                .interact()?
+            {
+                self.reporter.say("Aborted.");
+                return Ok(());
+            }
+        }
 
-        for cmd in cleanup_commands {
-            std::process::Command::new("git")
-                .arg("-C")
-                .arg(&self.repo_path)
-                .args(cmd)
-                .output()?;
+        let original = create_backup(
+            self.reporter,
+            &git,
+            &backup_target,
+            &branch,
+            backup.on_conflict == BackupConflict::Overwrite,
+        )?;
+        self.journal(&git, "anonymize", &[&branch], Some(&backup_target))?;
+        let rewrite = RewriteOptions::default();
+        let mapping = git
+            .anonymize_branch(&self.identity, &branch, since, until, &rewrite)
+            .map_err(|e| self.interrupted(&git, true, Some(&backup_target), e))?;
+        self.record_mapping(&mapping);
+        self.check_forbidden(&git, &mapping)?;
+        verify_snapshot(self.reporter, &git, &branch, &original, &[])?;
+
+        if let Some(mode) = options.reflog {
+            let mut rewritten = 0;
+            for refname in [format!("refs/heads/{branch}"), "HEAD".to_string()] {
+                rewritten += git.rewrite_reflog(&refname, &self.identity, mode, &rewrite)?;
+            }
+            self.reporter
+                .say(format_args!("Rewrote {rewritten} reflog entries"));
         }
 
-        println!("{} Repository fully anonymized!", "✓".green());
-        println!("Backup saved to branch: {}", backup_branch.yellow());
+        self.reporter.say(format_args!(
+            "{} Anonymized {} commits in {}",
+            "✓".green(),
+            count,
+            range
+        ));
+        self.reporter.say(format_args!(
+            "Backup saved to {}: {}",
+            backup_target.kind(),
+            backup_target.to_string().yellow()
+        ));
+        self.reporter.emit(&Outcome {
+            backup: Some(backup_target.to_string()),
+            ..self.outcome("anonymize", &branch, false, mapping.len())
+        });
 
         Ok(())
     }
+
+    /// Resets the current branch to a backup branch left by an earlier
+    /// destructive operation.
+    pub fn restore(&self, no_confirm: bool, dry_run: bool, backup: Option<&str>) -> Result<()> {
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        let branch = git.current_branch()?;
+
+        if !self.check_clean_tree(&git, no_confirm || dry_run, false)? {
+            return Ok(());
+        }
+
+        let backup = match backup {
+            Some(backup) => backup.to_string(),
+            None => {
+                let backups = git.list_backup_branches()?;
+                match backups.as_slice() {
+                    [] => anyhow::bail!("No backup branches found; pass --branch to name one"),
+                    [only] => only.name.clone(),
+                    _ if no_confirm || dry_run => anyhow::bail!(
+                        "{} backup branches found ({}); pass --branch to choose one",
+                        backups.len(),
+                        backups
+                            .iter()
+                            .map(|backup| backup.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    _ => {
+                        let labels: Vec<String> = backups
+                            .iter()
+                            .map(|backup| {
+                                format!("{} ({})", backup.name, &backup.tip.to_string()[..8])
+                            })
+                            .collect();
+                        let choice = Select::new()
+                            .with_prompt("Restore from which backup?")
+                            .items(&labels)
+                            .default(0)
+                            .interact()?;
+                        backups[choice].name.clone()
+                    }
+                }
+            }
+        };
+        if backup == branch {
+            anyhow::bail!("Cannot restore {branch} from itself");
+        }
+        let target = git.branch_tip(&backup)?;
+
+        if dry_run {
+            self.reporter
+                .say("[DRY RUN] Restore preview:".blue().bold());
+            self.reporter
+                .say(format_args!("  Current branch: {}", branch.yellow()));
+            self.reporter
+                .say(format_args!("  Backup branch: {}", backup.green()));
+            self.reporter.say(format_args!(
+                "  {} {} would be reset to {}",
+                "→".blue(),
+                branch,
+                target.to_string().cyan()
+            ));
+            return Ok(());
+        }
+
+        if !no_confirm {
+            self.reporter.say(
+                format!(
+                    "WARNING: {branch} will be reset to {backup}; commits made since are lost!"
+                )
+                .red()
+                .bold(),
+            );
+            if !Confirm::new()
+                .with_prompt("Continue?")
+                .default(false)
+                .interact()?
+            {
+                self.reporter.say("Aborted.");
+                return Ok(());
+            }
+        }
+
+        git.restore_from_backup(&branch, &backup)?;
+        self.reporter.say(format_args!(
+            "{} Restored {} to {} ({})",
+            "✓".green(),
+            branch.yellow(),
+            backup.green(),
+            &target.to_string()[..8]
+        ));
+        self.reporter.say(format_args!(
+            "The backup branch was kept; delete it with `git branch -D {backup}`"
+        ));
+
+        Ok(())
+    }
+
+    /// Lists backup branches newest first, and with `prune` deletes all but
+    /// the newest `keep`.
+    pub fn backups(&self, no_confirm: bool, dry_run: bool, prune: bool, keep: usize) -> Result<()> {
+        let git = self.open_git()?;
+        let backups = git.list_backup_branches()?;
+        if backups.is_empty() {
+            self.reporter.say("No backup branches");
+            return Ok(());
+        }
+
+        for (i, backup) in backups.iter().enumerate() {
+            let pruned = prune && i >= keep;
+            let source = match &backup.source {
+                Some(branch) => format!("{branch} backup"),
+                None => "pre-clean backup".to_string(),
+            };
+            let line = format!(
+                "{}  {}, made {}, tip {} from {}",
+                backup.name,
+                source,
+                format_timestamp(backup.created),
+                &backup.tip.to_string()[..8],
+                format_timestamp(backup.commit_time)
+            );
+            if pruned {
+                self.reporter
+                    .say(format_args!("  {} {}", "-".red(), line.dimmed()));
+            } else {
+                self.reporter
+                    .say(format_args!("  {} {}", "*".green(), line));
+            }
+        }
+
+        let doomed = backups.get(keep..).filter(|_| prune).unwrap_or_default();
+        if doomed.is_empty() {
+            return Ok(());
+        }
+        self.reporter.say("");
+        if dry_run {
+            self.reporter.say(format_args!(
+                "{} {} backup branches would be deleted",
+                "[DRY RUN]".blue().bold(),
+                doomed.len()
+            ));
+            return Ok(());
+        }
+
+        let _lock = git.lock()?;
+        if !no_confirm
+            && !Confirm::new()
+                .with_prompt(format!(
+                    "Delete {} backup branches? Their history can't be restored afterwards",
+                    doomed.len()
+                ))
+                .default(false)
+                .interact()?
+        {
+            self.reporter.say("Aborted.");
+            return Ok(());
+        }
+
+        for backup in doomed {
+            git.delete_branch(&backup.name)?;
+        }
+        self.reporter.say(format_args!(
+            "{} Deleted {} backup branches, kept {}",
+            "✓".green(),
+            doomed.len(),
+            keep.min(backups.len())
+        ));
+
+        Ok(())
+    }
+
+    /// Handles uncommitted changes before an operation. Runs with `--yes`,
+    /// `--dry-run` or without a terminal fail as before; otherwise the user
+    /// may stash the changes, abort, or (when `allow_continue`) carry on with
+    /// them in place. Returns whether to proceed.
+    fn check_clean_tree(
+        &self,
+        git: &GitOps,
+        non_interactive: bool,
+        allow_continue: bool,
+    ) -> Result<bool> {
+        if !git.has_uncommitted_changes()? {
+            return Ok(true);
+        }
+        if non_interactive || !io::stdin().is_terminal() {
+            anyhow::bail!("Uncommitted changes detected. Please commit or stash them first.");
+        }
+
+        let mut choices = vec!["Stash them and continue", "Abort"];
+        if allow_continue {
+            choices.push("Continue anyway (the changes stay in the working tree)");
+        }
+        let choice = Select::new()
+            .with_prompt("Uncommitted changes detected")
+            .items(&choices)
+            .default(0)
+            .interact()?;
+
+        match choice {
+            0 => {
+                git.stash_changes("git-anon: changes stashed before rewriting")?;
+                self.reporter.say(format_args!(
+                    "Stashed uncommitted changes; run {} afterwards to restore them",
+                    "git stash pop".cyan()
+                ));
+                Ok(true)
+            }
+            1 => {
+                self.reporter.say("Aborted.");
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    /// Fails if any identity other than the `known` anonymous ones (or
+    /// pseudonyms derived from them) remains in the history, listing each
+    /// with where it was found. Emails the scrubber allows are not leaks.
+    pub fn verify(
+        &self,
+        all_refs: bool,
+        tags: bool,
+        known: &[AnonymousIdentity],
+        scrubber: &IdentityScrubber,
+    ) -> Result<()> {
+        let git = self.open_git()?;
+        let scan = git.scan_identities(all_refs, tags)?;
+        self.reporter.say(format_args!(
+            "Scanned {} commits{}",
+            scan.commits,
+            match tags {
+                true => format!(" and {} annotated tags", scan.tags),
+                false => String::new(),
+            }
+        ));
+
+        let leaked_signatures: Vec<_> = scan
+            .signatures
+            .iter()
+            .filter(|(signature, _)| {
+                !anonymous_signature(signature, known, scrubber, &self.forbidden)
+            })
+            .collect();
+        let leaked_emails: Vec<_> = scan
+            .message_emails
+            .iter()
+            .filter(|(email, _)| !anonymous_email(email, known, scrubber, &self.forbidden))
+            .collect();
+
+        if !leaked_signatures.is_empty() {
+            self.reporter.say("Identities in commit or tag headers:");
+            for (signature, location) in &leaked_signatures {
+                self.reporter.say(format_args!(
+                    "  {} {}",
+                    signature.red(),
+                    format!("(first in {location})").dimmed()
+                ));
+            }
+        }
+        if !leaked_emails.is_empty() {
+            self.reporter.say("Email addresses in messages:");
+            for (email, location) in &leaked_emails {
+                self.reporter.say(format_args!(
+                    "  {} {}",
+                    email.red(),
+                    format!("(first in {location})").dimmed()
+                ));
+            }
+        }
+
+        let leaks = leaked_signatures.len() + leaked_emails.len();
+        if leaks > 0 {
+            anyhow::bail!("{leaks} real identities found in the history");
+        }
+        self.reporter
+            .say(format_args!("{} No real identities found", "✓".green()));
+        Ok(())
+    }
+
+    /// Summarizes, without changing anything, whether the current branch
+    /// is ready to push: who made HEAD, how many commits carry an identity
+    /// other than the one in use, uncommitted changes and the upstream.
+    /// Warns about stash entries, which no rewrite anonymizes.
+    pub fn status(&self) -> Result<()> {
+        let git = self.open_git()?;
+        let branch = git.current_branch()?;
+        let (author, committer) = git.head_signatures()?;
+        let (commits, other) = git.count_commits_not_by(&self.identity)?;
+        let dirty = git.has_uncommitted_changes()?;
+        let upstream = git.upstream(&branch)?;
+        let stashes = git.stash_count()?;
+        let identity = format!("{} <{}>", self.identity.name, self.identity.email);
+
+        self.reporter.say(format_args!(
+            "Branch: {} ({} commits)",
+            branch.yellow(),
+            commits
+        ));
+        let colored = |signature: &str| match signature == identity {
+            true => signature.green(),
+            false => signature.red(),
+        };
+        self.reporter
+            .say(format_args!("  HEAD author:    {}", colored(&author)));
+        self.reporter
+            .say(format_args!("  HEAD committer: {}", colored(&committer)));
+        self.reporter.say(format_args!(
+            "  Not by {}: {} commits",
+            identity.cyan(),
+            match other {
+                0 => "0".green(),
+                other => other.to_string().red(),
+            }
+        ));
+        self.reporter.say(format_args!(
+            "  Uncommitted changes: {}",
+            match dirty {
+                true => "yes".yellow(),
+                false => "none".green(),
+            }
+        ));
+        match upstream {
+            Some((name, ahead, behind)) => self.reporter.say(format_args!(
+                "  Tracking: {} ({ahead} ahead, {behind} behind)",
+                name.blue()
+            )),
+            None => self.reporter.say(format_args!(
+                "  Tracking: {}",
+                "no upstream branch".dimmed()
+            )),
+        }
+
+        self.reporter.say("");
+        match (other, dirty) {
+            (0, false) => self
+                .reporter
+                .say(format_args!("{} Clean to push", "✓".green())),
+            (0, true) => self.reporter.say(format_args!(
+                "{} History is anonymous, but there are uncommitted changes",
+                "!".yellow()
+            )),
+            (other, _) => self.reporter.say(format_args!(
+                "{} {other} commits would be rewritten by push or anonymize",
+                "✗".red()
+            )),
+        }
+        if stashes > 0 {
+            self.warnings.warn(format!(
+                "{stashes} stash entries keep the original identities and are not anonymized; \
+                 `clean --drop-stashes` removes them"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Resets the branches moved by the most recent `squash`, `push`,
+    /// `clean`, `anonymize` or `rotate-identity` to where they were before
+    /// it, and offers to delete the backup branch it created.
+    pub fn undo(&self, no_confirm: bool, dry_run: bool) -> Result<()> {
+        let git = self.open_git()?;
+        let _lock = git.lock()?;
+        let journal = git.journal();
+        let Some(operation) = journal.entries()?.pop() else {
+            self.reporter.say("No operation to undo");
+            return Ok(());
+        };
+
+        if !self.check_clean_tree(&git, no_confirm || dry_run, false)? {
+            return Ok(());
+        }
+
+        self.reporter.say(format_args!(
+            "Last operation: {} on {}, {}",
+            operation.operation.yellow(),
+            operation
+                .branches
+                .iter()
+                .map(|branch| branch.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            format_timestamp(operation.timestamp)
+        ));
+        for branch in &operation.branches {
+            match &branch.before {
+                Some(before) => self.reporter.say(format_args!(
+                    "  {} {} would be reset to {}",
+                    "→".blue(),
+                    branch.name,
+                    before[..8].cyan()
+                )),
+                None => self.reporter.say(format_args!(
+                    "  {} {}, created by the operation, would be deleted",
+                    "→".blue(),
+                    branch.name
+                )),
+            }
+        }
+        for tag in &operation.tags {
+            self.reporter.say(format_args!(
+                "  {} tag {} would be moved back to {}",
+                "→".blue(),
+                tag.name,
+                tag.before[..8].cyan()
+            ));
+        }
+        if operation.operation == "push" {
+            self.reporter.say(format_args!(
+                "  {} Commits already pushed stay on the remote",
+                "→".blue()
+            ));
+        }
+        if dry_run {
+            self.reporter
+                .say("[DRY RUN] Nothing was changed".blue().bold());
+            return Ok(());
+        }
+        if !no_confirm
+            && !Confirm::new()
+                .with_prompt("Undo it? Commits made since are lost")
+                .default(false)
+                .interact()?
+        {
+            self.reporter.say("Aborted.");
+            return Ok(());
+        }
+
+        restore_branches(&git, &operation, "undid", true)?;
+        journal.pop()?;
+        self.reporter.say(format_args!(
+            "{} Undid {}",
+            "✓".green(),
+            operation.operation
+        ));
+
+        if let Some(backup) = &operation.backup
+            && git.branch_tip(backup).is_ok()
+        {
+            let delete = !no_confirm
+                && Confirm::new()
+                    .with_prompt(format!("Delete backup branch {backup}?"))
+                    .default(false)
+                    .interact()?;
+            match delete {
+                true => {
+                    git.delete_branch(backup)?;
+                    self.reporter
+                        .say(format_args!("Deleted backup branch {}", backup.yellow()));
+                }
+                false => self.reporter.say(format_args!(
+                    "The backup branch was kept; delete it with `git branch -D {backup}`"
+                )),
+            }
+        }
+        Ok(())
+    }
+
+    /// Records in the operation log where `branches` point before
+    /// `operation` moves them.
+    fn journal(
+        &self,
+        git: &GitOps,
+        operation: &str,
+        branches: &[&str],
+        backup: Option<&Backup>,
+    ) -> Result<()> {
+        git.journal().append(Operation {
+            operation: operation.to_string(),
+            branches: branches
+                .iter()
+                .map(|name| JournaledBranch {
+                    name: name.to_string(),
+                    before: git.branch_tip(name).ok().map(|oid| oid.to_string()),
+                })
+                .collect(),
+            backup: backup.and_then(Backup::branch).map(String::from),
+            tags: Vec::new(),
+            dirty: git.has_uncommitted_changes()?,
+            timestamp: Utc::now().timestamp(),
+        })
+    }
+
+    /// Fails the run if any of the rewritten commits still carries a
+    /// `forbidden_identities` match, putting the branches of the run just
+    /// journaled back where they were first.
+    fn check_forbidden(&self, git: &GitOps, mapping: &HashMap<Oid, Oid>) -> Result<()> {
+        if self.forbidden.is_empty() {
+            return Ok(());
+        }
+        let rewritten = mapping.values().copied().collect();
+        let found = git.find_forbidden(&rewritten, &self.forbidden)?;
+        if found.is_empty() {
+            return Ok(());
+        }
+
+        let journal = git.journal();
+        let mut kept = "";
+        if let Some(operation) = journal.pop()? {
+            // A forced checkout would throw away changes kept in the tree.
+            restore_branches(git, &operation, "rolled back", !operation.dirty)?;
+            if operation.dirty {
+                kept =
+                    "\nYour uncommitted changes were left in the working tree; check `git status`";
+            }
+        }
+        anyhow::bail!(
+            "Rewritten history still contains forbidden identities, so the branches were rolled back:\n  {}{kept}",
+            found
+                .iter()
+                .map(|(identity, oid)| format!("{identity} (in {})", &oid.to_string()[..8]))
+                .collect::<Vec<_>>()
+                .join("\n  ")
+        );
+    }
+
+    /// Explains a rewrite stopped by Ctrl-C, first putting back the branches
+    /// of the run when it was `journaled`. Other errors pass through.
+    fn interrupted(
+        &self,
+        git: &GitOps,
+        journaled: bool,
+        backup: Option<&Backup>,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        if !interrupt::requested() {
+            return error;
+        }
+        if journaled {
+            let rolled_back = git.journal().pop().and_then(|operation| match operation {
+                Some(mut operation) => {
+                    // A branch the run was to create may not exist yet.
+                    operation.branches.retain(|branch| {
+                        branch.before.is_some() || git.branch_tip(&branch.name).is_ok()
+                    });
+                    // Changes kept in a dirty tree must survive the rollback.
+                    restore_branches(git, &operation, "rolled back", !operation.dirty)
+                }
+                None => Ok(()),
+            });
+            if let Err(e) = rolled_back {
+                return e.context(
+                    "Interrupted, but the branches could not be rolled back; run `git-anon undo`",
+                );
+            }
+        }
+        let kept = match backup {
+            Some(backup) => format!(
+                ", and the original history is also in {} {backup}",
+                backup.kind()
+            ),
+            None => String::new(),
+        };
+        error.context(format!(
+            "Interrupted; no branch was changed{kept}. Commits written before the \
+             interruption are unreferenced and `git gc --prune=now` removes them"
+        ))
+    }
+
+    /// An `Outcome` of `operation` with the identity in use filled in.
+    fn outcome(&self, operation: &str, branch: &str, dry_run: bool, commits: usize) -> Outcome {
+        Outcome {
+            operation: operation.to_string(),
+            branch: branch.to_string(),
+            identity: format!("{} <{}>", self.identity.name, self.identity.email),
+            dry_run,
+            commits,
+            ..Outcome::default()
+        }
+    }
+
+    fn reviewed_plan(&self) -> Result<Option<Plan>> {
+        self.plan_in.as_deref().map(Plan::read).transpose()
+    }
+
+    /// Checks the operation against the reviewed `--plan-in` plan, and writes
+    /// it to `--plan-out` on dry runs.
+    fn check_plan(
+        &self,
+        reviewed: Option<&Plan>,
+        dry_run: bool,
+        build: impl FnOnce() -> Result<Plan>,
+    ) -> Result<()> {
+        let plan_out = self.plan_out.as_deref().filter(|_| dry_run);
+        if reviewed.is_none() && plan_out.is_none() && !self.collects_report() {
+            return Ok(());
+        }
+
+        let plan = build()?;
+        if self.collects_report() {
+            *self.report.borrow_mut() = Some(Report::from_plan(&plan, dry_run));
+        }
+        if let Some(reviewed) = reviewed {
+            let diffs = reviewed.differences(&plan);
+            if !diffs.is_empty() {
+                anyhow::bail!(
+                    "Repository no longer matches the reviewed plan:\n  {}",
+                    diffs.join("\n  ")
+                );
+            }
+            self.reporter.say(format_args!(
+                "{} Operation matches the reviewed plan",
+                "✓".green()
+            ));
+        }
+        if let Some(path) = plan_out {
+            plan.write(path)?;
+            self.reporter.say(format_args!(
+                "Plan written to {}",
+                path.display().to_string().cyan()
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn build_plan(
+        &self,
+        git: &GitOps,
+        operation: &str,
+        branch: &str,
+        remote: Option<&str>,
+        since_commit: Option<&str>,
+        backup_branch: Option<&str>,
+    ) -> Result<Plan> {
+        let commits = git.plan_commits(Some(branch), since_commit)?;
+        let replacement = format!("{} <{}>", self.identity.name, self.identity.email);
+
+        let originals: BTreeSet<&str> = commits
+            .iter()
+            .flat_map(|commit| [commit.author.as_str(), commit.committer.as_str()])
+            .collect();
+        let contributors = originals.len();
+        let identity_mapping = originals
+            .into_iter()
+            .map(|original| IdentityMapping {
+                original: original.to_string(),
+                replacement: replacement.clone(),
+            })
+            .collect();
+
+        let oids = commits
+            .iter()
+            .filter_map(|commit| Oid::from_str(&commit.oid).ok())
+            .collect();
+        let tags = git.tags_targeting(&oids)?;
+
+        Ok(Plan {
+            operation: operation.to_string(),
+            branch: branch.to_string(),
+            remote: remote.map(str::to_string),
+            identity: PlannedIdentity {
+                name: self.identity.name.clone(),
+                email: self.identity.email.clone(),
+            },
+            backup_branch: backup_branch.map(str::to_string),
+            commits,
+            contributors,
+            identity_mapping,
+            tags,
+        })
+    }
+
+    fn warn_encrypted_content(&self, git: &GitOps) -> Result<()> {
+        let markers = git.find_encryption_markers()?;
+        if markers.is_empty() {
+            return Ok(());
+        }
+
+        self.warnings
+            .warn("encrypted content is not anonymized; rotate the encryption keys separately:");
+        for marker in &markers {
+            eprintln!("  {}", marker.yellow());
+        }
+        eprintln!();
+
+        Ok(())
+    }
+
+    /// Reports the files left out of the squashed tree, listing them with
+    /// `--verbose`.
+    fn report_stripped(&self, paths: &[String], dry_run: bool) {
+        if paths.is_empty() {
+            return;
+        }
+        match dry_run {
+            true => self.reporter.say(format_args!(
+                "  {} {} files would be stripped from the squashed tree",
+                "→".blue(),
+                paths.len()
+            )),
+            false => self.reporter.say(format_args!(
+                "Stripped {} files from the squashed tree",
+                paths.len()
+            )),
+        }
+        if self.verbose {
+            for path in paths {
+                self.reporter.say(format_args!("    {}", path.yellow()));
+            }
+        }
+    }
+
+    /// With `--include-tags`, moves tags to the rewritten commits and returns
+    /// the names of those moved.
+    fn retarget_tags(&self, git: &GitOps, mapping: &HashMap<Oid, Oid>) -> Result<Vec<String>> {
+        let tags = git.retarget_tags(mapping, &self.identity)?;
+        let moved: Vec<String> = tags.moved.iter().map(|(name, _)| name.clone()).collect();
+        if let Some(report) = self.report.borrow_mut().as_mut() {
+            report.moved_tags = moved.clone();
+        }
+        if !moved.is_empty() {
+            git.journal().record_tags(
+                tags.moved
+                    .iter()
+                    .map(|(name, before)| JournaledTag {
+                        name: name.clone(),
+                        before: before.to_string(),
+                    })
+                    .collect(),
+            )?;
+            self.reporter.say(format_args!(
+                "Moved {} tags to the anonymized commits: {}",
+                moved.len(),
+                moved.join(", ").green()
+            ));
+        }
+        if !tags.untouched.is_empty() {
+            self.reporter.say(format_args!(
+                "{} tags point at commits that were not rewritten and were left alone: {}",
+                tags.untouched.len(),
+                tags.untouched.join(", ").yellow()
+            ));
+        }
+        Ok(moved)
+    }
+
+    /// With `--verify-objects`, refuses to start on a corrupt object database.
+    fn verify_objects(&self, git: &GitOps) -> Result<()> {
+        if !self.verify_objects {
+            return Ok(());
+        }
+
+        self.reporter.say("Verifying repository objects...");
+        let dangling = git.verify_objects()?;
+        if !dangling.is_empty() {
+            self.reporter.say(format_args!(
+                "  {} dangling objects (unreachable, left from earlier rewrites):",
+                dangling.len()
+            ));
+            for line in &dangling {
+                self.reporter.say(format_args!("    {}", line.dimmed()));
+            }
+        }
+        self.reporter
+            .say(format_args!("{} Object database is intact", "✓".green()));
+
+        Ok(())
+    }
+
+    fn warn_large_blobs(&self, git: &GitOps) -> Result<()> {
+        let Some(threshold) = self.large_blob_threshold else {
+            return Ok(());
+        };
+
+        let blobs = git.find_large_blobs(threshold)?;
+        if blobs.is_empty() {
+            return Ok(());
+        }
+
+        self.warnings.warn(format!(
+            "{} blobs larger than {} bytes would be published:",
+            blobs.len(),
+            threshold
+        ));
+        for blob in &blobs {
+            eprintln!(
+                "  {} ({} bytes, introduced in {})",
+                blob.path.yellow(),
+                blob.size,
+                &blob.commit.to_string()[..8]
+            );
+        }
+        eprintln!();
+
+        Ok(())
+    }
+}
+
+fn format_timestamp(seconds: i64) -> String {
+    DateTime::from_timestamp(seconds, 0)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|| seconds.to_string())
+}
+
+/// Whether a `Name <email>` signature is one of the `known` identities, a
+/// pseudonym derived from one, or has an allowed email.
+fn anonymous_signature(
+    signature: &str,
+    known: &[AnonymousIdentity],
+    scrubber: &IdentityScrubber,
+    forbidden: &ForbiddenIdentities,
+) -> bool {
+    let Some((name, email)) = signature
+        .strip_suffix('>')
+        .and_then(|signature| signature.rsplit_once(" <"))
+    else {
+        return false;
+    };
+    if forbidden.matches(name, email) {
+        return false;
+    }
+    known.iter().any(|identity| {
+        (identity.name == name && identity.email == email)
+            || (email
+                .split_once('@')
+                .is_some_and(|(local, _)| local == name)
+                && is_pseudonym_of(email, identity))
+    }) || scrubber.allowed(email)
+}
+
+fn anonymous_email(
+    email: &str,
+    known: &[AnonymousIdentity],
+    scrubber: &IdentityScrubber,
+    forbidden: &ForbiddenIdentities,
+) -> bool {
+    if forbidden.matches_email(email) {
+        return false;
+    }
+    known
+        .iter()
+        .any(|identity| identity.email == email || is_pseudonym_of(email, identity))
+        || scrubber.allowed(email)
+}
+
+/// Whether `email` has the `contributor-<hash>@<domain>` shape
+/// `--pseudonyms deterministic` derives from `identity`.
+fn is_pseudonym_of(email: &str, identity: &AnonymousIdentity) -> bool {
+    let domain = identity
+        .email
+        .rsplit_once('@')
+        .map_or("example.com", |(_, domain)| domain);
+    email
+        .strip_suffix(domain)
+        .and_then(|local| local.strip_suffix('@'))
+        .and_then(|local| local.strip_prefix("contributor-"))
+        .is_some_and(|hash| hash.len() == 8 && hash.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Prints which pseudonym each original contributor gets.
+fn print_pseudonyms(
+    reporter: Reporter,
+    pseudonyms: &BTreeMap<String, AnonymousIdentity>,
+    dry_run: bool,
+) {
+    if pseudonyms.is_empty() {
+        return;
+    }
+    let indent = match dry_run {
+        true => {
+            reporter.say(format_args!(
+                "  {} {} contributors would get pseudonyms:",
+                "→".blue(),
+                pseudonyms.len()
+            ));
+            "      "
+        }
+        false => {
+            reporter.say("Pseudonyms:");
+            "  "
+        }
+    };
+    for (original, pseudonym) in pseudonyms {
+        reporter.say(format_args!(
+            "{indent}{original} {} {} <{}>",
+            "→".blue(),
+            pseudonym.name.green(),
+            pseudonym.email.green()
+        ));
+    }
+}
+
+fn report_metadata_files(warnings: &Warnings, files: &[MetadataFile], rewrite: bool) {
+    if files.is_empty() {
+        return;
+    }
+
+    warnings.warn(format!(
+        "{} files name contributors and are not covered by history rewriting:",
+        files.len()
+    ));
+    for file in files {
+        eprintln!("  {}", file.path.yellow());
+        for line in &file.lines {
+            eprintln!("    {line}");
+        }
+    }
+    if rewrite {
+        eprintln!("These names will be replaced with the anonymous identity.");
+    } else {
+        eprintln!("Review them, or pass --rewrite-metadata to replace the names.");
+    }
+    eprintln!();
+}
+
+/// Checks the remotes `push` will talk to before anything is rewritten:
+/// every `--also-to` remote must exist, and the backup remote must exist and
+/// be none of the public ones.
+fn check_push_remotes(git: &GitOps, remote: &str, options: &PushOptions) -> Result<()> {
+    for remote in &options.also_to {
+        git.check_remote(remote)?;
+    }
+    if let Some(backup_remote) = &options.backup_remote {
+        git.check_remote(backup_remote)?;
+        if push_targets(remote, options).contains(&backup_remote.as_str()) {
+            anyhow::bail!(
+                "backup_remote {backup_remote} is also a push target; refusing to publish the original history"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The remote `push` targets followed by its `--also-to` remotes.
+fn push_targets<'a>(remote: &'a str, options: &'a PushOptions) -> Vec<&'a str> {
+    iter::once(remote)
+        .chain(options.also_to.iter().map(String::as_str))
+        .collect()
+}
+
+/// Puts the branches `operation` moved back where it found them, deleting
+/// those it created. `checkout` resets the working tree of the current branch
+/// to match.
+fn restore_branches(git: &GitOps, operation: &Operation, verb: &str, checkout: bool) -> Result<()> {
+    for branch in &operation.branches {
+        match &branch.before {
+            Some(before) => git.reset_branch(
+                &branch.name,
+                Oid::from_str(before)?,
+                &format!("git-anon: {verb} {}", operation.operation),
+                checkout,
+            )?,
+            None => git.delete_branch(&branch.name)?,
+        }
+    }
+    for tag in &operation.tags {
+        git.restore_tag(
+            &tag.name,
+            Oid::from_str(&tag.before)?,
+            &format!("git-anon: {verb} {}", operation.operation),
+        )?;
+    }
+    Ok(())
+}
+
+/// Checks the squashed snapshot against the original history so a bad tree
+/// never goes unnoticed. `expected` lists paths that were rewritten on purpose.
+fn verify_snapshot(
+    reporter: Reporter,
+    git: &GitOps,
+    branch: &str,
+    original: &str,
+    expected: &[String],
+) -> Result<()> {
+    let changed = git.snapshot_differences(branch, original, expected)?;
+    if !changed.is_empty() {
+        anyhow::bail!(
+            "Snapshot tree differs from {original} in {} unexpected paths:\n  {}\nRestore from the backup and report this as a bug.",
+            changed.len(),
+            changed.join("\n  ")
+        );
+    }
+
+    if expected.is_empty() {
+        reporter.say(format_args!(
+            "{} Snapshot tree verified identical",
+            "✓".green()
+        ));
+    } else {
+        reporter.say(format_args!(
+            "{} Snapshot tree verified identical apart from {} rewritten or stripped files",
+            "✓".green(),
+            expected.len()
+        ));
+    }
+    Ok(())
+}
+
+/// `refs/tags/<tag>` pushed to the same name, for each moved tag.
+fn tag_refs(tags: &[String]) -> impl Iterator<Item = (String, String)> + '_ {
+    tags.iter().map(|tag| {
+        let refname = format!("refs/tags/{tag}");
+        (refname.clone(), refname)
+    })
+}
+
+/// Fills the `{date}`, `{branch}` and `{count}` placeholders of a squash
+/// message. `count` walks the history, so it only runs if the message asks.
+fn render_squash_message(
+    template: &str,
+    branch: &str,
+    count: impl FnOnce() -> Result<usize>,
+) -> Result<String> {
+    let mut message = template
+        .replace("{date}", &Utc::now().format("%Y-%m-%d").to_string())
+        .replace("{branch}", branch);
+    if message.contains("{count}") {
+        message = message.replace("{count}", &count()?.to_string());
+    }
+    Ok(message)
+}
+
+fn validate_into_branch(into: &str, branch: &str) -> Result<()> {
+    if !git2::Branch::name_is_valid(into)? {
+        anyhow::bail!("Invalid branch name: {into}");
+    }
+    if into == branch {
+        anyhow::bail!("--into must name a branch other than {branch}");
+    }
+    Ok(())
+}
+
+/// Reuses the reviewed plan's backup branch name unless one was given.
+fn planned_backup(backup: &BackupOptions, reviewed: Option<&Plan>) -> BackupOptions {
+    let mut backup = backup.clone();
+    if backup.name.is_none() {
+        backup.name = reviewed.and_then(|plan| plan.backup_branch.clone());
+    }
+    backup
+}
+
+/// Where the original history is saved before a destructive rewrite.
+enum Backup {
+    Branch(String),
+    /// A bundle file, keeping the original identities off the ref graph.
+    Bundle(PathBuf),
+}
+
+impl Backup {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Branch(_) => "branch",
+            Self::Bundle(_) => "bundle",
+        }
+    }
+
+    fn branch(&self) -> Option<&str> {
+        match self {
+            Self::Branch(name) => Some(name),
+            Self::Bundle(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Backup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Branch(name) => f.write_str(name),
+            Self::Bundle(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Saves `branch` to the backup and returns a revision naming the original
+/// tip, for verifying the rewritten snapshot against it.
+fn create_backup(
+    reporter: Reporter,
+    git: &GitOps,
+    backup: &Backup,
+    branch: &str,
+    force: bool,
+) -> Result<String> {
+    reporter.say(format_args!(
+        "Creating backup {}: {}",
+        backup.kind(),
+        backup.to_string().green()
+    ));
+    match backup {
+        Backup::Branch(name) => {
+            git.create_backup_branch(name, branch, force)?;
+            Ok(name.clone())
+        }
+        Backup::Bundle(path) => Ok(git.create_backup_bundle(path, branch, force)?.to_string()),
+    }
+}
+
+/// Picks the backup: the `--backup-bundle` file, or the branch name,
+/// validating an explicit `--backup-name`. A taken name is handled as
+/// `--on-conflict` says.
+fn resolve_backup(
+    git: &GitOps,
+    backup: &BackupOptions,
+    default_name: impl FnOnce() -> String,
+) -> Result<Backup> {
+    if let Some(path) = &backup.bundle {
+        // `git bundle` runs inside the repository, so a relative path would
+        // be checked here and written there.
+        let path = &std::path::absolute(path)
+            .with_context(|| format!("Invalid backup bundle path {}", path.display()))?;
+        let taken = |suffix: Option<u32>| bundle_path(path, suffix).exists();
+        return Ok(Backup::Bundle(bundle_path(
+            path,
+            free_suffix(backup.on_conflict, taken, || {
+                format!("Backup bundle {} already exists", path.display())
+            })?,
+        )));
+    }
+
+    let name = match &backup.name {
+        Some(name) => {
+            if !git2::Branch::name_is_valid(name)? {
+                anyhow::bail!("Invalid backup branch name: {name}");
+            }
+            name.clone()
+        }
+        None => default_name(),
+    };
+    let suffixed = |suffix: Option<u32>| match suffix {
+        Some(n) => format!("{name}-{n}"),
+        None => name.clone(),
+    };
+    // Timestamped default names can collide when runs land in the same
+    // second; only a name the user picked should make the run fail.
+    let on_conflict = match backup.name {
+        Some(_) => backup.on_conflict,
+        None if backup.on_conflict == BackupConflict::Abort => BackupConflict::Rename,
+        None => backup.on_conflict,
+    };
+    let suffix = free_suffix(
+        on_conflict,
+        |suffix| git.branch_exists(&suffixed(suffix)),
+        || format!("Backup branch '{name}' already exists"),
+    )?;
+
+    Ok(Backup::Branch(suffixed(suffix)))
+}
+
+/// The suffix that makes a backup name usable: none when it is free or will
+/// be overwritten, the first free number for `rename`, an error for `abort`.
+fn free_suffix(
+    on_conflict: BackupConflict,
+    taken: impl Fn(Option<u32>) -> bool,
+    exists: impl FnOnce() -> String,
+) -> Result<Option<u32>> {
+    if !taken(None) {
+        return Ok(None);
+    }
+    match on_conflict {
+        BackupConflict::Abort => anyhow::bail!(
+            "{} (use --on-conflict overwrite or rename, or --force)",
+            exists()
+        ),
+        BackupConflict::Overwrite => Ok(None),
+        BackupConflict::Rename => Ok((2..)
+            .find(|&n| !taken(Some(n)))
+            .map(Some)
+            .expect("some suffix is free")),
+    }
+}
+
+/// `backup.bundle` with `-N` appended to the file stem.
+fn bundle_path(path: &Path, suffix: Option<u32>) -> PathBuf {
+    let Some(n) = suffix else {
+        return path.to_path_buf();
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}-{n}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{n}"),
+    };
+    path.with_file_name(name)
 }
@@ -1,29 +1,210 @@
 use anyhow::{Context, Result};
+use glob::Pattern;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use crate::AnonymousIdentity;
+use crate::scrub::{ForbiddenIdentities, IdentityScrubber, TrailerMode};
+use crate::sign::{Signer, SigningFormat};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Keyring service under which keyring-backed identities are stored.
+const KEYRING_SERVICE: &str = "git-anon";
+
+/// The name mappings use to refer to `anonymous_identity`, the default.
+pub const DEFAULT_IDENTITY: &str = "anonymous_identity";
+
+/// Schema version written to new config files. Files without a `version`
+/// are version 1.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Prefix of the environment variables that override the configured
+/// identity: `GIT_ANON_NAME`/`GIT_ANON_EMAIL`, or
+/// `GIT_ANON_<REMOTE>_NAME`/`GIT_ANON_<REMOTE>_EMAIL` for one remote alias.
+const ENV_PREFIX: &str = "GIT_ANON";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    /// Schema version; older files are migrated when loaded.
     #[serde(default)]
+    pub version: u32,
+    /// Also read as `default_identity`, its name in early configs.
+    #[serde(default, alias = "default_identity")]
     pub anonymous_identity: Identity,
+    /// Further identities, by the name remotes and branch mappings refer to
+    /// them with.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub identities: HashMap<String, Identity>,
     #[serde(default)]
     pub remotes: HashMap<String, RemoteConfig>,
+    /// Maps a branch name or glob pattern to the identity used for that branch.
+    #[serde(default)]
+    pub branch_identities: HashMap<String, String>,
+    /// Extra find/replace rules applied to rewritten commit messages.
+    #[serde(default, skip_serializing_if = "ScrubConfig::is_empty")]
+    pub scrub: ScrubConfig,
+    /// How `--sign` runs the signer; the key belongs to each identity.
+    #[serde(default, skip_serializing_if = "SigningConfig::is_default")]
+    pub signing: SigningConfig,
+    /// Fail any run that emitted warnings, like `--abort-on-warning`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub abort_on_warning: bool,
+    /// Real names and emails (glob patterns) that must never appear in
+    /// rewritten history; a rewrite that produces one is rolled back.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forbidden_identities: Vec<String>,
+    /// Skip the backup of `squash` and `clean`, like `--no-backup`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_backup: bool,
+    /// Private git remote for the real, un-anonymized history, which `push
+    /// --force-backup-push-first` pushes to before publishing anything.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_remote: Option<String>,
+    /// URL (`https://` or `file://`) of an organization policy laid over this
+    /// config for every operation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_url: Option<String>,
+    /// Salt for `--pseudonyms deterministic`. When unset a random salt is
+    /// used, so aliases differ between runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pseudonym_salt: Option<String>,
+    /// Message template for `squash` and `clean` when `--message` isn't
+    /// given; `{date}`, `{branch}` and `{count}` are filled in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub squash_message: Option<String>,
+    /// Email domains the policy requires of anonymous identities.
+    #[serde(skip)]
+    pub required_domains: Vec<String>,
+    /// Why keyring-backed identities could not be read, if they couldn't.
+    #[serde(skip)]
+    pub keyring_error: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Identity {
+    #[serde(default)]
     pub name: String,
+    #[serde(default)]
     pub email: String,
+    /// Read `name` and `email` from the OS keyring instead of this file.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub keyring: bool,
+    /// GPG key ID or SSH key path that `--sign` uses for this identity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningConfig {
+    /// `openpgp` (default) or `ssh`, like git's `gpg.format`.
+    #[serde(default)]
+    pub format: SigningFormat,
+    /// Signing program; `gpg` or `ssh-keygen` by default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl SigningConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScrubConfig {
+    /// Applied in order; `replace` may use `$1`-style capture references.
+    #[serde(default)]
+    pub patterns: Vec<ScrubPattern>,
+    /// With `--scrub-messages`, email addresses matching these regexes are kept.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// With `--scrub-messages`, text matching these regexes (e.g. real names)
+    /// is replaced with the anonymous name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+}
+
+impl ScrubConfig {
+    fn is_empty(&self) -> bool {
+        self.patterns.is_empty() && self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScrubPattern {
+    pub find: String,
+    #[serde(default)]
+    pub replace: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RemoteConfig {
     pub name: String,
     pub identity: String,
+    /// SSH private key to push to this remote with, instead of the agent's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<String>,
+}
+
+/// Where a resolved identity came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentitySource {
+    Default,
+    Remote(String),
+    Branch(String),
+    /// Copied from an existing commit's author.
+    Commit(String),
+    /// Read from the `<prefix>_NAME`/`<prefix>_EMAIL` environment variables.
+    Env(String),
+    /// A mapping referenced an identity that does not exist, so the default was used.
+    Fallback {
+        mapping: String,
+        missing: String,
+    },
+    /// A mapping referenced an identity that does not exist, and the user
+    /// picked `name` instead when prompted.
+    Chosen {
+        mapping: String,
+        name: String,
+    },
+}
+
+impl fmt::Display for IdentitySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default identity"),
+            Self::Remote(remote) => write!(f, "remote '{remote}'"),
+            Self::Branch(pattern) => write!(f, "branch mapping '{pattern}'"),
+            Self::Commit(oid) => write!(f, "commit {oid}"),
+            Self::Env(prefix) => write!(f, "{prefix}_NAME/{prefix}_EMAIL"),
+            Self::Fallback { mapping, .. } => {
+                write!(f, "default identity, fallback from {mapping}")
+            }
+            Self::Chosen { mapping, name } => write!(f, "identity '{name}', chosen for {mapping}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedIdentity {
+    pub identity: AnonymousIdentity,
+    pub source: IdentitySource,
+}
+
+impl ResolvedIdentity {
+    /// Explains why the default was used, if this identity is a fallback.
+    pub fn fallback_reason(&self) -> Option<String> {
+        match &self.source {
+            IdentitySource::Fallback { mapping, missing } => {
+                Some(format!("{mapping} references unknown identity '{missing}'"))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl Default for Identity {
@@ -31,6 +212,8 @@ impl Default for Identity {
         Self {
             name: "Anonymous".to_string(),
             email: "anonymous@example.com".to_string(),
+            keyring: false,
+            signing_key: None,
         }
     }
 }
@@ -42,13 +225,28 @@ impl Default for Config {
             "radicle".to_string(),
             RemoteConfig {
                 name: "rad".to_string(),
-                identity: "anonymous_identity".to_string(),
+                identity: DEFAULT_IDENTITY.to_string(),
+                identity_file: None,
             },
         );
 
         Self {
+            version: CONFIG_VERSION,
             anonymous_identity: Identity::default(),
+            identities: HashMap::new(),
             remotes,
+            branch_identities: HashMap::new(),
+            scrub: ScrubConfig::default(),
+            signing: SigningConfig::default(),
+            abort_on_warning: false,
+            no_backup: false,
+            backup_remote: None,
+            forbidden_identities: Vec::new(),
+            policy_url: None,
+            pseudonym_salt: None,
+            squash_message: None,
+            required_domains: Vec::new(),
+            keyring_error: None,
         }
     }
 }
@@ -75,32 +273,594 @@ impl Config {
             return Ok(default_config);
         }
 
-        let contents = fs::read_to_string(&config_path).context("Failed to read config file")?;
+        let (config, migrated_from) = Self::read(&config_path)?;
+        if let Some(version) = migrated_from {
+            let backup = config_path.with_extension(format!("toml.v{version}"));
+            fs::copy(&config_path, &backup)
+                .with_context(|| format!("Failed to back up config to {}", backup.display()))?;
+            config.save_to(&config_path)?;
+            eprintln!(
+                "Migrated {} from config version {version} to {CONFIG_VERSION} (old file kept as {})",
+                config_path.display(),
+                backup.display()
+            );
+        }
+        Ok(config)
+    }
+
+    /// Like `load`, but returns the default config instead of creating the
+    /// file when it is missing, for previews that must not write.
+    pub fn load_or_default() -> Result<Self> {
+        let config_path = Self::config_path()?;
+        if config_path.exists() {
+            Self::load_from(&config_path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Reads a config file without creating or rewriting anything. Older
+    /// schema versions are migrated in memory.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        Ok(Self::read(path)?.0)
+    }
+
+    /// Reads and migrates a config file, also returning the version it was
+    /// migrated from, if it was.
+    fn read(path: &Path) -> Result<(Self, Option<u32>)> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        let mut table: toml::Table = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        let migrated_from = migrate(&mut table)
+            .with_context(|| format!("Failed to migrate config file {}", path.display()))?;
+        let mut config: Self = table
+            .try_into()
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        config
+            .scrub_patterns()
+            .and_then(|_| config.identity_scrubber(TrailerMode::default()))
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        config.resolve_keyring();
+        Ok((config, migrated_from))
+    }
+
+    /// Fills keyring-backed identities from the OS keyring. When the keyring
+    /// can't be read they stay blank, as saved, and `keyring_error` is set.
+    fn resolve_keyring(&mut self) {
+        let mut failed = Vec::new();
+        let identities = std::iter::once((DEFAULT_IDENTITY, &mut self.anonymous_identity)).chain(
+            self.identities
+                .iter_mut()
+                .map(|(name, id)| (name.as_str(), id)),
+        );
+        for (name, identity) in identities.filter(|(_, identity)| identity.keyring) {
+            match keyring_identity(name) {
+                Ok((display_name, email)) => {
+                    identity.name = display_name;
+                    identity.email = email;
+                }
+                Err(e) => failed.push(format!("{name} ({e:#})")),
+            }
+        }
+
+        if !failed.is_empty() {
+            failed.sort();
+            self.keyring_error = Some(format!(
+                "could not read {} from the keyring; their name and email are unavailable until the keyring is reachable",
+                failed.join(", ")
+            ));
+        }
+    }
+
+    /// Moves the identity `name` into the OS keyring and marks it as
+    /// keyring-backed so that `save` no longer writes it to disk.
+    pub fn store_identity_in_keyring(&mut self, name: &str) -> Result<()> {
+        let identity = self
+            .identity_mut(name)
+            .with_context(|| format!("Unknown identity '{name}'"))?;
+        keyring_entry(name)?
+            .set_password(&format!("{} <{}>", identity.name, identity.email))
+            .with_context(|| format!("Failed to write {name} to the keyring"))?;
+        identity.keyring = true;
+        Ok(())
+    }
+
+    /// Deletes the named identity `name` (and its keyring entry, if it has
+    /// one). Remotes that used it fall back to the default identity and its
+    /// branch mappings are dropped; callers check `identity_references` first.
+    pub fn remove_identity(&mut self, name: &str) -> Result<Identity> {
+        if name == DEFAULT_IDENTITY {
+            anyhow::bail!("Can't remove the default identity");
+        }
+        let identity = self
+            .identities
+            .remove(name)
+            .with_context(|| format!("Unknown identity '{name}'"))?;
+        if identity.keyring {
+            match keyring_entry(name)?.delete_credential() {
+                Ok(()) | Err(keyring::Error::NoEntry) => {}
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to delete {name} from the keyring"));
+                }
+            }
+        }
+        for remote in self.remotes.values_mut() {
+            if remote.identity == name {
+                remote.identity = DEFAULT_IDENTITY.to_string();
+            }
+        }
+        self.branch_identities
+            .retain(|_, identity| identity != name);
+        Ok(identity)
+    }
+
+    /// Compiles `scrub.patterns` into `(regex, replacement)` pairs, in order.
+    pub fn scrub_patterns(&self) -> Result<Vec<(Regex, String)>> {
+        self.scrub
+            .patterns
+            .iter()
+            .enumerate()
+            .map(|(i, pattern)| {
+                let regex = Regex::new(&pattern.find).with_context(|| {
+                    format!("scrub.patterns[{i}]: invalid regex '{}'", pattern.find)
+                })?;
+                Ok((regex, pattern.replace.clone()))
+            })
+            .collect()
+    }
+
+    /// Compiles `scrub.allow` and `scrub.deny` into a scrubber for
+    /// `--scrub-messages`.
+    pub fn identity_scrubber(&self, trailers: TrailerMode) -> Result<IdentityScrubber> {
+        let compile = |key: &str, regexes: &[String]| {
+            regexes
+                .iter()
+                .enumerate()
+                .map(|(i, regex)| {
+                    Regex::new(regex)
+                        .with_context(|| format!("scrub.{key}[{i}]: invalid regex '{regex}'"))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(IdentityScrubber {
+            trailers,
+            allow: compile("allow", &self.scrub.allow)?,
+            deny: compile("deny", &self.scrub.deny)?,
+        })
+    }
+
+    /// Compiles `forbidden_identities` for the post-rewrite check and `verify`.
+    pub fn forbidden_identities(&self) -> Result<ForbiddenIdentities> {
+        let patterns = self
+            .forbidden_identities
+            .iter()
+            .enumerate()
+            .map(|(i, pattern)| {
+                glob::Pattern::new(pattern).with_context(|| {
+                    format!("forbidden_identities[{i}]: invalid pattern '{pattern}'")
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ForbiddenIdentities { patterns })
+    }
+
+    /// The signer for `--sign`, using `identity`'s signing key.
+    pub fn signer(&self, identity: &AnonymousIdentity) -> Result<Signer> {
+        let Some(key) = &identity.signing_key else {
+            anyhow::bail!(
+                "--sign needs a signing key for {} <{}>; set signing_key on the identity in the config",
+                identity.name,
+                identity.email
+            );
+        };
+        Ok(Signer::new(
+            self.signing.format,
+            self.signing.program.clone(),
+            key.clone(),
+        ))
+    }
+
+    /// Lints the config: returns `(errors, warnings)`.
+    pub fn validate(&self) -> (Vec<String>, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+
+        if let Err(e) = validate_identity(
+            &self.anonymous_identity.name,
+            &self.anonymous_identity.email,
+        ) {
+            errors.push(format!("anonymous_identity: {e}"));
+        }
+
+        let mut identities: Vec<_> = self.identities.iter().collect();
+        identities.sort_by_key(|(name, _)| *name);
+        for (name, identity) in identities {
+            if name == DEFAULT_IDENTITY {
+                errors.push(format!(
+                    "identities.{name}: this name refers to the top-level anonymous_identity"
+                ));
+            } else if let Err(e) = validate_identity(&identity.name, &identity.email) {
+                errors.push(format!("identities.{name}: {e}"));
+            }
+        }
+
+        let mut aliases: Vec<_> = self.remotes.iter().collect();
+        aliases.sort_by_key(|(alias, _)| *alias);
+        let mut remote_names: HashMap<&str, &str> = HashMap::new();
+        for (alias, remote) in aliases {
+            if self.get_identity(&remote.identity).is_none() {
+                errors.push(format!(
+                    "remotes.{alias}: references unknown identity '{}'",
+                    remote.identity
+                ));
+            }
+            if let Some(other) = remote_names.insert(&remote.name, alias) {
+                warnings.push(format!(
+                    "remotes.{other} and remotes.{alias} both point at git remote '{}'",
+                    remote.name
+                ));
+            }
+        }
+
+        let mut branches: Vec<_> = self.branch_identities.iter().collect();
+        branches.sort();
+        for (pattern, identity) in branches {
+            if let Err(e) = Pattern::new(pattern) {
+                errors.push(format!(
+                    "branch_identities.\"{pattern}\": invalid pattern: {e}"
+                ));
+            }
+            if self.get_identity(identity).is_none() {
+                errors.push(format!(
+                    "branch_identities.\"{pattern}\": references unknown identity '{identity}'"
+                ));
+            }
+        }
 
-        toml::from_str(&contents).context("Failed to parse config file")
+        (errors, warnings)
     }
 
     pub fn save(&self) -> Result<()> {
+        self.save_to(&Self::config_path()?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        let contents = self.to_toml()?;
+        fs::write(path, contents)
+            .with_context(|| format!("Failed to write config file {}", path.display()))
+    }
+
+    /// The config as it is written to disk, without keyring-backed identities.
+    pub fn to_toml(&self) -> Result<String> {
+        let mut on_disk = self.clone();
+        let identities =
+            std::iter::once(&mut on_disk.anonymous_identity).chain(on_disk.identities.values_mut());
+        for identity in identities.filter(|identity| identity.keyring) {
+            identity.name.clear();
+            identity.email.clear();
+        }
+        Ok(toml::to_string_pretty(&on_disk)?)
+    }
+
+    /// Copies the active config next to itself as `config.toml.bak`, returning
+    /// the backup path, or `None` when there is no config yet.
+    pub fn backup_active() -> Result<Option<PathBuf>> {
         let config_path = Self::config_path()?;
-        let contents = toml::to_string_pretty(self)?;
-        fs::write(config_path, contents)?;
-        Ok(())
+        if !config_path.exists() {
+            return Ok(None);
+        }
+
+        let backup_path = config_path.with_extension("toml.bak");
+        fs::copy(&config_path, &backup_path)
+            .with_context(|| format!("Failed to back up config to {}", backup_path.display()))?;
+        Ok(Some(backup_path))
+    }
+
+    /// Layers `other` on top of this config: its identity wins, its named
+    /// identities, remotes and branch mappings replace entries with the same
+    /// key, its scrub patterns run after the existing ones, its forbidden
+    /// identities are added, and the other settings it sets win.
+    pub fn merge(&mut self, other: Config) {
+        self.anonymous_identity = other.anonymous_identity;
+        self.identities.extend(other.identities);
+        self.remotes.extend(other.remotes);
+        self.branch_identities.extend(other.branch_identities);
+        for pattern in other.forbidden_identities {
+            if !self.forbidden_identities.contains(&pattern) {
+                self.forbidden_identities.push(pattern);
+            }
+        }
+        self.abort_on_warning |= other.abort_on_warning;
+        self.no_backup |= other.no_backup;
+        if !other.signing.is_default() {
+            self.signing = other.signing;
+        }
+        self.backup_remote = other.backup_remote.or(self.backup_remote.take());
+        self.policy_url = other.policy_url.or(self.policy_url.take());
+        self.pseudonym_salt = other.pseudonym_salt.or(self.pseudonym_salt.take());
+        self.squash_message = other.squash_message.or(self.squash_message.take());
+        for pattern in other.scrub.patterns {
+            if !self
+                .scrub
+                .patterns
+                .iter()
+                .any(|existing| existing.find == pattern.find)
+            {
+                self.scrub.patterns.push(pattern);
+            }
+        }
+        for regex in other.scrub.allow {
+            if !self.scrub.allow.contains(&regex) {
+                self.scrub.allow.push(regex);
+            }
+        }
+        for regex in other.scrub.deny {
+            if !self.scrub.deny.contains(&regex) {
+                self.scrub.deny.push(regex);
+            }
+        }
+    }
+
+    /// Every identity defined in the config, by the name mappings refer to it with.
+    pub fn identity_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.identities.keys().map(String::as_str).collect();
+        names.sort();
+        names.insert(0, DEFAULT_IDENTITY);
+        names
+    }
+
+    /// The stored identity `name` refers to, for editing.
+    pub fn identity_mut(&mut self, name: &str) -> Option<&mut Identity> {
+        match name {
+            DEFAULT_IDENTITY => Some(&mut self.anonymous_identity),
+            _ => self.identities.get_mut(name),
+        }
+    }
+
+    /// Describes the remote and branch mappings that use the identity `name`.
+    pub fn identity_references(&self, name: &str) -> Vec<String> {
+        let mut remotes: Vec<String> = self
+            .remotes
+            .iter()
+            .filter(|(_, remote)| remote.identity == name)
+            .map(|(alias, _)| format!("remote {alias}"))
+            .collect();
+        remotes.sort();
+
+        let mut branches: Vec<String> = self
+            .branch_identities
+            .iter()
+            .filter(|(_, identity)| *identity == name)
+            .map(|(pattern, _)| format!("branch {pattern}"))
+            .collect();
+        branches.sort();
+
+        remotes.extend(branches);
+        remotes
     }
 
     pub fn get_identity(&self, name: &str) -> Option<AnonymousIdentity> {
-        (name == "anonymous_identity").then(|| AnonymousIdentity {
+        let identity = match name {
+            DEFAULT_IDENTITY => &self.anonymous_identity,
+            _ => self.identities.get(name)?,
+        };
+        Some(AnonymousIdentity {
+            name: identity.name.clone(),
+            email: identity.email.clone(),
+            signing_key: identity.signing_key.clone(),
+        })
+    }
+
+    pub fn default_identity(&self) -> AnonymousIdentity {
+        AnonymousIdentity {
             name: self.anonymous_identity.name.clone(),
             email: self.anonymous_identity.email.clone(),
-        })
+            signing_key: self.anonymous_identity.signing_key.clone(),
+        }
     }
 
     pub fn get_remote_identity(&self, remote: &str) -> AnonymousIdentity {
-        self.remotes
-            .get(remote)
-            .and_then(|rc| self.get_identity(&rc.identity))
-            .unwrap_or_else(|| AnonymousIdentity {
-                name: self.anonymous_identity.name.clone(),
-                email: self.anonymous_identity.email.clone(),
+        self.resolve_remote_identity(remote).identity
+    }
+
+    /// Resolves the identity for `remote`, recording whether it came from the
+    /// remote mapping or fell back to the default.
+    pub fn resolve_remote_identity(&self, remote: &str) -> ResolvedIdentity {
+        match self.remotes.get(remote) {
+            Some(rc) => {
+                self.resolve_named(&rc.identity, IdentitySource::Remote(remote.to_string()))
+            }
+            None => self.resolve_default(),
+        }
+    }
+
+    /// Returns the identity name mapped to `branch`. An exact entry wins over
+    /// glob patterns; among matching patterns the longest one wins.
+    pub fn branch_identity_name(&self, branch: &str) -> Option<(&str, &str)> {
+        if let Some((pattern, name)) = self.branch_identities.get_key_value(branch) {
+            return Some((pattern, name));
+        }
+
+        self.branch_identities
+            .iter()
+            .filter(|(pattern, _)| Pattern::new(pattern).is_ok_and(|p| p.matches(branch)))
+            .max_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| b.cmp(a)))
+            .map(|(pattern, name)| (pattern.as_str(), name.as_str()))
+    }
+
+    pub fn resolve_branch_identity(&self, branch: &str) -> ResolvedIdentity {
+        match self.branch_identity_name(branch) {
+            Some((pattern, name)) => {
+                self.resolve_named(name, IdentitySource::Branch(pattern.to_string()))
+            }
+            None => self.resolve_default(),
+        }
+    }
+
+    fn resolve_default(&self) -> ResolvedIdentity {
+        ResolvedIdentity {
+            identity: self.default_identity(),
+            source: IdentitySource::Default,
+        }
+    }
+
+    fn resolve_named(&self, name: &str, source: IdentitySource) -> ResolvedIdentity {
+        match self.get_identity(name) {
+            Some(identity) => ResolvedIdentity { identity, source },
+            None => ResolvedIdentity {
+                identity: self.default_identity(),
+                source: IdentitySource::Fallback {
+                    mapping: source.to_string(),
+                    missing: name.to_string(),
+                },
+            },
+        }
+    }
+}
+
+fn keyring_entry(name: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, name).context("Failed to open the OS keyring")
+}
+
+/// The identity set in the environment, which wins over the config: the
+/// variables for `remote` (`GIT_ANON_RADICLE_NAME` for alias `radicle`) if
+/// set, else `GIT_ANON_NAME`/`GIT_ANON_EMAIL`.
+pub fn env_identity(remote: Option<&str>) -> Result<Option<ResolvedIdentity>> {
+    let remote_prefix = remote.map(|remote| {
+        let alias: String = remote
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
             })
+            .collect();
+        format!("{ENV_PREFIX}_{alias}")
+    });
+    for prefix in remote_prefix.into_iter().chain([ENV_PREFIX.to_string()]) {
+        let name = env::var(format!("{prefix}_NAME")).ok();
+        let email = env::var(format!("{prefix}_EMAIL")).ok();
+        let (name, email) = match (name, email) {
+            (Some(name), Some(email)) => (name, email),
+            (None, None) => continue,
+            (Some(_), None) => anyhow::bail!("{prefix}_NAME is set but {prefix}_EMAIL is not"),
+            (None, Some(_)) => anyhow::bail!("{prefix}_EMAIL is set but {prefix}_NAME is not"),
+        };
+        return Ok(Some(ResolvedIdentity {
+            identity: AnonymousIdentity {
+                name,
+                email,
+                signing_key: None,
+            },
+            source: IdentitySource::Env(prefix),
+        }));
+    }
+    Ok(None)
+}
+
+/// Reads a `Name <email>` identity stored under `name` in the keyring.
+fn keyring_identity(name: &str) -> Result<(String, String)> {
+    let secret = keyring_entry(name)?.get_password()?;
+    let (display_name, email) = secret
+        .trim()
+        .strip_suffix('>')
+        .and_then(|rest| rest.rsplit_once('<'))
+        .context("keyring entry is not in 'Name <email>' form")?;
+    Ok((display_name.trim().to_string(), email.to_string()))
+}
+
+pub fn validate_identity(name: &str, email: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        anyhow::bail!("Name cannot be empty");
     }
+    if name.contains(['<', '>', '\n']) {
+        anyhow::bail!("Name cannot contain '<', '>' or line breaks");
+    }
+    validate_email(email)
+}
+
+/// Checks that `email` looks like `local@domain.tld`: one `@`, something on
+/// each side, a dot inside the domain and no whitespace or angle brackets,
+/// which would break the `Name <email>` signature git writes.
+pub fn validate_email(email: &str) -> Result<()> {
+    if email.trim().is_empty() {
+        anyhow::bail!("Email cannot be empty");
+    }
+    if email.contains(|c: char| c.is_whitespace() || c == '<' || c == '>') {
+        anyhow::bail!("Email '{email}' cannot contain spaces or angle brackets");
+    }
+    let Some((local, domain)) = email.split_once('@') else {
+        anyhow::bail!("Email '{email}' must contain @ symbol");
+    };
+    if local.is_empty() {
+        anyhow::bail!("Email '{email}' needs a name before the @");
+    }
+    if domain.contains('@') {
+        anyhow::bail!("Email '{email}' must contain a single @");
+    }
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 || labels.iter().any(|label| label.is_empty()) {
+        anyhow::bail!("Email '{email}' needs a domain like example.com after the @");
+    }
+    Ok(())
+}
+
+/// Upgrades a config table to `CONFIG_VERSION` in place, returning the
+/// version it started at if anything changed.
+fn migrate(table: &mut toml::Table) -> Result<Option<u32>> {
+    let version = match table.get("version") {
+        Some(version) => version
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .context("version must be a positive integer")?,
+        None => 1,
+    };
+    if version > CONFIG_VERSION {
+        anyhow::bail!(
+            "config version {version} is newer than this git-anon supports ({CONFIG_VERSION}); upgrade git-anon"
+        );
+    }
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    // v1 → v2: the default identity was called `default_identity`, and
+    // mappings referred to it by that name.
+    // When a file has both tables, `anonymous_identity` wins.
+    if let Some(identity) = table.remove("default_identity") {
+        table
+            .entry(DEFAULT_IDENTITY.to_string())
+            .or_insert(identity);
+    }
+    let rename = |value: &mut toml::Value| {
+        if value.as_str() == Some("default_identity") {
+            *value = toml::Value::String(DEFAULT_IDENTITY.to_string());
+        }
+    };
+    if let Some(remotes) = table.get_mut("remotes").and_then(toml::Value::as_table_mut) {
+        for (_, remote) in remotes.iter_mut() {
+            let Some(remote) = remote.as_table_mut() else {
+                continue;
+            };
+            if let Some(identity) = remote.get_mut("identity") {
+                rename(identity);
+            }
+        }
+    }
+    if let Some(branches) = table
+        .get_mut("branch_identities")
+        .and_then(toml::Value::as_table_mut)
+    {
+        branches
+            .iter_mut()
+            .for_each(|(_, identity)| rename(identity));
+    }
+
+    table.insert(
+        "version".to_string(),
+        toml::Value::Integer(CONFIG_VERSION.into()),
+    );
+    Ok(Some(version))
 }
@@ -1,27 +1,499 @@
-use anyhow::Result;
-use git2::{BranchType, Commit, Oid, Repository, Signature, Status, StatusOptions};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use git2::{
+    BranchType, Commit, ConfigLevel, Cred, CredentialType, ErrorCode, Mailmap, ObjectType, Oid,
+    PushOptions, Reference, RemoteCallbacks, Repository, Revwalk, Signature, Sort, Status,
+    StatusOptions, Tag, Time, Tree, TreeWalkMode, TreeWalkResult,
+};
+use glob::Pattern;
 use indicatif::{ProgressBar, ProgressStyle};
+use rand::Rng;
+use rayon::prelude::*;
+use regex::Regex;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::Write;
 use std::path::Path;
+use std::process::Command;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+use tempfile::NamedTempFile;
 
-use crate::AnonymousIdentity;
+use crate::interrupt::{self, Deferral};
+use crate::journal::Journal;
+use crate::lock::RepoLock;
+use crate::mapping::{PlannedCommit, PlannedTag};
+use crate::metadata::{self, MetadataFile};
+use crate::profile::Profiler;
+use crate::scrub::{ForbiddenIdentities, IdentityScrubber};
+use crate::sign::Signer;
+use crate::strip::StripRules;
+use crate::trace::Tracer;
+use crate::warnings::Warnings;
+use crate::{AnonymousIdentity, ReflogMode};
+
+/// Which date an anonymized author signature carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AuthorDatePolicy {
+    /// The time of the rewrite.
+    #[default]
+    Now,
+    /// The original author date, preserving the authorship timeline.
+    Keep,
+}
+
+/// Which date an anonymized committer signature carries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CommitterDatePolicy {
+    /// The time of the rewrite.
+    #[default]
+    Now,
+    /// The original committer date.
+    Keep,
+}
+
+/// Which identity replaces each original contributor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PseudonymStrategy {
+    /// Everyone becomes the anonymous identity.
+    #[default]
+    Single,
+    /// Each original email becomes a stable `contributor-<hash>` alias, so
+    /// who did what stays visible without naming anyone.
+    Deterministic,
+}
+
+/// How rewritten commits are dated. Takes precedence over the separate
+/// author and committer date policies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimestampStrategy {
+    /// The original dates.
+    Preserve,
+    /// The time of the rewrite.
+    #[default]
+    Now,
+    /// The original dates shifted by a random amount within `±max_secs`,
+    /// never earlier than the rewritten parents.
+    Jitter { max_secs: u32 },
+    /// One fixed instant for every commit.
+    Fixed(DateTime<Utc>),
+}
+
+impl TimestampStrategy {
+    /// The date replacing `original`; `floor` is the latest matching date
+    /// among the rewritten parents, which jittered dates never go below.
+    fn date(self, original: Time, now: Time, floor: Option<i64>) -> Time {
+        match self {
+            Self::Preserve => original,
+            Self::Now => now,
+            Self::Jitter { max_secs } => {
+                let max = i64::from(max_secs);
+                let jittered = original.seconds() + rand::thread_rng().gen_range(-max..=max);
+                let seconds = floor.map_or(jittered, |floor| jittered.max(floor));
+                Time::new(seconds, original.offset_minutes())
+            }
+            Self::Fixed(at) => Time::new(at.timestamp(), 0),
+        }
+    }
+}
+
+impl FromStr for TimestampStrategy {
+    type Err = String;
+
+    /// Parses `preserve`, `now`, `jitter:<secs>` or `fixed:<RFC 3339 date>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "preserve" => Ok(Self::Preserve),
+            None if s == "now" => Ok(Self::Now),
+            Some(("jitter", secs)) => secs
+                .parse()
+                .map(|max_secs| Self::Jitter { max_secs })
+                .map_err(|_| format!("invalid jitter window '{secs}', expected seconds")),
+            Some(("fixed", date)) => DateTime::parse_from_rfc3339(date)
+                .map(|date| Self::Fixed(date.with_timezone(&Utc)))
+                .map_err(|e| format!("invalid date '{date}': {e}")),
+            _ => Err(format!(
+                "unknown timestamp strategy '{s}', expected preserve, now, \
+                 jitter:<secs> or fixed:<RFC 3339 date>"
+            )),
+        }
+    }
+}
+
+/// How `anonymize_commits` rewrites each commit.
+#[derive(Debug, Clone, Default)]
+pub struct RewriteOptions {
+    /// Keep each commit's original author and only replace the committer.
+    pub keep_authors: bool,
+    /// Only replace signatures with this email; others are kept as they are.
+    pub only_email: Option<String>,
+    /// Only anonymize commits whose changes touch a path matching this glob.
+    /// Other commits are still rebuilt on the new chain but keep their identities.
+    pub pathspec: Option<Pattern>,
+    /// Only anonymize these commits (from `--range`); the rest of the chain is
+    /// rebuilt with its identities intact.
+    pub only_commits: Option<HashSet<Oid>>,
+    pub author_date: AuthorDatePolicy,
+    pub committer_date: CommitterDatePolicy,
+    /// Dates for both signatures, overriding `author_date` and `committer_date`.
+    pub timestamps: Option<TimestampStrategy>,
+    /// Per-identity replacements keyed by `Name <email>`, from `--author-map`.
+    /// Identities not listed get the anonymous identity.
+    pub author_map: HashMap<String, AnonymousIdentity>,
+    /// Exact identities for individual commits, from `--commit-map`. These take
+    /// precedence over `author_map` and the anonymous identity.
+    pub commit_map: HashMap<Oid, AnonymousIdentity>,
+    /// Move author/committer dates that lie in the future back to now.
+    pub clamp_future_dates: bool,
+    /// Regex find/replace pairs applied in order to every rewritten message.
+    pub message_scrubs: Vec<(Regex, String)>,
+    /// Replace names and emails in message trailers and bodies (`--scrub-messages`).
+    pub identity_scrub: Option<IdentityScrubber>,
+    pub pseudonyms: PseudonymStrategy,
+    /// Canonicalize identities through `.mailmap` before looking up their
+    /// replacement, so one person's several emails get the same one.
+    pub use_mailmap: bool,
+    /// Mixed into pseudonym hashes; the same salt gives the same aliases
+    /// across runs.
+    pub pseudonym_salt: String,
+    /// Keep a commit that would come out identical, parents included, as it
+    /// is instead of rebuilding it, so its OID and signature survive.
+    pub keep_unchanged: bool,
+}
+
+impl RewriteOptions {
+    fn replacement_for<'a>(
+        &'a self,
+        commit: Oid,
+        signature: &Signature,
+        identity: &'a AnonymousIdentity,
+    ) -> Cow<'a, AnonymousIdentity> {
+        if let Some(mapped) = self.commit_map.get(&commit) {
+            return Cow::Borrowed(mapped);
+        }
+        if let Some(mapped) = self.author_map.get(&signature_key(signature)) {
+            return Cow::Borrowed(mapped);
+        }
+        match self.pseudonyms {
+            PseudonymStrategy::Single => Cow::Borrowed(identity),
+            PseudonymStrategy::Deterministic => Cow::Owned(
+                self.pseudonym(&String::from_utf8_lossy(signature.email_bytes()), identity),
+            ),
+        }
+    }
+
+    /// The alias for the original `email`, with an address at the anonymous
+    /// identity's domain.
+    pub fn pseudonym(&self, email: &str, identity: &AnonymousIdentity) -> AnonymousIdentity {
+        let mut hasher = Sha256::new();
+        hasher.update(self.pseudonym_salt.as_bytes());
+        hasher.update([0]);
+        hasher.update(email.trim().to_lowercase().as_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        let alias = format!("contributor-{}", &hash[..8]);
+        let domain = identity
+            .email
+            .rsplit_once('@')
+            .map_or("example.com", |(_, domain)| domain);
+        AnonymousIdentity {
+            email: format!("{alias}@{domain}"),
+            name: alias,
+            signing_key: identity.signing_key.clone(),
+        }
+    }
+
+    fn scrub_message<'a>(&self, message: &'a str, identity: &AnonymousIdentity) -> Cow<'a, str> {
+        let mut message = Cow::Borrowed(message);
+        for (regex, replacement) in &self.message_scrubs {
+            if let Cow::Owned(scrubbed) = regex.replace_all(&message, replacement.as_str()) {
+                message = Cow::Owned(scrubbed);
+            }
+        }
+        if let Some(scrubber) = &self.identity_scrub
+            && let Cow::Owned(scrubbed) = scrubber.scrub(&message, identity)
+        {
+            message = Cow::Owned(scrubbed);
+        }
+        message
+    }
+
+    fn replaces(&self, signature: &Signature) -> bool {
+        self.only_email
+            .as_deref()
+            .is_none_or(|email| signature.email_bytes() == email.as_bytes())
+    }
+}
+
+/// Histories shorter than this are prepared on the calling thread; opening a
+/// repository handle per worker costs more than it saves.
+const PARALLEL_PREPARE_MIN: usize = 256;
+
+/// How many commits the rewrite takes from the walk at a time, preparing
+/// each batch before rewriting it.
+const PREPARE_BATCH: usize = 8192;
+
+/// Around this many identities, two of the 2^32 `contributor-<8 hex>`
+/// pseudonyms are likely to collide (the birthday bound).
+const PSEUDONYM_POOL_LIMIT: usize = 65_536;
+
+/// The part of rewriting a commit that doesn't depend on its rewritten
+/// parents, so it can be worked out for all commits up front and in parallel.
+#[derive(Debug, Default)]
+struct PreparedCommit {
+    /// Whether the commit changes a path matching `--pathspec`.
+    touches_pathspec: bool,
+    /// The scrubbed message, if scrubbing changed it.
+    message: Option<String>,
+}
+
+/// What to do with a blob while rebuilding a tree.
+pub enum BlobAction {
+    Keep,
+    Replace(Oid),
+    Remove,
+}
+
+/// A backup branch and what its name says about it.
+#[derive(Debug, Clone)]
+pub struct BackupBranch {
+    pub name: String,
+    /// The branch that was backed up; `None` for `pre-clean-backup-*`.
+    pub source: Option<String>,
+    /// When the backup was made, in seconds since the epoch.
+    pub created: i64,
+    pub tip: Oid,
+    pub commit_time: i64,
+}
+
+/// What `retarget_tags` did with each tag.
+#[derive(Debug, Clone, Default)]
+pub struct RetargetedTags {
+    /// Tags now pointing at the rewritten commits, with the tag object or
+    /// commit each pointed to before.
+    pub moved: Vec<(String, Oid)>,
+    /// Tags whose commit was not rewritten, left as they were.
+    pub untouched: Vec<String>,
+}
+
+/// Every identity `scan_identities` found in the scanned history.
+#[derive(Debug, Clone, Default)]
+pub struct IdentityScan {
+    pub commits: usize,
+    pub tags: usize,
+    /// Each distinct `Name <email>` of an author, committer or tagger, with
+    /// the first object (`commit <oid>` or `tag <name>`) it was seen on.
+    pub signatures: BTreeMap<String, String>,
+    /// Email addresses mentioned in commit and tag messages, likewise.
+    pub message_emails: BTreeMap<String, String>,
+}
+
+/// The commits a squash would collapse, as `summarize_range` reports them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RangeSummary {
+    pub commits: usize,
+    /// Earliest and latest commit times in the range, in seconds.
+    pub oldest: i64,
+    pub newest: i64,
+}
+
+/// What `anonymize_branches` rewrote.
+#[derive(Debug, Clone, Default)]
+pub struct RewrittenBranches {
+    /// Original → new OID of every commit rewritten on any of the branches.
+    pub mapping: HashMap<Oid, Oid>,
+    /// How many commits each branch added to `mapping`, in the order given.
+    pub counts: Vec<(String, usize)>,
+}
+
+/// A blob above the large-blob threshold and the commit that introduced it.
+#[derive(Debug, Clone)]
+pub struct LargeBlob {
+    pub oid: Oid,
+    pub path: String,
+    pub size: u64,
+    pub commit: Oid,
+}
 
 pub struct GitOps {
     repo: Repository,
+    skip_corrupt: bool,
+    tz_offset: Option<i32>,
+    profiler: Rc<Profiler>,
+    warnings: Rc<Warnings>,
+    tracer: Rc<Tracer>,
+    signer: Option<Signer>,
+    /// SSH key to push with, instead of the ssh-agent.
+    identity_file: Option<std::path::PathBuf>,
+    /// `key=value` overrides from `--git-config`, applied for this run only.
+    config_overrides: Vec<(String, String)>,
+    /// Holds the overrides for libgit2; removed when `GitOps` is dropped.
+    config_file: Option<NamedTempFile>,
 }
 
 impl GitOps {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let repo = Repository::open(path)?;
-        Ok(Self { repo })
+        let path = path.as_ref();
+        let repo = Repository::discover(path)
+            .with_context(|| format!("Not a git repository: {}", path.display()))?;
+        Ok(Self {
+            repo,
+            skip_corrupt: false,
+            tz_offset: None,
+            profiler: Rc::default(),
+            warnings: Rc::default(),
+            tracer: Rc::default(),
+            signer: None,
+            identity_file: None,
+            config_overrides: Vec::new(),
+            config_file: None,
+        })
+    }
+
+    /// Log and skip unreadable commits instead of aborting the walk.
+    pub fn with_skip_corrupt(mut self, skip_corrupt: bool) -> Self {
+        self.skip_corrupt = skip_corrupt;
+        self
+    }
+
+    /// Stamp anonymous signatures with this UTC offset (in minutes) instead of
+    /// the local one.
+    pub fn with_tz_offset(mut self, tz_offset: Option<i32>) -> Self {
+        self.tz_offset = tz_offset;
+        self
+    }
+
+    pub fn with_profiler(mut self, profiler: Rc<Profiler>) -> Self {
+        self.profiler = profiler;
+        self
+    }
+
+    pub fn with_warnings(mut self, warnings: Rc<Warnings>) -> Self {
+        self.warnings = warnings;
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: Rc<Tracer>) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    /// Sign the commits created from now on that carry a rewritten identity
+    /// with `signer`.
+    pub fn with_signer(mut self, signer: Option<Signer>) -> Self {
+        self.signer = signer;
+        self
+    }
+
+    /// Authenticate SSH pushes with `identity_file` instead of the ssh-agent.
+    pub fn with_identity_file(mut self, identity_file: Option<std::path::PathBuf>) -> Self {
+        self.identity_file = identity_file;
+        self
+    }
+
+    /// Layers `overrides` over the repository config for the lifetime of this
+    /// `GitOps`, without writing them to any config file git reads later.
+    pub fn with_config_overrides(mut self, overrides: &[(String, String)]) -> Result<Self> {
+        if overrides.is_empty() {
+            return Ok(self);
+        }
+
+        let mut file = NamedTempFile::new().context("Failed to create git config overrides")?;
+        let mut config = git2::Config::open(file.path())?;
+        for (key, value) in overrides {
+            config
+                .set_str(key, value)
+                .with_context(|| format!("Invalid git config override {key}={value}"))?;
+        }
+        file.flush()?;
+        self.repo
+            .config()?
+            .add_file(file.path(), ConfigLevel::App, true)
+            .context("Failed to apply git config overrides")?;
+
+        self.config_overrides = overrides.to_vec();
+        self.config_file = Some(file);
+        Ok(self)
+    }
+
+    /// Whether git signs commits made here, per `commit.gpgsign`.
+    pub fn signs_by_default(&self) -> Result<bool> {
+        Ok(self
+            .repo
+            .config()?
+            .get_bool("commit.gpgsign")
+            .unwrap_or(false))
+    }
+
+    /// A `git` command run in this repository with the config overrides applied.
+    pub fn git_command(&self) -> Command {
+        let dir = self.repo.workdir().unwrap_or_else(|| self.repo.path());
+        let mut command = Command::new("git");
+        command.arg("-C").arg(dir);
+        for (key, value) in &self.config_overrides {
+            command.arg("-c").arg(format!("{key}={value}"));
+        }
+        command
+    }
+
+    /// Takes the per-repository lock. The common git dir is used so linked
+    /// worktrees, which share branches, share the lock too.
+    pub fn lock(&self) -> Result<RepoLock> {
+        RepoLock::acquire(&self.common_dir())
+    }
+
+    /// The operation log shared by every worktree of the repository.
+    pub fn journal(&self) -> Journal {
+        Journal::open(&self.common_dir())
+    }
+
+    fn common_dir(&self) -> std::path::PathBuf {
+        // A linked worktree's git dir names the shared one in `commondir`.
+        let git_dir = self.repo.path();
+        match std::fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common) => git_dir.join(common.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        }
+    }
+
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
     }
 
+    fn head(&self) -> Result<Reference<'_>> {
+        match self.repo.head() {
+            Ok(head) => Ok(head),
+            Err(e) if e.code() == ErrorCode::UnbornBranch => {
+                anyhow::bail!("HEAD points to a branch with no commits yet")
+            }
+            Err(e) => Err(e).context("Failed to resolve HEAD"),
+        }
+    }
+
+    /// The checked-out branch. Fails on a detached HEAD, where there is no
+    /// branch for a rewrite to move.
     pub fn current_branch(&self) -> Result<String> {
-        let head = self.repo.head()?;
+        let head = self.head()?;
+        if self.repo.head_detached()? {
+            let commit = head.target().map(|oid| oid.to_string()).unwrap_or_default();
+            anyhow::bail!(
+                "HEAD is detached at {}; check out a branch first (e.g. git switch -c <branch>)",
+                &commit[..commit.len().min(8)]
+            );
+        }
         let shorthand = head.shorthand().unwrap_or("HEAD");
         Ok(shorthand.to_string())
     }
 
+    /// Always false in a bare repository, which has no working tree.
     pub fn has_uncommitted_changes(&self) -> Result<bool> {
+        if self.repo.is_bare() {
+            return Ok(false);
+        }
+
         let mut opts = StatusOptions::new();
         opts.include_untracked(false).include_ignored(false);
 
@@ -41,51 +513,598 @@ impl GitOps {
             .any(|status| status.status().intersects(change_flags)))
     }
 
-    pub fn create_backup_branch(&self, branch_name: &str) -> Result<()> {
-        let head = self.repo.head()?;
-        let commit = head.peel_to_commit()?;
-        self.repo.branch(branch_name, &commit, false)?;
+    /// Stashes uncommitted changes to tracked files, like `git stash push`.
+    pub fn stash_changes(&self, message: &str) -> Result<()> {
+        let output = self
+            .git_command()
+            .args(["stash", "push", "--message", message])
+            .output()
+            .context("Failed to run git stash")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "git stash failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn branch_exists(&self, branch_name: &str) -> bool {
+        self.repo
+            .find_branch(branch_name, BranchType::Local)
+            .is_ok()
+    }
+
+    /// Creates `branch_name` at the tip of `source`.
+    pub fn create_backup_branch(&self, branch_name: &str, source: &str, force: bool) -> Result<()> {
+        let commit = self.repo.find_commit(self.branch_tip(source)?)?;
+        self.repo
+            .branch(branch_name, &commit, force)
+            .with_context(|| format!("Failed to create backup branch {branch_name}"))?;
+        Ok(())
+    }
+
+    /// Local branches named like the backups `squash`, `clean` and
+    /// `rotate-identity` create by default, newest first.
+    pub fn list_backup_branches(&self) -> Result<Vec<BackupBranch>> {
+        let mut backups = Vec::new();
+        for branch in self.repo.branches(Some(BranchType::Local))? {
+            let (branch, _) = branch?;
+            let Some(name) = branch.name()? else {
+                continue;
+            };
+            let Some((source, created)) = parse_backup_name(name) else {
+                continue;
+            };
+            let commit = branch.get().peel_to_commit()?;
+            backups.push(BackupBranch {
+                name: name.to_string(),
+                source,
+                created,
+                tip: commit.id(),
+                commit_time: commit.time().seconds(),
+            });
+        }
+        backups.sort_by(|a, b| b.created.cmp(&a.created).then_with(|| b.name.cmp(&a.name)));
+        Ok(backups)
+    }
+
+    /// Local branches other than the backups git-anon creates, by name.
+    pub fn local_branches(&self) -> Result<Vec<String>> {
+        let mut branches = Vec::new();
+        for branch in self.repo.branches(Some(BranchType::Local))? {
+            let (branch, _) = branch?;
+            if let Some(name) = branch.name()?
+                && parse_backup_name(name).is_none()
+            {
+                branches.push(name.to_string());
+            }
+        }
+        branches.sort();
+        Ok(branches)
+    }
+
+    pub fn delete_branch(&self, name: &str) -> Result<()> {
+        self.repo
+            .find_branch(name, BranchType::Local)?
+            .delete()
+            .with_context(|| format!("Failed to delete branch {name}"))
+    }
+
+    /// The commit a local branch points to.
+    pub fn branch_tip(&self, branch: &str) -> Result<Oid> {
+        self.repo
+            .find_branch(branch, BranchType::Local)
+            .with_context(|| format!("No such branch: {branch}"))?
+            .get()
+            .peel_to_commit()
+            .map(|commit| commit.id())
+            .with_context(|| format!("Branch {branch} does not point to a commit"))
+    }
+
+    /// Resets `branch` (checked out) to the tip of `backup`, like
+    /// `git reset --hard <backup>`. Returns the restored commit.
+    pub fn restore_from_backup(&self, branch: &str, backup: &str) -> Result<Oid> {
+        let target = self.branch_tip(backup)?;
+        self.reset_branch(
+            branch,
+            target,
+            &format!("git-anon: restored from {backup}"),
+            true,
+        )?;
+        Ok(target)
+    }
+
+    /// Points `branch` at `target`, checking it out again with `checkout` if
+    /// it is the current branch of a non-bare repository. Without it the
+    /// index and working tree are left as they are.
+    pub fn reset_branch(
+        &self,
+        branch: &str,
+        target: Oid,
+        log_message: &str,
+        checkout: bool,
+    ) -> Result<()> {
+        self.repo
+            .find_commit(target)
+            .with_context(|| format!("Commit {target} no longer exists"))?;
+        self.set_branch_target(branch, target, log_message)?;
+        if !checkout || self.repo.is_bare() {
+            return Ok(());
+        }
+
+        let head = self.repo.head().ok();
+        if !self.repo.head_detached()?
+            && head.as_ref().and_then(|head| head.shorthand()) == Some(branch)
+        {
+            let mut checkout = git2::build::CheckoutBuilder::new();
+            checkout.force();
+            self.repo
+                .checkout_head(Some(&mut checkout))
+                .with_context(|| format!("Failed to check out {branch}"))?;
+        }
+        Ok(())
+    }
+
+    /// Points tag `name` back at `target`, a tag object or commit.
+    pub fn restore_tag(&self, name: &str, target: Oid, log_message: &str) -> Result<()> {
+        self.repo
+            .find_object(target, None)
+            .with_context(|| format!("Tag {name}'s original object {target} no longer exists"))?;
+        self.repo
+            .reference(&format!("refs/tags/{name}"), target, true, log_message)?;
         Ok(())
     }
 
+    /// Saves `branch`'s history to a bundle file instead of a branch, so the
+    /// original identities stay off the ref graph and can't be pushed by
+    /// accident. Returns the commit the bundle was taken at.
+    pub fn create_backup_bundle(&self, path: &Path, branch: &str, force: bool) -> Result<Oid> {
+        // Relative to the cwd, not to the repository `git bundle` runs in.
+        let path = &std::path::absolute(path)
+            .with_context(|| format!("Invalid backup bundle path {}", path.display()))?;
+        if path.exists() && !force {
+            anyhow::bail!("Backup bundle {} already exists", path.display());
+        }
+
+        let tip = self.branch_tip(branch)?;
+        let output = self
+            .git_command()
+            .arg("bundle")
+            .arg("create")
+            .arg(path)
+            .arg(format!("refs/heads/{branch}"))
+            .output()
+            .context("Failed to run git bundle")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Failed to create backup bundle {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(tip)
+    }
+
+    /// Returns the mapping from every original commit to the squashed one.
+    /// The squashed commit is dated from HEAD's dates by `timestamps`.
     pub fn squash_all_commits(
         &self,
         identity: &AnonymousIdentity,
         message: &str,
         branch: &str,
+        timestamps: TimestampStrategy,
+    ) -> Result<HashMap<Oid, Oid>> {
+        self.squash_range(identity, message, branch, None, "HEAD", None, timestamps)
+    }
+
+    /// Like `squash_all_commits`, but commits `tree` instead of HEAD's tree.
+    pub fn squash_all_commits_onto_tree(
+        &self,
+        identity: &AnonymousIdentity,
+        message: &str,
+        branch: &str,
+        tree: Oid,
+        timestamps: TimestampStrategy,
+    ) -> Result<HashMap<Oid, Oid>> {
+        self.squash_range(
+            identity,
+            message,
+            branch,
+            None,
+            "HEAD",
+            Some(tree),
+            timestamps,
+        )
+    }
+
+    /// Squashes the commits after `from` (the whole history when `None`) up
+    /// to and including `to` into one commit on top of `from`, and points
+    /// `branch` at it. The commit gets `to`'s tree unless `tree` overrides it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn squash_range(
+        &self,
+        identity: &AnonymousIdentity,
+        message: &str,
+        branch: &str,
+        from: Option<&str>,
+        to: &str,
+        tree: Option<Oid>,
+        timestamps: TimestampStrategy,
+    ) -> Result<HashMap<Oid, Oid>> {
+        let (base, tip, commits) = self.resolve_squash_range(from, to)?;
+        let walked_tip = commits[0];
+        let tree = match tree {
+            Some(tree) => self.repo.find_tree(tree)?,
+            None => {
+                // The squashed snapshot is that of the walk's tip, which must
+                // be the commit the caller asked for.
+                let tree = self.repo.find_commit(walked_tip)?.tree()?;
+                if tree.id() != tip.tree_id() {
+                    anyhow::bail!(
+                        "The history walk ended at {walked_tip}, whose tree differs from {to}; refusing to squash"
+                    );
+                }
+                tree
+            }
+        };
+
+        let parents: Vec<&Commit> = base.iter().collect();
+        let (author, committer) = self.squash_signatures(identity, &tip, timestamps)?;
+        let new_commit_oid = self.profiler.time("commit creation", || {
+            self.create_commit(&author, &committer, message, &tree, &parents, true)
+        })?;
+        self.tracer.event(|| {
+            json!({
+                "event": "squash",
+                "new": new_commit_oid.to_string(),
+                "squashed": commits.len(),
+                "identity": format!("{} <{}>", identity.name, identity.email),
+            })
+        });
+
+        self.profiler.time("ref update", || {
+            self.set_branch_target(branch, new_commit_oid, "Squashed all commits")
+        })?;
+
+        Ok(commits
+            .into_iter()
+            .map(|oid| (oid, new_commit_oid))
+            .collect())
+    }
+
+    /// Anonymizes the commits after `since_commit` on HEAD and points
+    /// `branch` at the result. With `until`, only those up to and including
+    /// it get the anonymous identity; the commits after it are only
+    /// re-parented onto the rewritten history.
+    pub fn anonymize_commits(
+        &self,
+        identity: &AnonymousIdentity,
+        branch: &str,
+        since_commit: Option<&str>,
+        until: Option<&str>,
+        options: &RewriteOptions,
+    ) -> Result<HashMap<Oid, Oid>> {
+        self.anonymize_range(identity, branch, None, since_commit, until, options)
+    }
+
+    /// Like `anonymize_commits`, but rewrites `branch`'s own history, which
+    /// need not be checked out.
+    pub fn anonymize_branch(
+        &self,
+        identity: &AnonymousIdentity,
+        branch: &str,
+        since_commit: Option<&str>,
+        until: Option<&str>,
+        options: &RewriteOptions,
+    ) -> Result<HashMap<Oid, Oid>> {
+        self.anonymize_range(identity, branch, Some(branch), since_commit, until, options)
+    }
+
+    /// Like `anonymize_branch`, but points `target` at the rewritten history
+    /// of `source`, which is left alone.
+    pub fn anonymize_branch_into(
+        &self,
+        identity: &AnonymousIdentity,
+        source: &str,
+        target: &str,
+        since_commit: Option<&str>,
+        options: &RewriteOptions,
+    ) -> Result<HashMap<Oid, Oid>> {
+        self.anonymize_range(identity, target, Some(source), since_commit, None, options)
+    }
+
+    fn anonymize_range(
+        &self,
+        identity: &AnonymousIdentity,
+        branch: &str,
+        source: Option<&str>,
+        since_commit: Option<&str>,
+        until: Option<&str>,
+        options: &RewriteOptions,
+    ) -> Result<HashMap<Oid, Oid>> {
+        let options = match until {
+            Some(_) => {
+                let in_range: HashSet<Oid> = self
+                    .resolve_anonymize_range(source, since_commit, until)?
+                    .into_iter()
+                    .collect();
+                Cow::Owned(RewriteOptions {
+                    only_commits: Some(match &options.only_commits {
+                        Some(only) => only.intersection(&in_range).copied().collect(),
+                        None => in_range,
+                    }),
+                    ..options.clone()
+                })
+            }
+            None => Cow::Borrowed(options),
+        };
+        let start = source.map(|source| self.branch_tip(source)).transpose()?;
+        self.anonymize_commits_onto(
+            identity,
+            branch,
+            start,
+            since_commit,
+            &options,
+            HashMap::new(),
+        )
+    }
+
+    /// The commits after `since` up to and including `until` (the tip when
+    /// unset), newest first, on `branch` (HEAD when unset). `until` must be
+    /// on that history and `since` an ancestor of `until`.
+    pub fn resolve_anonymize_range(
+        &self,
+        branch: Option<&str>,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Vec<Oid>> {
+        let resolve = |rev: &str| {
+            self.repo
+                .revparse_single(rev)
+                .and_then(|object| object.peel_to_commit())
+                .map(|commit| commit.id())
+                .with_context(|| format!("Failed to resolve revision {rev}"))
+        };
+        let tip = match branch {
+            Some(branch) => self.branch_tip(branch)?,
+            None => self.head_commit()?.id(),
+        };
+        let label = branch.unwrap_or("HEAD");
+        let until_oid = until.map(resolve).transpose()?.unwrap_or(tip);
+        if let Some(until) = until
+            && until_oid != tip
+            && !self.repo.graph_descendant_of(tip, until_oid)?
+        {
+            anyhow::bail!("--until {until} is not an ancestor of {label}");
+        }
+        if let Some(since) = since {
+            let since_oid = resolve(since)?;
+            let until = until.unwrap_or(label);
+            if since_oid == until_oid {
+                anyhow::bail!("No commits after {since} up to {until}");
+            }
+            if !self.repo.graph_descendant_of(until_oid, since_oid)? {
+                anyhow::bail!("--since {since} is not an ancestor of {until}");
+            }
+        }
+
+        self.collect_commits_from(Some(until_oid), since)
+    }
+
+    /// Anonymizes each of `branches` (with the commit its rewrite starts
+    /// after) using one shared old→new map, so a commit reachable from
+    /// several branches is rewritten once and maps to the same new commit
+    /// everywhere. Branches listed in `branch_identities` get that identity
+    /// instead of `identity`; `check_branch_identities` tells whether they
+    /// can.
+    pub fn anonymize_branches(
+        &self,
+        identity: &AnonymousIdentity,
+        branch_identities: &HashMap<String, AnonymousIdentity>,
+        branches: &[(String, Option<String>)],
+        options: &RewriteOptions,
+    ) -> Result<RewrittenBranches> {
+        let mut mapping = HashMap::new();
+        let mut counts = Vec::new();
+        for (branch, since_commit) in branches {
+            let before = mapping.len();
+            let tip = self.branch_tip(branch)?;
+            mapping = self.anonymize_commits_onto(
+                branch_identities.get(branch).unwrap_or(identity),
+                branch,
+                Some(tip),
+                since_commit.as_deref(),
+                options,
+                mapping,
+            )?;
+            counts.push((branch.clone(), mapping.len() - before));
+        }
+        Ok(RewrittenBranches { mapping, counts })
+    }
+
+    /// Fails if a commit `anonymize_branches` would rewrite is reachable from
+    /// two of `branches` whose identities differ, since it can only get one
+    /// of them.
+    pub fn check_branch_identities(
+        &self,
+        identity: &AnonymousIdentity,
+        branch_identities: &HashMap<String, AnonymousIdentity>,
+        branches: &[(String, Option<String>)],
     ) -> Result<()> {
+        if branch_identities.is_empty() {
+            return Ok(());
+        }
+        let identity_of = |branch: &str| branch_identities.get(branch).unwrap_or(identity);
+        let mut owners: HashMap<Oid, &str> = HashMap::new();
+        for (branch, since_commit) in branches {
+            let theirs = identity_of(branch);
+            let tip = self.branch_tip(branch)?;
+            for oid in self.collect_commits_from(Some(tip), since_commit.as_deref())? {
+                let owner = *owners.entry(oid).or_insert(branch);
+                let ours = identity_of(owner);
+                if (&ours.name, &ours.email) != (&theirs.name, &theirs.email) {
+                    anyhow::bail!(
+                        "Commit {} is on both {owner} and {branch}, which map to different identities ({} <{}> and {} <{}>); push them separately",
+                        &oid.to_string()[..8],
+                        ours.name,
+                        ours.email,
+                        theirs.name,
+                        theirs.email
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Squashes everything before the last `keep` commits into one base
+    /// commit and rebuilds those `keep` commits on top of it, anonymized.
+    /// Returns the mapping for the whole history.
+    pub fn squash_keeping_last(
+        &self,
+        identity: &AnonymousIdentity,
+        message: &str,
+        branch: &str,
+        keep: usize,
+        timestamps: TimestampStrategy,
+    ) -> Result<HashMap<Oid, Oid>> {
+        let mut boundary = self.head_commit()?;
+        for kept in 0..keep {
+            boundary = boundary.parent(0).map_err(|_| {
+                anyhow::anyhow!(
+                    "--keep-last {keep} keeps the whole history ({} commits); nothing to squash",
+                    kept + 1
+                )
+            })?;
+        }
+
+        let (author, committer) = self.squash_signatures(identity, &boundary, timestamps)?;
+        let base_tree = boundary.tree()?;
+        let base = self.profiler.time("commit creation", || {
+            self.create_commit(&author, &committer, message, &base_tree, &[], true)
+        })?;
+
         let mut revwalk = self.repo.revwalk()?;
-        revwalk.push_head()?;
-        revwalk.set_sorting(git2::Sort::REVERSE)?;
+        revwalk.push(boundary.id())?;
+        let squashed = revwalk
+            .map(|oid| oid.map(|oid| (oid, base)))
+            .collect::<Result<HashMap<_, _>, _>>()
+            .context("Failed to walk the history to squash")?;
+        self.tracer.event(|| {
+            json!({
+                "event": "squash",
+                "new": base.to_string(),
+                "squashed": squashed.len(),
+                "identity": format!("{} <{}>", identity.name, identity.email),
+            })
+        });
 
-        let commits: Vec<Oid> = revwalk.collect::<Result<Vec<_>, _>>()?;
-        if commits.is_empty() {
-            anyhow::bail!("No commits found in repository");
+        self.anonymize_commits_onto(
+            identity,
+            branch,
+            None,
+            Some(&boundary.id().to_string()),
+            &RewriteOptions {
+                timestamps: Some(timestamps),
+                ..RewriteOptions::default()
+            },
+            squashed,
+        )
+    }
+
+    /// How many commits squashing `from..to` would collapse and when they
+    /// were made. Only reads the repository.
+    pub fn summarize_range(&self, from: Option<&str>, to: &str) -> Result<RangeSummary> {
+        let (_, _, commits) = self.resolve_squash_range(from, to)?;
+        let mut times = Vec::with_capacity(commits.len());
+        for &oid in &commits {
+            times.push(self.repo.find_commit(oid)?.time().seconds());
         }
+        Ok(RangeSummary {
+            commits: commits.len(),
+            oldest: times.iter().copied().min().unwrap_or_default(),
+            newest: times.iter().copied().max().unwrap_or_default(),
+        })
+    }
 
-        let tree = self
-            .repo
-            .find_commit(self.repo.head()?.target().unwrap())?
-            .tree()?;
-        let signature = Signature::now(&identity.name, &identity.email)?;
-        let new_commit_oid = self
-            .repo
-            .commit(None, &signature, &signature, message, &tree, &[])?;
+    /// The base and tip commits of a squash range and the (non-empty) list of
+    /// commits between them, newest first.
+    fn resolve_squash_range(
+        &self,
+        from: Option<&str>,
+        to: &str,
+    ) -> Result<(Option<Commit<'_>>, Commit<'_>, Vec<Oid>)> {
+        let resolve = |rev: &str| {
+            self.repo
+                .revparse_single(rev)
+                .and_then(|object| object.peel_to_commit())
+                .with_context(|| format!("Failed to resolve revision {rev}"))
+        };
+        let tip = resolve(to)?;
+        let base = from.map(resolve).transpose()?;
+        if let Some(base) = &base
+            && base.id() != tip.id()
+            && !self.repo.graph_descendant_of(tip.id(), base.id())?
+        {
+            anyhow::bail!("{} is not an ancestor of {to}", from.unwrap_or_default());
+        }
 
-        let mut branch_ref = self.repo.find_branch(branch, BranchType::Local)?;
-        branch_ref
-            .get_mut()
-            .set_target(new_commit_oid, "Squashed all commits")?;
+        let commits = self.collect_commits_from(
+            Some(tip.id()),
+            base.as_ref().map(|base| base.id().to_string()).as_deref(),
+        )?;
+        if commits.is_empty() {
+            match from {
+                Some(from) => anyhow::bail!("No commits after {from} to squash"),
+                None => anyhow::bail!("No commits found to squash"),
+            }
+        }
+        Ok((base, tip, commits))
+    }
 
-        Ok(())
+    /// Author and committer for a squashed commit: the anonymous identity,
+    /// dated from `tip`'s dates by `timestamps`.
+    fn squash_signatures(
+        &self,
+        identity: &AnonymousIdentity,
+        tip: &Commit,
+        timestamps: TimestampStrategy,
+    ) -> Result<(Signature<'static>, Signature<'static>)> {
+        let now = self.anonymous_signature(identity)?;
+        if timestamps == TimestampStrategy::Now {
+            return Ok((now.clone(), now));
+        }
+        let author = timestamps.date(tip.author().when(), now.when(), None);
+        let committer = timestamps.date(tip.committer().when(), now.when(), None);
+        // Jittering the two dates independently must not date the commit
+        // before it was authored.
+        let committer = match committer.seconds() < author.seconds() {
+            true => Time::new(author.seconds(), committer.offset_minutes()),
+            false => committer,
+        };
+        Ok((
+            self.signature_at(identity, author)?,
+            self.signature_at(identity, committer)?,
+        ))
     }
 
-    pub fn anonymize_commits(
+    /// Like `anonymize_commits`, but walks from `start` instead of HEAD when
+    /// given, and commits found in `rewritten` are not rewritten again:
+    /// children are re-attached to the commits it maps them to. The returned
+    /// mapping includes `rewritten`.
+    fn anonymize_commits_onto(
         &self,
         identity: &AnonymousIdentity,
         branch: &str,
+        start: Option<Oid>,
         since_commit: Option<&str>,
-    ) -> Result<u32> {
+        options: &RewriteOptions,
+        rewritten: HashMap<Oid, Oid>,
+    ) -> Result<HashMap<Oid, Oid>> {
+        // Ctrl-C lets the commit in progress finish; `branch` is only moved
+        // once every commit is rewritten.
+        let _deferral = Deferral::start();
         let pb = ProgressBar::new_spinner();
         pb.set_style(
             ProgressStyle::default_spinner()
@@ -94,98 +1113,2074 @@ impl GitOps {
         );
         pb.set_message("Collecting commits to anonymize...");
 
-        let commits = self.collect_commits(since_commit)?;
-        let total = commits.len() as u32;
-
-        if total == 0 {
+        let tip = match start {
+            Some(oid) => oid,
+            None => self.head_commit()?.id(),
+        };
+        let mut walk = self
+            .walk_commits_oldest_first(start, since_commit)?
+            .peekable();
+        if walk.peek().is_none() {
             pb.finish_with_message("No commits to anonymize");
-            return Ok(0);
+            return Ok(rewritten);
         }
-
-        pb.set_length(total as u64);
+        // The walk is not buffered; an unsorted count is cheap and gives the
+        // bar a length to estimate from.
+        let total = self
+            .profiler
+            .time("history walk", || self.count_commits(start, since_commit))?;
+        pb.set_length(total);
         pb.set_style(
             ProgressStyle::default_bar()
-                .template("{bar:40.cyan/blue} {pos}/{len} {msg}")
+                .template("{bar:40.cyan/blue} {pos}/{len} ({per_sec}, ETA {eta}) {msg}")
                 .unwrap(),
         );
+        pb.enable_steady_tick(Duration::from_millis(100));
 
-        let signature = Signature::now(&identity.name, &identity.email)?;
-        let mut new_commits = std::collections::HashMap::new();
+        let now = self.anonymous_signature(identity)?.when();
+        let mailmap = options
+            .use_mailmap
+            .then(|| self.load_mailmap())
+            .transpose()?;
+        let seeded = rewritten.len();
+        let mut new_commits = rewritten;
+        let mut future_dated = 0;
+        let mut signatures_dropped = 0;
+        // Skipped commits map to the rewritten parents their children are re-attached to.
+        let mut skipped: HashMap<Oid, Vec<Oid>> = HashMap::new();
+        // The tree of the commit rewritten last, usually the next one's parent.
+        let mut last_tree: Option<Tree> = None;
+        let mut walked = 0;
 
-        for (i, &oid) in commits.iter().rev().enumerate() {
-            pb.set_position(i as u64);
-            pb.set_message(format!("Anonymizing commit {}", &oid.to_string()[..8]));
+        loop {
+            let batch = self.profiler.time("history walk", || {
+                walk.by_ref()
+                    .take(PREPARE_BATCH)
+                    .collect::<Result<Vec<Oid>>>()
+            })?;
+            if batch.is_empty() {
+                break;
+            }
+            let mut prepared = self.profiler.time("commit preparation", || {
+                self.prepare_commits(&batch, &new_commits, identity, options)
+            })?;
 
-            let commit = self.repo.find_commit(oid)?;
-            let tree = commit.tree()?;
-
-            let new_parents: Vec<Commit> = commit
-                .parent_ids()
-                .filter_map(|pid| {
-                    new_commits
-                        .get(&pid)
-                        .and_then(|&new_oid| self.repo.find_commit(new_oid).ok())
-                })
-                .collect();
+            for &oid in &batch {
+                if interrupt::requested() {
+                    return Err(interrupted(&pb, branch, new_commits.len() - seeded));
+                }
+                walked += 1;
+                pb.set_position(walked);
+                pb.set_message(format!("Anonymizing commit {}", &oid.to_string()[..8]));
+                if new_commits.contains_key(&oid) {
+                    continue;
+                }
 
-            let parents_refs: Vec<&Commit> = new_parents.iter().collect();
-            let new_oid = self.repo.commit(
-                None,
-                &signature,
-                &signature,
-                commit.message().unwrap_or(""),
-                &tree,
-                &parents_refs,
-            )?;
+                let Some((commit, tree)) = self.read_commit(oid, last_tree.as_ref(), &pb)? else {
+                    let replacement: Vec<Oid> = self
+                        .repo
+                        .find_commit(oid)
+                        .map(|commit| {
+                            commit
+                                .parent_ids()
+                                .flat_map(|pid| resolve_parent(&new_commits, &skipped, pid))
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    self.tracer.event(|| {
+                        json!({
+                            "event": "skip",
+                            "old": oid.to_string(),
+                            "reattached_to": oid_strings(&replacement),
+                        })
+                    });
+                    skipped.insert(oid, replacement);
+                    continue;
+                };
 
-            new_commits.insert(oid, new_oid);
+                let new_parents: Vec<Commit> = commit
+                    .parent_ids()
+                    .flat_map(|pid| resolve_parent(&new_commits, &skipped, pid))
+                    .filter_map(|new_oid| self.repo.find_commit(new_oid).ok())
+                    .collect();
+
+                let parents_refs: Vec<&Commit> = new_parents.iter().collect();
+                let prepared = match prepared.remove(&oid) {
+                    Some(prepared) => prepared,
+                    None => prepare_commit(
+                        &self.repo,
+                        mailmap.as_ref(),
+                        &commit,
+                        &tree,
+                        last_tree.as_ref(),
+                        identity,
+                        options,
+                    )?,
+                };
+                let touched = options
+                    .only_commits
+                    .as_ref()
+                    .is_none_or(|only| only.contains(&oid))
+                    && (options.pathspec.is_none() || prepared.touches_pathspec);
+                let author = match commit.author() {
+                    author if !touched || options.keep_authors || !options.replaces(&author) => {
+                        author
+                    }
+                    author => {
+                        let when = match (options.timestamps, options.author_date) {
+                            (Some(strategy), _) => {
+                                let floor = new_parents.iter().map(|p| p.author().when().seconds());
+                                strategy.date(author.when(), now, floor.max())
+                            }
+                            (None, AuthorDatePolicy::Now) => now,
+                            (None, AuthorDatePolicy::Keep) => author.when(),
+                        };
+                        let canonical = mailmapped(mailmap.as_ref(), &author)?;
+                        self.signature_at(
+                            &options.replacement_for(oid, &canonical, identity),
+                            when,
+                        )?
+                    }
+                };
+                let committer = match commit.committer() {
+                    committer if !touched || !options.replaces(&committer) => committer,
+                    committer => {
+                        let when = match (options.timestamps, options.committer_date) {
+                            (Some(strategy), _) => {
+                                let floor = new_parents
+                                    .iter()
+                                    .map(|p| p.committer().when().seconds())
+                                    .chain([author.when().seconds()]);
+                                strategy.date(committer.when(), now, floor.max())
+                            }
+                            (None, CommitterDatePolicy::Now) => now,
+                            (None, CommitterDatePolicy::Keep) => committer.when(),
+                        };
+                        let canonical = mailmapped(mailmap.as_ref(), &committer)?;
+                        self.signature_at(
+                            &options.replacement_for(oid, &canonical, identity),
+                            when,
+                        )?
+                    }
+                };
+                let future = author.when().seconds() > now.seconds()
+                    || committer.when().seconds() > now.seconds();
+                let (author, committer) = if future {
+                    future_dated += 1;
+                    match options.clamp_future_dates {
+                        true => (clamp_to(&author, now)?, clamp_to(&committer, now)?),
+                        false => (author, committer),
+                    }
+                } else {
+                    (author, committer)
+                };
+                let original_message = commit.message().unwrap_or("");
+                // A commit left out by `--range` or `--pathspec` keeps its real
+                // author, so its message, trailers included, stays theirs too.
+                let message = match (touched, prepared.message.as_deref()) {
+                    (true, Some(scrubbed)) => scrubbed,
+                    _ => original_message,
+                };
+                if options.keep_unchanged
+                    && raw_signature(&author) == raw_signature(&commit.author())
+                    && raw_signature(&committer) == raw_signature(&commit.committer())
+                    && message == original_message
+                    && new_parents.iter().map(Commit::id).eq(commit.parent_ids())
+                {
+                    new_commits.insert(oid, oid);
+                    last_tree = Some(tree);
+                    continue;
+                }
+                // The new commit is built from scratch, so the original's `gpgsig`
+                // and `mergetag` headers, whose key IDs and taggers would identify
+                // the author, are never carried over.
+                let was_signed = carries_signature(&commit);
+                if was_signed {
+                    signatures_dropped += 1;
+                }
+                // A commit that keeps its real identities isn't the anonymous
+                // identity's to vouch for.
+                let sign = signature_key(&author) != signature_key(&commit.author())
+                    || signature_key(&committer) != signature_key(&commit.committer());
+                let new_oid = self.profiler.time("commit creation", || {
+                    self.create_commit(&author, &committer, message, &tree, &parents_refs, sign)
+                })?;
+
+                self.tracer.event(|| {
+                    let mut transforms = Vec::new();
+                    if signature_key(&author) != signature_key(&commit.author()) {
+                        transforms.push("author");
+                    }
+                    if signature_key(&committer) != signature_key(&commit.committer()) {
+                        transforms.push("committer");
+                    }
+                    if message != original_message {
+                        transforms.push("message-scrub");
+                    }
+                    if future && options.clamp_future_dates {
+                        transforms.push("clamp-future-date");
+                    }
+                    if was_signed {
+                        transforms.push("strip-signature");
+                    }
+                    let parents: Vec<_> = commit
+                        .parent_ids()
+                        .map(|pid| {
+                            json!({
+                                "old": pid.to_string(),
+                                "new": oid_strings(&resolve_parent(&new_commits, &skipped, pid)),
+                            })
+                        })
+                        .collect();
+                    json!({
+                        "event": "commit",
+                        "old": oid.to_string(),
+                        "new": new_oid.to_string(),
+                        "touched": touched,
+                        "author": signature_key(&author),
+                        "committer": signature_key(&committer),
+                        "transforms": transforms,
+                        "parents": parents,
+                    })
+                });
+                new_commits.insert(oid, new_oid);
+                last_tree = Some(tree);
+            }
+        }
+
+        if interrupt::requested() {
+            return Err(interrupted(&pb, branch, new_commits.len() - seeded));
         }
+        let Some(&new_head) = new_commits.get(&tip) else {
+            anyhow::bail!("Tip commit {tip} could not be rewritten");
+        };
+        self.profiler.time("ref update", || {
+            self.set_branch_target(branch, new_head, "Anonymized commits")
+        })?;
 
-        if let Some(&new_head) = new_commits.get(&commits[0]) {
-            let mut branch_ref = self.repo.find_branch(branch, BranchType::Local)?;
-            branch_ref
-                .get_mut()
-                .set_target(new_head, "Anonymized commits")?;
+        if future_dated > 0 {
+            pb.suspend(|| {
+                self.warnings.warn(match options.clamp_future_dates {
+                    true => {
+                        format!("{future_dated} commits were dated in the future; clamped to now")
+                    }
+                    false => format!(
+                        "{future_dated} commits are dated in the future, which looks suspicious \
+                         in published history; pass --clamp-future-dates to move them to now"
+                    ),
+                })
+            });
         }
 
-        pb.finish_with_message(format!("Anonymized {total} commits"));
-        Ok(total)
+        let rewritten = new_commits.len() - seeded;
+        let mut notes = Vec::new();
+        if !skipped.is_empty() {
+            notes.push(format!("{} unreadable commits skipped", skipped.len()));
+        }
+        if signatures_dropped > 0 {
+            notes.push(format!("{signatures_dropped} original signatures dropped"));
+        }
+        if notes.is_empty() {
+            pb.finish_with_message(format!("Anonymized {rewritten} commits"));
+        } else {
+            pb.finish_with_message(format!(
+                "Anonymized {rewritten} commits ({})",
+                notes.join(", ")
+            ));
+        }
+        Ok(new_commits)
     }
 
-    pub fn push_to_remote(&self, remote_name: &str, branch: &str, force: bool) -> Result<()> {
-        let mut remote = self.repo.find_remote(remote_name)?;
-        let refspec = format!(
-            "{}refs/heads/{}:refs/heads/{}",
-            if force { "+" } else { "" },
-            branch,
-            branch
-        );
+    /// Points `branch` at `target`, creating the branch if it does not exist.
+    fn set_branch_target(&self, branch: &str, target: Oid, log_message: &str) -> Result<()> {
+        self.tracer.event(|| {
+            json!({
+                "event": "ref-update",
+                "ref": branch,
+                "target": target.to_string(),
+            })
+        });
+        if branch.starts_with("refs/") {
+            match self.repo.find_reference(branch) {
+                Ok(reference) => {
+                    reference.resolve()?.set_target(target, log_message)?;
+                }
+                Err(_) => {
+                    self.repo.reference(branch, target, false, log_message)?;
+                }
+            }
+            return Ok(());
+        }
 
-        remote.push(&[&refspec], None)?;
+        match self.repo.find_branch(branch, BranchType::Local) {
+            Ok(branch_ref) => {
+                branch_ref
+                    .get()
+                    .resolve()?
+                    .set_target(target, log_message)?;
+            }
+            Err(_) => {
+                self.repo
+                    .branch(branch, &self.repo.find_commit(target)?, false)?;
+            }
+        }
         Ok(())
     }
 
-    pub fn get_remote_tracking_branch(&self, remote: &str, branch: &str) -> Result<Option<Oid>> {
-        let refname = format!("refs/remotes/{remote}/{branch}");
-        match self.repo.find_reference(&refname) {
-            Ok(reference) => Ok(reference.target()),
-            Err(_) => Ok(None),
+    pub fn push_to_remote(&self, remote_name: &str, branch: &str, force: bool) -> Result<()> {
+        self.push_to_remote_as(remote_name, branch, branch, force)
+    }
+
+    /// The first tip of `refs` still authored by an email the rewrite in
+    /// `mapping` replaced as an author, or committed by one it replaced as a
+    /// committer, which means part of the history was left un-anonymized.
+    /// Described as `<ref>: its tip <oid> still has the real <role> <who>`.
+    /// Roles are kept apart so `--keep-authors`, which leaves the real
+    /// authors in place on purpose, isn't flagged.
+    pub fn real_push_tip(
+        &self,
+        refs: &[(String, String)],
+        mapping: &HashMap<Oid, Oid>,
+    ) -> Result<Option<String>> {
+        let (mut authors, mut committers) = (HashSet::new(), HashSet::new());
+        for (&old, &new) in mapping {
+            let (old, new) = (self.repo.find_commit(old)?, self.repo.find_commit(new)?);
+            for (replaced, before, after) in [
+                (&mut authors, old.author(), new.author()),
+                (&mut committers, old.committer(), new.committer()),
+            ] {
+                if before.email_bytes() != after.email_bytes() {
+                    replaced.insert(before.email_bytes().to_vec());
+                }
+            }
+        }
+
+        for (local, _) in refs {
+            let tip = self
+                .find_push_ref(local)?
+                .peel_to_commit()
+                .with_context(|| format!("Failed to read the tip of {local}"))?;
+            for (role, replaced, signature) in [
+                ("author", &authors, tip.author()),
+                ("committer", &committers, tip.committer()),
+            ] {
+                if replaced.contains(signature.email_bytes()) {
+                    return Ok(Some(format!(
+                        "{local}: its tip {} still has the real {role} {}",
+                        &tip.id().to_string()[..8],
+                        signature_key(&signature)
+                    )));
+                }
+            }
         }
+        Ok(None)
     }
 
-    pub fn count_commits_to_anonymize(&self, since_commit: Option<&str>) -> Result<u32> {
-        Ok(self.collect_commits(since_commit)?.len() as u32)
+    /// The `forbidden` identities carried by any of `commits`, as
+    /// `Name <email>` with the first commit seen with each.
+    pub fn find_forbidden(
+        &self,
+        commits: &HashSet<Oid>,
+        forbidden: &ForbiddenIdentities,
+    ) -> Result<BTreeMap<String, Oid>> {
+        let mut found = BTreeMap::new();
+        for &oid in commits {
+            let commit = self.repo.find_commit(oid)?;
+            for signature in [commit.author(), commit.committer()] {
+                let name = String::from_utf8_lossy(signature.name_bytes());
+                let email = String::from_utf8_lossy(signature.email_bytes());
+                if forbidden.matches(&name, &email) {
+                    found.entry(signature_key(&signature)).or_insert(oid);
+                }
+            }
+        }
+        Ok(found)
     }
 
-    fn collect_commits(&self, since_commit: Option<&str>) -> Result<Vec<Oid>> {
-        let mut revwalk = self.repo.revwalk()?;
-        revwalk.push_head()?;
+    /// Fails unless a remote called `name` is configured.
+    pub fn check_remote(&self, name: &str) -> Result<()> {
+        self.repo
+            .find_remote(name)
+            .with_context(|| format!("No such remote: {name}"))?;
+        Ok(())
+    }
 
-        if let Some(since) = since_commit {
-            let oid = self.repo.revparse_single(since)?.id();
+    /// Pushes local `branch` to `remote_branch` on the remote.
+    pub fn push_to_remote_as(
+        &self,
+        remote_name: &str,
+        branch: &str,
+        remote_branch: &str,
+        force: bool,
+    ) -> Result<()> {
+        self.push_refs_as(
+            remote_name,
+            &[(branch.to_string(), remote_branch.to_string())],
+            force,
+        )
+    }
+
+    /// Pushes every `(local, remote)` ref pair in a single push, so branches
+    /// and tags reach the remote together.
+    pub fn push_refs_as(
+        &self,
+        remote_name: &str,
+        refs: &[(String, String)],
+        force: bool,
+    ) -> Result<()> {
+        self.profiler
+            .time("push", || self.push_refspecs(remote_name, refs, force))
+    }
+
+    fn push_refspecs(
+        &self,
+        remote_name: &str,
+        refs: &[(String, String)],
+        force: bool,
+    ) -> Result<()> {
+        let mut remote = self.repo.find_remote(remote_name)?;
+        let refspecs = refs
+            .iter()
+            .map(|(branch, remote_branch)| {
+                let source = self.resolve_push_source(branch)?;
+                let destination = match remote_branch.starts_with("refs/") {
+                    true => remote_branch.to_string(),
+                    false => format!("refs/heads/{remote_branch}"),
+                };
+                Ok(format!(
+                    "{}{source}:{destination}",
+                    if force { "+" } else { "" }
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if remote.url().is_some_and(needs_remote_helper) {
+            return self.push_with_git_cli(remote_name, &refspecs);
+        }
+
+        let rejected = RefCell::new(Vec::new());
+        let mut callbacks = self.remote_callbacks()?;
+        callbacks.push_update_reference(|refname, status| {
+            if let Some(status) = status {
+                rejected.borrow_mut().push(format!("{refname}: {status}"));
+            }
+            Ok(())
+        });
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks);
+        match remote.push(&refspecs, Some(&mut options)) {
+            Err(e) if e.message().contains("unsupported URL protocol") => {
+                return self.push_with_git_cli(remote_name, &refspecs);
+            }
+            result => result.with_context(|| format!("Failed to push to {remote_name}"))?,
+        }
+        drop(options);
+        let rejected = rejected.into_inner();
+        match rejected.is_empty() {
+            true => Ok(()),
+            false => anyhow::bail!("{remote_name} rejected the push of {}", rejected.join(", ")),
+        }
+    }
+
+    /// Callbacks that authenticate network operations: with `identity_file`
+    /// or else the ssh-agent for SSH remotes, and with the configured git
+    /// credential helper for HTTPS ones. libgit2 asks again after every
+    /// rejected credential, so each source is offered once. A rejected
+    /// `identity_file` is not followed by the agent, whose keys would tie the
+    /// anonymous identity to the real one (like `IdentitiesOnly=yes`).
+    fn remote_callbacks(&self) -> Result<RemoteCallbacks<'_>> {
+        let config = self.repo.config()?;
+        let identity_file = self.identity_file.clone();
+        let (mut tried_key, mut tried_agent, mut tried_helper) = (false, false, false);
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(move |url, username, allowed| {
+            let user = username.unwrap_or("git");
+            if allowed.contains(CredentialType::SSH_KEY) {
+                if let Some(key) = &identity_file {
+                    if !tried_key {
+                        tried_key = true;
+                        return Cred::ssh_key(user, None, key, None);
+                    }
+                    return Err(git2::Error::from_str(&format!(
+                        "{url} rejected the --identity-file key; not falling back to ssh-agent"
+                    )));
+                }
+                if !tried_agent {
+                    tried_agent = true;
+                    return Cred::ssh_key_from_agent(user);
+                }
+            }
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) && !tried_helper {
+                tried_helper = true;
+                return Cred::credential_helper(&config, url, username);
+            }
+            if allowed.contains(CredentialType::USERNAME) {
+                return Cred::username(user);
+            }
+            Err(git2::Error::from_str(&format!(
+                "no accepted credentials for {url}: tried {}",
+                [
+                    (tried_key, "--identity-file"),
+                    (tried_agent, "ssh-agent"),
+                    (tried_helper, "credential helper"),
+                ]
+                .iter()
+                .filter(|(tried, _)| *tried)
+                .map(|(_, source)| *source)
+                .collect::<Vec<_>>()
+                .join(", ")
+            )))
+        });
+        Ok(callbacks)
+    }
+
+    /// The full name of the ref `branch` refers to, following symbolic refs,
+    /// so branches outside `refs/heads` or behind a symref still push.
+    fn resolve_push_source(&self, branch: &str) -> Result<String> {
+        let resolved = self
+            .find_push_ref(branch)?
+            .resolve()
+            .with_context(|| format!("Failed to resolve {branch}"))?;
+
+        resolved
+            .name()
+            .map(str::to_string)
+            .with_context(|| format!("Ref for {branch} has a non-UTF-8 name"))
+    }
+
+    /// The ref a pushed `branch` names: the local branch of that name when
+    /// there is one, so a tag or remote branch with the same short name
+    /// can't shadow it, otherwise whatever git's DWIM rules find.
+    fn find_push_ref(&self, branch: &str) -> Result<Reference<'_>> {
+        if branch.starts_with("refs/") {
+            return self
+                .repo
+                .find_reference(branch)
+                .with_context(|| format!("Cannot find a local ref for {branch}"));
+        }
+        self.repo
+            .find_reference(&format!("refs/heads/{branch}"))
+            .or_else(|_| self.repo.resolve_reference_from_short_name(branch))
+            .with_context(|| format!("Cannot find a local ref for {branch}"))
+    }
+
+    /// Asks the remote whether it already has `branch`. This goes through
+    /// `git ls-remote` so remote helpers work, and because git2's
+    /// `Remote::list` builds a slice from a null pointer for empty remotes.
+    pub fn remote_has_branch(&self, remote_name: &str, branch: &str) -> Result<bool> {
+        let refname = format!("refs/heads/{branch}");
+        let output = self
+            .remote_git_command()
+            .args(["ls-remote", "--heads", remote_name, &refname])
+            .output()
+            .context("Failed to run git ls-remote")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "git ls-remote {remote_name} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(!output.stdout.is_empty())
+    }
+
+    /// Runs `git fsck` over the object database. Returns the dangling objects
+    /// it reports, and fails with its findings if any object is broken.
+    pub fn verify_objects(&self) -> Result<Vec<String>> {
+        let output = self
+            .profiler
+            .time("object verification", || {
+                self.git_command()
+                    .args(["fsck", "--full", "--no-progress"])
+                    .output()
+            })
+            .context("Failed to run git fsck")?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (dangling, problems): (Vec<&str>, Vec<&str>) = stdout
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .partition(|line| line.starts_with("dangling "));
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let findings: Vec<&str> = stderr
+                .lines()
+                .chain(problems)
+                .filter(|line| !line.trim().is_empty())
+                .collect();
+            anyhow::bail!(
+                "Object verification failed; repair the repository before rewriting it:\n  {}",
+                findings.join("\n  ")
+            );
+        }
+
+        Ok(dangling.into_iter().map(str::to_string).collect())
+    }
+
+    /// A `git` command for talking to remotes: like `git_command`, but SSH
+    /// only offers `identity_file` when one is set.
+    fn remote_git_command(&self) -> Command {
+        let mut command = self.git_command();
+        if let Some(key) = &self.identity_file {
+            command.arg("-c").arg(format!(
+                "core.sshCommand=ssh -i {} -o IdentitiesOnly=yes",
+                shell_quote(&key.to_string_lossy())
+            ));
+        }
+        command
+    }
+
+    /// Pushes through the `git` CLI so custom remote helpers (such as
+    /// `git-remote-rad`) are used, which libgit2 cannot do.
+    fn push_with_git_cli(&self, remote_name: &str, refspecs: &[String]) -> Result<()> {
+        let output = self
+            .remote_git_command()
+            .args(["push", remote_name])
+            .args(refspecs)
+            .output()
+            .context("Failed to run git push")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match stderr.trim() {
+                "" => anyhow::bail!("git push to {remote_name} failed ({})", output.status),
+                stderr => anyhow::bail!("git push to {remote_name} failed: {stderr}"),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_remote_tracking_branch(&self, remote: &str, branch: &str) -> Result<Option<Oid>> {
+        let refname = format!("refs/remotes/{remote}/{branch}");
+        match self.repo.find_reference(&refname) {
+            Ok(reference) => Ok(reference.target()),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Finds blobs reachable from HEAD larger than `threshold` bytes, each
+    /// reported with the oldest commit whose tree contains it.
+    pub fn find_large_blobs(&self, threshold: u64) -> Result<Vec<LargeBlob>> {
+        let odb = self.repo.odb()?;
+        let mut seen_trees = HashSet::new();
+        let mut seen_blobs = HashSet::new();
+        let mut large = Vec::new();
+
+        for oid in self.collect_commits(None)?.into_iter().rev() {
+            let Ok(commit) = self.repo.find_commit(oid) else {
+                continue;
+            };
+            let tree = commit
+                .tree()
+                .with_context(|| format!("Failed to read tree of commit {oid}"))?;
+            if !seen_trees.insert(tree.id()) {
+                continue;
+            }
+
+            tree.walk(TreeWalkMode::PreOrder, |root, entry| match entry.kind() {
+                Some(ObjectType::Tree) if !seen_trees.insert(entry.id()) => TreeWalkResult::Skip,
+                Some(ObjectType::Blob) if seen_blobs.insert(entry.id()) => {
+                    if let Ok((size, _)) = odb.read_header(entry.id())
+                        && size as u64 > threshold
+                    {
+                        large.push(LargeBlob {
+                            oid: entry.id(),
+                            path: format!("{root}{}", entry.name().unwrap_or("")),
+                            size: size as u64,
+                            commit: oid,
+                        });
+                    }
+                    TreeWalkResult::Ok
+                }
+                _ => TreeWalkResult::Ok,
+            })?;
+        }
+
+        Ok(large)
+    }
+
+    /// A signature for `identity` at the current instant, in the configured
+    /// timezone when one is set.
+    fn anonymous_signature(&self, identity: &AnonymousIdentity) -> Result<Signature<'static>> {
+        let now = Signature::now(&identity.name, &identity.email)?;
+        match self.tz_offset {
+            Some(_) => self.signature_at(identity, now.when()),
+            None => Ok(now),
+        }
+    }
+
+    /// Writes a commit without updating any ref, signed when `sign` is set
+    /// and there is a signer.
+    fn create_commit(
+        &self,
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+        tree: &Tree,
+        parents: &[&Commit],
+        sign: bool,
+    ) -> Result<Oid> {
+        let Some(signer) = self.signer.as_ref().filter(|_| sign) else {
+            return Ok(self
+                .repo
+                .commit(None, author, committer, message, tree, parents)?);
+        };
+        let buffer = self
+            .repo
+            .commit_create_buffer(author, committer, message, tree, parents)?;
+        let payload = std::str::from_utf8(&buffer).context("Commit is not valid UTF-8")?;
+        let signature = signer.sign(payload)?;
+        Ok(self.repo.commit_signed(payload, &signature, None)?)
+    }
+
+    /// A signature for `identity` at the instant `when`, moved into the
+    /// configured timezone when one is set.
+    fn signature_at(&self, identity: &AnonymousIdentity, when: Time) -> Result<Signature<'static>> {
+        let offset = self.tz_offset.unwrap_or(when.offset_minutes());
+        Ok(Signature::new(
+            &identity.name,
+            &identity.email,
+            &Time::new(when.seconds(), offset),
+        )?)
+    }
+
+    /// Resolves HEAD to a commit, failing cleanly when HEAD is unborn or does
+    /// not point at a commit.
+    fn head_commit(&self) -> Result<Commit<'_>> {
+        let head = self.head()?;
+        head.peel_to_commit().with_context(|| {
+            format!(
+                "HEAD ({}) does not point to a commit",
+                head.name().unwrap_or("HEAD")
+            )
+        })
+    }
+
+    pub fn head_tree(&self) -> Result<Tree<'_>> {
+        let commit = self.head_commit()?;
+        commit
+            .tree()
+            .with_context(|| format!("Failed to read tree of HEAD commit {}", commit.id()))
+    }
+
+    /// Describes signs that the repository uses git-crypt, transcrypt or
+    /// git-secret. Their encrypted blobs and key metadata pass through
+    /// anonymization untouched.
+    pub fn find_encryption_markers(&self) -> Result<Vec<String>> {
+        let mut markers = Vec::new();
+        if self.repo.path().join("git-crypt").exists() {
+            markers.push("git-crypt keys are unlocked in .git/git-crypt".to_string());
+        }
+
+        self.head_tree()?
+            .walk(TreeWalkMode::PreOrder, |root, entry| {
+                let name = entry.name().unwrap_or("");
+                let path = format!("{root}{name}");
+                match name {
+                    ".git-crypt" => markers.push(format!("{path}/ holds git-crypt key metadata")),
+                    ".gitsecret" => markers.push(format!("{path}/ holds git-secret key metadata")),
+                    ".gitattributes" => {
+                        if let Ok(blob) = self.repo.find_blob(entry.id()) {
+                            let content = String::from_utf8_lossy(blob.content());
+                            if content.contains("filter=git-crypt") {
+                                markers.push(format!("{path} marks files for git-crypt"));
+                            } else if content.contains("filter=crypt") {
+                                markers.push(format!("{path} marks files for transcrypt"));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                TreeWalkResult::Ok
+            })?;
+        Ok(markers)
+    }
+
+    /// Lists AUTHORS/LICENSE-style files in HEAD's tree that name people.
+    pub fn find_metadata_files(&self) -> Result<Vec<MetadataFile>> {
+        let mut files = Vec::new();
+        self.head_tree()?
+            .walk(TreeWalkMode::PreOrder, |root, entry| {
+                let path = format!("{root}{}", entry.name().unwrap_or(""));
+                if let Some(kind) = metadata::classify(&path)
+                    && let Ok(blob) = self.repo.find_blob(entry.id())
+                    && let Ok(content) = std::str::from_utf8(blob.content())
+                {
+                    let lines = metadata::identifying_lines(kind, content);
+                    if !lines.is_empty() {
+                        files.push(MetadataFile { path, kind, lines });
+                    }
+                }
+                TreeWalkResult::Ok
+            })?;
+        Ok(files)
+    }
+
+    /// Builds a copy of HEAD's tree with metadata files rewritten to name
+    /// `identity`. Returns the new tree and the paths that changed.
+    pub fn rewrite_metadata_tree(
+        &self,
+        identity: &AnonymousIdentity,
+    ) -> Result<(Oid, Vec<String>)> {
+        let mut rewritten = Vec::new();
+        let tree = self.rebuild_tree(&self.head_tree()?, &mut |path, oid| {
+            let Some(kind) = metadata::classify(path) else {
+                return Ok(BlobAction::Keep);
+            };
+            let blob = self.repo.find_blob(oid)?;
+            let Ok(content) = std::str::from_utf8(blob.content()) else {
+                return Ok(BlobAction::Keep);
+            };
+
+            let new_content = metadata::rewrite(kind, content, identity);
+            if new_content == content {
+                return Ok(BlobAction::Keep);
+            }
+            rewritten.push(path.to_string());
+            Ok(BlobAction::Replace(self.repo.blob(new_content.as_bytes())?))
+        })?;
+        Ok((tree, rewritten))
+    }
+
+    /// Updates the index and working tree for `paths` to match HEAD.
+    pub fn checkout_head_paths(&self, paths: &[String]) -> Result<()> {
+        if paths.is_empty() || self.repo.is_bare() {
+            return Ok(());
+        }
+
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.force();
+        for path in paths {
+            checkout.path(path);
+        }
+        self.repo.checkout_head(Some(&mut checkout))?;
+        Ok(())
+    }
+
+    /// The rules from `.git-anon-ignore` in the working tree, followed by
+    /// the `--strip` globs in `extra`.
+    pub fn strip_rules(&self, extra: &[String]) -> Result<StripRules> {
+        let dir = self.repo.workdir().unwrap_or_else(|| self.repo.path());
+        StripRules::load(dir, extra)
+    }
+
+    /// Files in HEAD's tree that `rules` would strip, without writing anything.
+    pub fn paths_to_strip(&self, rules: &StripRules) -> Result<Vec<String>> {
+        let mut paths = Vec::new();
+        if rules.is_empty() {
+            return Ok(paths);
+        }
+        self.head_tree()?
+            .walk(TreeWalkMode::PreOrder, |root, entry| {
+                if entry.kind() == Some(ObjectType::Blob) {
+                    let path = format!("{root}{}", entry.name().unwrap_or(""));
+                    if rules.matches(&path) {
+                        paths.push(path);
+                    }
+                }
+                TreeWalkResult::Ok
+            })?;
+        Ok(paths)
+    }
+
+    /// `tree` without the files `rules` match, and the paths removed.
+    pub fn strip_tree(&self, tree: Oid, rules: &StripRules) -> Result<(Oid, Vec<String>)> {
+        let mut stripped = Vec::new();
+        let tree = self.rebuild_tree(&self.repo.find_tree(tree)?, &mut |path, _| {
+            if !rules.matches(path) {
+                return Ok(BlobAction::Keep);
+            }
+            stripped.push(path.to_string());
+            Ok(BlobAction::Remove)
+        })?;
+        Ok((tree, stripped))
+    }
+
+    /// Drops `paths` from the index, so a HEAD that no longer has them
+    /// leaves the files in the working tree untracked rather than deleted.
+    pub fn untrack_paths(&self, paths: &[String]) -> Result<()> {
+        if paths.is_empty() || self.repo.is_bare() {
+            return Ok(());
+        }
+
+        let mut index = self.repo.index()?;
+        for path in paths {
+            index.remove_path(Path::new(path))?;
+        }
+        index.write()?;
+        Ok(())
+    }
+
+    /// Rebuilds `tree` bottom-up, letting `action` keep, replace or remove
+    /// each blob by its full path. Subtrees left empty are dropped. When
+    /// `core.ignorecase` is set, entries that differ only in case are an error
+    /// since they would collide on checkout.
+    pub fn rebuild_tree(
+        &self,
+        tree: &Tree,
+        action: &mut dyn FnMut(&str, Oid) -> Result<BlobAction>,
+    ) -> Result<Oid> {
+        let ignorecase = self
+            .repo
+            .config()?
+            .get_bool("core.ignorecase")
+            .unwrap_or(false);
+        self.rebuild_subtree(tree, "", ignorecase, action)
+    }
+
+    fn rebuild_subtree(
+        &self,
+        tree: &Tree,
+        prefix: &str,
+        ignorecase: bool,
+        action: &mut dyn FnMut(&str, Oid) -> Result<BlobAction>,
+    ) -> Result<Oid> {
+        let mut builder = self.repo.treebuilder(Some(tree))?;
+
+        for entry in tree.iter() {
+            let Some(name) = entry.name() else {
+                continue;
+            };
+            let path = format!("{prefix}{name}");
+
+            match entry.kind() {
+                Some(ObjectType::Tree) => {
+                    let subtree = self.repo.find_tree(entry.id())?;
+                    let new_oid =
+                        self.rebuild_subtree(&subtree, &format!("{path}/"), ignorecase, action)?;
+                    if self.repo.find_tree(new_oid)?.is_empty() {
+                        builder.remove(name)?;
+                    } else if new_oid != entry.id() {
+                        builder.insert(name, new_oid, entry.filemode())?;
+                    }
+                }
+                Some(ObjectType::Blob) => match action(&path, entry.id())? {
+                    BlobAction::Keep => {}
+                    BlobAction::Replace(oid) => {
+                        builder.insert(name, oid, entry.filemode())?;
+                    }
+                    BlobAction::Remove => {
+                        builder.remove(name)?;
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        let new_oid = builder.write()?;
+        if ignorecase {
+            self.check_case_collisions(new_oid, prefix)?;
+        }
+        Ok(new_oid)
+    }
+
+    fn check_case_collisions(&self, tree: Oid, prefix: &str) -> Result<()> {
+        let tree = self.repo.find_tree(tree)?;
+        let mut seen: HashMap<String, String> = HashMap::new();
+
+        for name in tree
+            .iter()
+            .filter_map(|entry| entry.name().map(str::to_string))
+        {
+            if let Some(other) = seen.insert(name.to_lowercase(), name.clone()) {
+                anyhow::bail!(
+                    "Path collision with core.ignorecase: '{prefix}{other}' and '{prefix}{name}' differ only in case"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The pseudonym each original identity in range would get, keyed by
+    /// `Name <email>`. Empty unless `options` asks for pseudonyms. Fails if
+    /// two different emails hash to the same alias, which would merge two
+    /// contributors into one.
+    pub fn pseudonym_map(
+        &self,
+        branch: Option<&str>,
+        since_commit: Option<&str>,
+        options: &RewriteOptions,
+        identity: &AnonymousIdentity,
+    ) -> Result<BTreeMap<String, AnonymousIdentity>> {
+        let mut pseudonyms = BTreeMap::new();
+        if options.pseudonyms == PseudonymStrategy::Single {
+            return Ok(pseudonyms);
+        }
+        let identities = self.distinct_identity_count(branch, since_commit)?;
+        if identities > PSEUDONYM_POOL_LIMIT {
+            self.warnings.warn(format!(
+                "{identities} distinct identities are more than the {PSEUDONYM_POOL_LIMIT} 8-digit pseudonyms can keep apart reliably; the run fails if two collide"
+            ));
+        }
+        let mailmap = options
+            .use_mailmap
+            .then(|| self.load_mailmap())
+            .transpose()?;
+        let mut owners: HashMap<String, String> = HashMap::new();
+        for commit in self
+            .collect_branch_commits(branch, since_commit)?
+            .into_iter()
+            .filter_map(|oid| self.repo.find_commit(oid).ok())
+        {
+            for signature in [commit.author(), commit.committer()] {
+                let canonical = mailmapped(mailmap.as_ref(), &signature)?;
+                if options.replaces(&signature)
+                    && !options.author_map.contains_key(&signature_key(&canonical))
+                {
+                    let email = String::from_utf8_lossy(canonical.email_bytes());
+                    let pseudonym = options.pseudonym(&email, identity);
+                    let email = email.trim().to_lowercase();
+                    let owner = owners
+                        .entry(pseudonym.name.clone())
+                        .or_insert(email.clone());
+                    if *owner != email {
+                        anyhow::bail!(
+                            "{owner} and {email} would both become {}; run again, or change pseudonym_salt if it is set",
+                            pseudonym.name
+                        );
+                    }
+                    pseudonyms
+                        .entry(signature_key(&signature))
+                        .or_insert(pseudonym);
+                }
+            }
+        }
+        Ok(pseudonyms)
+    }
+
+    /// The repository's mailmap: `.mailmap` in the worktree or HEAD, plus
+    /// `mailmap.file` and `mailmap.blob` from the git config.
+    pub fn load_mailmap(&self) -> Result<Mailmap> {
+        self.repo.mailmap().context("Failed to load the mailmap")
+    }
+
+    /// Lists the commits a rewrite of `branch` (HEAD when unset) since
+    /// `since_commit` would touch, newest first.
+    pub fn plan_commits(
+        &self,
+        branch: Option<&str>,
+        since_commit: Option<&str>,
+    ) -> Result<Vec<PlannedCommit>> {
+        self.collect_branch_commits(branch, since_commit)?
+            .into_iter()
+            .filter_map(|oid| self.repo.find_commit(oid).ok())
+            .map(|commit| {
+                Ok(PlannedCommit {
+                    oid: commit.id().to_string(),
+                    author: commit.author().to_string(),
+                    committer: commit.committer().to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Lists tags whose target commit is one of `commits`.
+    pub fn tags_targeting(&self, commits: &HashSet<Oid>) -> Result<Vec<PlannedTag>> {
+        let mut tags = Vec::new();
+        for name in self.repo.tag_names(None)?.iter().flatten() {
+            // Tags of trees and blobs can't target a rewritten commit.
+            let Some(target) = self
+                .repo
+                .find_reference(&format!("refs/tags/{name}"))?
+                .peel_to_commit()
+                .ok()
+                .map(|commit| commit.id())
+            else {
+                continue;
+            };
+            if commits.contains(&target) {
+                tags.push(PlannedTag {
+                    name: name.to_string(),
+                    target: target.to_string(),
+                });
+            }
+        }
+        Ok(tags)
+    }
+
+    /// Points every tag whose commit was rewritten at the commit `mapping`
+    /// maps it to. Annotated tags are recreated with `identity` as tagger,
+    /// their original signature removed and, when a signer is set, signed
+    /// again.
+    pub fn retarget_tags(
+        &self,
+        mapping: &HashMap<Oid, Oid>,
+        identity: &AnonymousIdentity,
+    ) -> Result<RetargetedTags> {
+        let mut tags = RetargetedTags::default();
+        let names: Vec<String> = self
+            .repo
+            .tag_names(None)?
+            .iter()
+            .flatten()
+            .map(str::to_string)
+            .collect();
+
+        for name in names {
+            let refname = format!("refs/tags/{name}");
+            let object = self.repo.revparse_single(&refname)?;
+            let before = object.id();
+            let target = object.peel_to_commit().ok().map(|commit| commit.id());
+            let Some(&new_target) = target.and_then(|target| mapping.get(&target)) else {
+                tags.untouched.push(name);
+                continue;
+            };
+
+            match object.as_tag() {
+                Some(tag) => self.rewrite_annotated_tag(&name, tag, new_target, identity)?,
+                None => {
+                    self.repo
+                        .reference(&refname, new_target, true, "git-anon: retarget tag")?;
+                }
+            }
+            self.tracer.event(|| {
+                json!({
+                    "event": "ref-update",
+                    "ref": refname,
+                    "target": new_target.to_string(),
+                })
+            });
+            tags.moved.push((name, before));
+        }
+        Ok(tags)
+    }
+
+    fn rewrite_annotated_tag(
+        &self,
+        name: &str,
+        tag: &Tag,
+        target: Oid,
+        identity: &AnonymousIdentity,
+    ) -> Result<()> {
+        let when = match tag.tagger() {
+            Some(tagger) => tagger.when(),
+            None => self.anonymous_signature(identity)?.when(),
+        };
+        let tagger = self.signature_at(identity, when)?;
+        let message = strip_tag_signature(tag.message().unwrap_or(""));
+
+        let Some(signer) = &self.signer else {
+            let target = self.repo.find_object(target, Some(ObjectType::Commit))?;
+            self.repo.tag(name, &target, &tagger, message, true)?;
+            return Ok(());
+        };
+        let mut payload = format!(
+            "object {target}\ntype commit\ntag {name}\ntagger {}\n\n{message}",
+            raw_signature(&tagger)
+        );
+        if !payload.ends_with('\n') {
+            payload.push('\n');
+        }
+        let signature = signer.sign(&payload)?;
+        let oid = self
+            .repo
+            .odb()?
+            .write(ObjectType::Tag, format!("{payload}{signature}").as_bytes())?;
+        self.repo.reference(
+            &format!("refs/tags/{name}"),
+            oid,
+            true,
+            "git-anon: retarget tag",
+        )?;
+        Ok(())
+    }
+
+    /// Resolves `tag` to its commit, requiring it to be an ancestor of HEAD.
+    pub fn resolve_tag_boundary(&self, tag: &str) -> Result<Oid> {
+        let target = self
+            .repo
+            .revparse_single(&format!("refs/tags/{tag}^{{commit}}"))
+            .with_context(|| format!("Tag not found: {tag}"))?
+            .id();
+        let head = self.head_commit()?.id();
+
+        if target != head && !self.repo.graph_descendant_of(head, target)? {
+            anyhow::bail!("Tag {tag} ({target}) is not an ancestor of HEAD");
+        }
+
+        Ok(target)
+    }
+
+    /// Counts the distinct author and committer identities in the commits a
+    /// rewrite of `branch` (HEAD when unset) since `since_commit` would touch.
+    pub fn distinct_identity_count(
+        &self,
+        branch: Option<&str>,
+        since_commit: Option<&str>,
+    ) -> Result<usize> {
+        let commits = self.collect_branch_commits(branch, since_commit)?;
+        self.profiler
+            .time("identity discovery", || self.count_identities(&commits))
+    }
+
+    /// The distinct authors and committers of `commits`, as `Name <email>`,
+    /// sorted.
+    pub fn identities_of(&self, commits: &[Oid]) -> Vec<String> {
+        let identities: BTreeSet<String> = commits
+            .iter()
+            .filter_map(|&oid| self.repo.find_commit(oid).ok())
+            .flat_map(|commit| [commit.author().to_string(), commit.committer().to_string()])
+            .collect();
+        identities.into_iter().collect()
+    }
+
+    fn count_identities(&self, commits: &[Oid]) -> Result<usize> {
+        let mut identities = HashSet::new();
+        for &oid in commits {
+            let Ok(commit) = self.repo.find_commit(oid) else {
+                continue;
+            };
+            for signature in [commit.author(), commit.committer()] {
+                identities.insert((
+                    signature.name_bytes().to_vec(),
+                    signature.email_bytes().to_vec(),
+                ));
+            }
+        }
+        Ok(identities.len())
+    }
+
+    /// Collects the identities in every commit reachable from HEAD, or from
+    /// every ref but git-anon's backup branches (which keep the original
+    /// history on purpose) when `all_refs` is set. With `tags`, the taggers
+    /// and messages of annotated tags are scanned too.
+    pub fn scan_identities(&self, all_refs: bool, tags: bool) -> Result<IdentityScan> {
+        let mut scan = IdentityScan::default();
+        let mut revwalk = self.repo.revwalk()?;
+        if all_refs {
+            for reference in self.repo.references()? {
+                let reference = reference?;
+                let is_backup = reference.is_branch()
+                    && reference
+                        .shorthand()
+                        .is_some_and(|name| parse_backup_name(name).is_some());
+                if is_backup {
+                    continue;
+                }
+                if let Ok(commit) = reference.peel_to_commit() {
+                    revwalk.push(commit.id())?;
+                }
+            }
+        } else {
+            revwalk.push_head()?;
+        }
+
+        for oid in revwalk {
+            let commit = self.repo.find_commit(oid?)?;
+            let location = format!("commit {}", &commit.id().to_string()[..8]);
+            for signature in [commit.author(), commit.committer()] {
+                scan.signatures
+                    .entry(signature_key(&signature))
+                    .or_insert_with(|| location.clone());
+            }
+            let message = String::from_utf8_lossy(commit.message_bytes());
+            for email in crate::scrub::find_emails(&message) {
+                scan.message_emails
+                    .entry(email.to_string())
+                    .or_insert_with(|| location.clone());
+            }
+            scan.commits += 1;
+        }
+
+        if tags {
+            for name in self.repo.tag_names(None)?.iter().flatten() {
+                let object = self.repo.revparse_single(&format!("refs/tags/{name}"))?;
+                let Some(tag) = object.as_tag() else {
+                    continue;
+                };
+                let location = format!("tag {name}");
+                if let Some(tagger) = tag.tagger() {
+                    scan.signatures
+                        .entry(signature_key(&tagger))
+                        .or_insert_with(|| location.clone());
+                }
+                let message = String::from_utf8_lossy(tag.message_bytes().unwrap_or_default());
+                for email in crate::scrub::find_emails(&message) {
+                    scan.message_emails
+                        .entry(email.to_string())
+                        .or_insert_with(|| location.clone());
+                }
+                scan.tags += 1;
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Reads the author identity of `rev` so new commits can match it.
+    pub fn identity_from_commit(&self, rev: &str) -> Result<(Oid, AnonymousIdentity)> {
+        let commit = self
+            .repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .with_context(|| format!("Commit not found: {rev}"))?;
+        let author = commit.author();
+
+        let (Some(name), Some(email)) = (author.name(), author.email()) else {
+            anyhow::bail!("Author of commit {} is not valid UTF-8", commit.id());
+        };
+        Ok((
+            commit.id(),
+            AnonymousIdentity {
+                name: name.to_string(),
+                email: email.to_string(),
+                signing_key: None,
+            },
+        ))
+    }
+
+    /// Compares `branch`'s tree with `backup_branch`'s and returns the paths
+    /// that differ, ignoring those listed in `expected`.
+    pub fn snapshot_differences(
+        &self,
+        branch: &str,
+        original: &str,
+        expected: &[String],
+    ) -> Result<Vec<String>> {
+        let tree_of = |name: &str| -> Result<Tree<'_>> {
+            Ok(self.repo.revparse_single(name)?.peel_to_tree()?)
+        };
+        let new_tree = tree_of(branch)?;
+        let old_tree = tree_of(original)?;
+        if new_tree.id() == old_tree.id() {
+            return Ok(Vec::new());
+        }
+
+        let diff = self
+            .repo
+            .diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None)?;
+        Ok(diff
+            .deltas()
+            .filter_map(|delta| {
+                delta
+                    .new_file()
+                    .path()
+                    .or_else(|| delta.old_file().path())
+                    .map(|path| path.to_string_lossy().into_owned())
+            })
+            .filter(|path| !expected.contains(path))
+            .collect())
+    }
+
+    /// The author and committer of HEAD, as `Name <email>`.
+    pub fn head_signatures(&self) -> Result<(String, String)> {
+        let commit = self.head_commit()?;
+        Ok((
+            signature_key(&commit.author()),
+            signature_key(&commit.committer()),
+        ))
+    }
+
+    /// Counts the commits reachable from HEAD, and how many of them have an
+    /// author or committer other than `identity`.
+    pub fn count_commits_not_by(&self, identity: &AnonymousIdentity) -> Result<(usize, usize)> {
+        let commits = self.collect_commits(None)?;
+        let is_identity = |signature: &Signature| {
+            signature.name_bytes() == identity.name.as_bytes()
+                && signature.email_bytes() == identity.email.as_bytes()
+        };
+        let mut other = 0;
+        for &oid in &commits {
+            let commit = self.repo.find_commit(oid)?;
+            if !is_identity(&commit.author()) || !is_identity(&commit.committer()) {
+                other += 1;
+            }
+        }
+        Ok((commits.len(), other))
+    }
+
+    /// The upstream `branch` tracks (such as `rad/main`), with how many
+    /// commits the branch is ahead of and behind it.
+    pub fn upstream(&self, branch: &str) -> Result<Option<(String, usize, usize)>> {
+        let local = self.repo.find_branch(branch, BranchType::Local)?;
+        let Ok(upstream) = local.upstream() else {
+            return Ok(None);
+        };
+        let name = upstream.name()?.unwrap_or("(non-UTF-8 name)").to_string();
+        let (Some(tip), Some(upstream_tip)) = (local.get().target(), upstream.get().target())
+        else {
+            return Ok(Some((name, 0, 0)));
+        };
+        let (ahead, behind) = self.repo.graph_ahead_behind(tip, upstream_tip)?;
+        Ok(Some((name, ahead, behind)))
+    }
+
+    /// Counts commits reachable from HEAD whose author or committer uses `email`.
+    pub fn count_commits_with_email(&self, email: &str) -> Result<usize> {
+        Ok(self
+            .collect_commits(None)?
+            .into_iter()
+            .filter_map(|oid| self.repo.find_commit(oid).ok())
+            .filter(|commit| {
+                commit.author().email_bytes() == email.as_bytes()
+                    || commit.committer().email_bytes() == email.as_bytes()
+            })
+            .count())
+    }
+
+    /// Resolves a rev-list style expression (`A..B`, `A...B`, `^A B`,
+    /// `--not A`) to the set of commits it selects.
+    pub fn resolve_range(&self, expr: &str) -> Result<HashSet<Oid>> {
+        let invalid = |reason: String| anyhow::anyhow!("Invalid revision range '{expr}': {reason}");
+        let resolve = |rev: &str| -> Result<Oid> {
+            self.repo
+                .revparse_single(rev)
+                .and_then(|object| object.peel_to_commit())
+                .map(|commit| commit.id())
+                .map_err(|e| invalid(format!("unknown revision '{rev}' ({})", e.message())))
+        };
+
+        let mut revwalk = self.repo.revwalk()?;
+        let mut negated = false;
+        let mut has_tip = false;
+        for token in expr.split_whitespace() {
+            if token == "--not" {
+                negated = !negated;
+                continue;
+            }
+            if negated && token.contains("..") {
+                return Err(invalid(format!("'{token}' cannot follow --not")));
+            }
+            if let Some((from, to)) = token.split_once("...") {
+                let (from, to) = (resolve(or_head(from))?, resolve(or_head(to))?);
+                // A criss-cross history has several merge bases; git hides them all.
+                let bases = self
+                    .repo
+                    .merge_bases(from, to)
+                    .map_err(|_| invalid(format!("'{token}' has no merge base")))?;
+                revwalk.push(from)?;
+                revwalk.push(to)?;
+                for &base in bases.iter() {
+                    revwalk.hide(base)?;
+                }
+                has_tip = true;
+            } else if let Some((from, to)) = token.split_once("..") {
+                revwalk.hide(resolve(or_head(from))?)?;
+                revwalk.push(resolve(or_head(to))?)?;
+                has_tip = true;
+            } else if let Some(rev) = token.strip_prefix('^') {
+                if negated {
+                    revwalk.push(resolve(rev)?)?;
+                    has_tip = true;
+                } else {
+                    revwalk.hide(resolve(rev)?)?;
+                }
+            } else if token.starts_with('-') {
+                return Err(invalid(format!("unsupported option '{token}'")));
+            } else if negated {
+                revwalk.hide(resolve(token)?)?;
+            } else {
+                revwalk.push(resolve(token)?)?;
+                has_tip = true;
+            }
+        }
+
+        if !has_tip {
+            return Err(invalid("no commits to include".to_string()));
+        }
+        revwalk
+            .collect::<Result<HashSet<_>, _>>()
+            .with_context(|| format!("Failed to walk revision range '{expr}'"))
+    }
+
+    pub fn count_commits_to_anonymize(
+        &self,
+        branch: Option<&str>,
+        since_commit: Option<&str>,
+    ) -> Result<u32> {
+        Ok(self.collect_branch_commits(branch, since_commit)?.len() as u32)
+    }
+
+    /// How many commits `anonymize_branches` would add for each branch, with
+    /// commits shared by several branches counted for the first only.
+    pub fn count_branch_commits(
+        &self,
+        branches: &[(String, Option<String>)],
+    ) -> Result<Vec<(String, usize)>> {
+        let mut seen = HashSet::new();
+        let mut counts = Vec::new();
+        for (branch, since_commit) in branches {
+            let tip = self.branch_tip(branch)?;
+            let commits = self.collect_commits_from(Some(tip), since_commit.as_deref())?;
+            let count = commits.into_iter().filter(|&oid| seen.insert(oid)).count();
+            counts.push((branch.clone(), count));
+        }
+        Ok(counts)
+    }
+
+    fn collect_commits(&self, since_commit: Option<&str>) -> Result<Vec<Oid>> {
+        self.collect_commits_from(None, since_commit)
+    }
+
+    /// Like `collect_commits`, but walks from `branch`'s tip when given.
+    fn collect_branch_commits(
+        &self,
+        branch: Option<&str>,
+        since_commit: Option<&str>,
+    ) -> Result<Vec<Oid>> {
+        let start = branch.map(|branch| self.branch_tip(branch)).transpose()?;
+        self.collect_commits_from(start, since_commit)
+    }
+
+    /// Like `collect_commits`, but walks from `start` instead of HEAD.
+    fn collect_commits_from(
+        &self,
+        start: Option<Oid>,
+        since_commit: Option<&str>,
+    ) -> Result<Vec<Oid>> {
+        self.profiler
+            .time("history walk", || self.walk_commits(start, since_commit))
+    }
+
+    fn walk_commits(&self, start: Option<Oid>, since_commit: Option<&str>) -> Result<Vec<Oid>> {
+        let since = self.resolve_since(since_commit)?;
+        if self.skip_corrupt {
+            return self.collect_commits_salvaging(start, since);
+        }
+
+        // Children before parents even when commit dates are skewed.
+        self.revwalk(start, since, Sort::TOPOLOGICAL)?
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to walk commit history (try --skip-corrupt)")
+    }
+
+    /// The commits `walk_commits` finds, parents before children, yielded as
+    /// the revwalk produces them instead of collected first. With
+    /// `--skip-corrupt` the salvaging walk still has to run up front.
+    fn walk_commits_oldest_first(
+        &self,
+        start: Option<Oid>,
+        since_commit: Option<&str>,
+    ) -> Result<Box<dyn Iterator<Item = Result<Oid>> + '_>> {
+        let since = self.resolve_since(since_commit)?;
+        if self.skip_corrupt {
+            let commits = self.collect_commits_salvaging(start, since)?;
+            return Ok(Box::new(commits.into_iter().rev().map(Ok)));
+        }
+
+        let revwalk = self.revwalk(start, since, Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        Ok(Box::new(revwalk.map(|oid| {
+            oid.context("Failed to walk commit history (try --skip-corrupt)")
+        })))
+    }
+
+    fn resolve_since(&self, since_commit: Option<&str>) -> Result<Option<Oid>> {
+        since_commit
+            .map(|since| {
+                self.repo
+                    .revparse_single(since)
+                    .with_context(|| format!("Failed to resolve revision {since}"))
+                    .map(|object| object.id())
+            })
+            .transpose()
+    }
+
+    /// Number of commits `walk_commits_oldest_first` yields, for progress
+    /// reporting. Unsorted, so no topological pass is paid for; with
+    /// `skip_corrupt` it can fall short of the salvaged walk.
+    fn count_commits(&self, start: Option<Oid>, since_commit: Option<&str>) -> Result<u64> {
+        let since = self.resolve_since(since_commit)?;
+        Ok(self.revwalk(start, since, Sort::NONE)?.count() as u64)
+    }
+
+    /// A revwalk from `start` (HEAD when unset) that hides `since`.
+    fn revwalk(&self, start: Option<Oid>, since: Option<Oid>, sort: Sort) -> Result<Revwalk<'_>> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.set_sorting(sort)?;
+        match start {
+            Some(oid) => revwalk.push(oid)?,
+            None => revwalk.push_head()?,
+        }
+        if let Some(oid) = since {
             revwalk.hide(oid)?;
         }
+        Ok(revwalk)
+    }
+
+    /// Walks parent links by hand so that a missing object doesn't abort the
+    /// whole walk. Unreadable commits are still returned so the rewrite can
+    /// report and skip them, but their parents can't be read, so ancestors
+    /// reachable only through them are not collected. Children come before
+    /// parents, like the default revwalk order.
+    fn collect_commits_salvaging(
+        &self,
+        start: Option<Oid>,
+        since: Option<Oid>,
+    ) -> Result<Vec<Oid>> {
+        let head = match start {
+            Some(oid) => oid,
+            None => self
+                .repo
+                .head()?
+                .target()
+                .context("HEAD does not point to a commit")?,
+        };
+        let hidden = match since {
+            Some(oid) => self.ancestors_salvaging(oid, &HashSet::new()),
+            None => HashSet::new(),
+        };
+
+        let mut commits: Vec<Oid> = self.ancestors_salvaging(head, &hidden);
+        commits.reverse();
+        Ok(commits)
+    }
+
+    /// Post-order walk of `start` and its ancestors, parents first.
+    fn ancestors_salvaging<C: FromIterator<Oid>>(&self, start: Oid, hidden: &HashSet<Oid>) -> C {
+        let mut seen = HashSet::new();
+        let mut order = Vec::new();
+        let mut stack = vec![(start, false)];
+
+        while let Some((oid, expanded)) = stack.pop() {
+            if expanded {
+                order.push(oid);
+                continue;
+            }
+            if hidden.contains(&oid) || !seen.insert(oid) {
+                continue;
+            }
+
+            stack.push((oid, true));
+            match self.repo.find_commit(oid) {
+                Ok(commit) => stack.extend(commit.parent_ids().map(|pid| (pid, false))),
+                Err(e) => self
+                    .warnings
+                    .warn(format!("cannot read commit {oid}: {}", e.message())),
+            }
+        }
+
+        order.into_iter().collect()
+    }
+
+    /// Number of entries in the stash. A `refs/stash` whose reflog has been
+    /// expired (as `clean` does) still holds its newest entry.
+    pub fn stash_count(&self) -> Result<usize> {
+        let entries = self
+            .repo
+            .reflog("refs/stash")
+            .context("Failed to read the stash")?
+            .len();
+        match self.repo.find_reference("refs/stash") {
+            Ok(_) => Ok(entries.max(1)),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(0),
+            Err(e) => Err(e).context("Failed to read the stash"),
+        }
+    }
+
+    /// Drops every stash entry by deleting `refs/stash` and its reflog.
+    pub fn drop_stashes(&self) -> Result<()> {
+        match self.repo.find_reference("refs/stash") {
+            Ok(mut stash) => stash.delete().context("Failed to drop the stash")?,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(()),
+            Err(e) => return Err(e).context("Failed to read the stash"),
+        }
+        self.repo
+            .reflog_delete("refs/stash")
+            .context("Failed to delete the stash reflog")
+    }
+
+    /// Rewrites every entry of `refname`'s reflog to be committed by `identity`
+    /// (keeping its time), scrubbing the messages the way `options` scrubs
+    /// commit messages, plus the entries' original names and emails, or
+    /// clearing them. Returns the number of entries rewritten.
+    pub fn rewrite_reflog(
+        &self,
+        refname: &str,
+        identity: &AnonymousIdentity,
+        mode: ReflogMode,
+        options: &RewriteOptions,
+    ) -> Result<usize> {
+        let mut reflog = self
+            .repo
+            .reflog(refname)
+            .with_context(|| format!("Failed to read reflog of {refname}"))?;
+
+        let mut originals = HashMap::new();
+        let mut entries = Vec::new();
+        for entry in reflog.iter() {
+            let committer = entry.committer();
+            let parts = [
+                (committer.name(), &identity.name),
+                (committer.email(), &identity.email),
+            ];
+            for (part, replacement) in parts {
+                if let Some(part) = part.filter(|part| {
+                    !part.is_empty() && *part != identity.name && *part != identity.email
+                }) {
+                    originals.insert(part.to_string(), replacement.as_str());
+                }
+            }
+            entries.push((
+                entry.id_new(),
+                committer.when(),
+                entry.message().unwrap_or("").to_string(),
+            ));
+        }
+        let originals_pattern = match (mode, originals.is_empty()) {
+            (ReflogMode::Scrub, false) => Some(whole_words(originals.keys())?),
+            _ => None,
+        };
+
+        // Everything is built before the old entries are dropped, so a
+        // failure leaves the reflog as it was.
+        let mut rewritten = Vec::with_capacity(entries.len());
+        for (id, when, message) in entries.iter().rev() {
+            let signature = Signature::new(&identity.name, &identity.email, when)?;
+            let message = match mode {
+                ReflogMode::Clear => None,
+                ReflogMode::Scrub => {
+                    let message = options.scrub_message(message, identity);
+                    Some(match &originals_pattern {
+                        Some(pattern) => pattern
+                            .replace_all(&message, |caps: &regex::Captures| {
+                                originals[&caps[0]].to_string()
+                            })
+                            .into_owned(),
+                        None => message.into_owned(),
+                    })
+                }
+            };
+            rewritten.push((*id, signature, message));
+        }
+        while !reflog.is_empty() {
+            reflog.remove(0, false)?;
+        }
+        for (id, signature, message) in &rewritten {
+            reflog.append(*id, signature, message.as_deref())?;
+        }
+        reflog
+            .write()
+            .with_context(|| format!("Failed to write reflog of {refname}"))?;
+
+        Ok(entries.len())
+    }
+
+    /// Runs `prepare_commit` for the `commits` not yet in `rewritten` on the
+    /// rayon pool, each worker with its own repository handle. Commits that
+    /// can't be read are left out for the rewrite loop to report. Short
+    /// histories, single-core machines and rewrites that neither filter by
+    /// path nor scrub messages are left to the loop entirely.
+    fn prepare_commits(
+        &self,
+        commits: &[Oid],
+        rewritten: &HashMap<Oid, Oid>,
+        identity: &AnonymousIdentity,
+        options: &RewriteOptions,
+    ) -> Result<HashMap<Oid, PreparedCommit>> {
+        let scrubs = !options.message_scrubs.is_empty() || options.identity_scrub.is_some();
+        if commits.len() < PARALLEL_PREPARE_MIN
+            || rayon::current_num_threads() < 2
+            || (options.pathspec.is_none() && !scrubs)
+        {
+            return Ok(HashMap::new());
+        }
+
+        let path = self.repo.path();
+        let overrides = self.config_file.as_ref().map(NamedTempFile::path);
+        let open = || -> Result<(Repository, Option<Mailmap>)> {
+            let repo = Repository::open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            // Each worker needs the `--git-config` overrides `with_config_overrides` added.
+            if let Some(overrides) = overrides {
+                repo.config()?
+                    .add_file(overrides, ConfigLevel::App, true)
+                    .context("Failed to apply git config overrides")?;
+            }
+            let mailmap = match options.use_mailmap {
+                true => Some(repo.mailmap().context("Failed to load the mailmap")?),
+                false => None,
+            };
+            Ok((repo, mailmap))
+        };
+        commits
+            .par_iter()
+            .filter(|oid| !rewritten.contains_key(oid))
+            .map_init(open, |handle, &oid| {
+                let (repo, mailmap) = handle.as_ref().map_err(|e| anyhow::anyhow!("{e:#}"))?;
+                let Ok(commit) = repo.find_commit(oid) else {
+                    return Ok(None);
+                };
+                let Ok(tree) = commit.tree() else {
+                    return Ok(None);
+                };
+                let prepared = prepare_commit(
+                    repo,
+                    mailmap.as_ref(),
+                    &commit,
+                    &tree,
+                    None,
+                    identity,
+                    options,
+                )?;
+                Ok(Some((oid, prepared)))
+            })
+            .filter_map(Result::transpose)
+            .collect()
+    }
+
+    /// Reads commit `oid` and its tree, reusing `cached` when the commit has
+    /// the same tree. Unreadable commits are skipped with `--skip-corrupt`.
+    fn read_commit<'r>(
+        &'r self,
+        oid: Oid,
+        cached: Option<&Tree<'r>>,
+        pb: &ProgressBar,
+    ) -> Result<Option<(Commit<'r>, Tree<'r>)>> {
+        let read = self
+            .repo
+            .find_commit(oid)
+            .with_context(|| format!("Failed to read commit {oid}"))
+            .and_then(|commit| {
+                let tree = self.profiler.time("tree loading", || {
+                    cached_tree(&self.repo, commit.tree_id(), cached)
+                        .with_context(|| format!("Failed to read tree of commit {oid}"))
+                })?;
+                Ok((commit, tree))
+            });
+
+        match read {
+            Ok(read) => Ok(Some(read)),
+            Err(e) if self.skip_corrupt => {
+                pb.suspend(|| self.warnings.warn(format!("{e:#} (skipped)")));
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Whether `url` uses a transport libgit2 cannot push to natively, either
+/// an unknown `scheme://` or git's `<transport>::<address>` helper syntax.
+fn needs_remote_helper(url: &str) -> bool {
+    if let Some((scheme, _)) = url.split_once("://") {
+        return !matches!(scheme, "http" | "https" | "ssh" | "git" | "file");
+    }
+    url.split_once("::")
+        .is_some_and(|(transport, _)| !transport.contains(['/', ':']))
+}
+
+/// `text` as a single shell word, for commands git runs through `sh`.
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// An empty side of `A..B` means HEAD, as in git.
+fn or_head(rev: &str) -> &str {
+    if rev.is_empty() { "HEAD" } else { rev }
+}
+
+/// Recovers the backed-up branch and creation timestamp from a default
+/// backup name: `backup-<branch>-<timestamp>` or `pre-clean-backup-<timestamp>`,
+/// either optionally followed by the `-N` that `--on-conflict rename` adds.
+/// The timestamp must be a full Unix time so ordinary branches like
+/// `backup-release-1` aren't mistaken for backups.
+fn parse_backup_name(name: &str) -> Option<(Option<String>, i64)> {
+    let is_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let is_timestamp = |part: &str| part.len() >= 10 && is_digits(part);
+    let (clean, rest) = match name.strip_prefix("pre-clean-backup-") {
+        Some(rest) => (true, rest),
+        None => (false, name.strip_prefix("backup-")?),
+    };
+
+    let mut parts: Vec<&str> = rest.split('-').collect();
+    // A rename suffix is much shorter than any timestamp.
+    if let [.., timestamp, suffix] = parts.as_slice()
+        && is_timestamp(timestamp)
+        && is_digits(suffix)
+        && suffix.len() < 4
+    {
+        parts.pop();
+    }
+    let timestamp = parts.pop().filter(|part| is_timestamp(part))?;
+    let created = timestamp.parse().ok()?;
+
+    match (clean, parts.is_empty()) {
+        (true, true) => Some((None, created)),
+        (false, false) => Some((Some(parts.join("-")), created)),
+        _ => None,
+    }
+}
+
+/// Matches any of `parts` where it stands on its own, so a short name like
+/// "Al" isn't found inside "install". Longer parts win, so an email isn't
+/// half-matched by a name inside it.
+fn whole_words<'a>(parts: impl Iterator<Item = &'a String>) -> Result<Regex> {
+    // `\b` only where the part itself ends in a word character.
+    let boundary = |c: Option<char>| match c.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+        true => r"\b",
+        false => "",
+    };
+    let mut parts: Vec<&String> = parts.collect();
+    parts.sort_by_key(|part| std::cmp::Reverse(part.len()));
+    let alternatives: Vec<String> = parts
+        .iter()
+        .map(|part| {
+            let start = boundary(part.chars().next());
+            let end = boundary(part.chars().last());
+            format!("{start}{}{end}", regex::escape(part))
+        })
+        .collect();
+    Regex::new(&alternatives.join("|")).context("Failed to build the reflog scrub pattern")
+}
+
+/// `message` without the signature git appends to signed tag messages.
+fn strip_tag_signature(message: &str) -> &str {
+    [
+        "-----BEGIN PGP SIGNATURE-----",
+        "-----BEGIN SSH SIGNATURE-----",
+        "-----BEGIN SIGNED MESSAGE-----",
+    ]
+    .iter()
+    .filter_map(|marker| message.find(marker))
+    .min()
+    .map_or(message, |start| &message[..start])
+}
+
+/// `signature` as it appears in a raw object header: `Name <email> secs +hhmm`.
+fn raw_signature(signature: &Signature) -> String {
+    let when = signature.when();
+    let offset = when.offset_minutes().abs();
+    format!(
+        "{} {} {}{:02}{:02}",
+        signature_key(signature),
+        when.seconds(),
+        when.sign(),
+        offset / 60,
+        offset % 60
+    )
+}
+
+/// Whether `commit` carries a signature or an embedded signed tag.
+fn carries_signature(commit: &Commit) -> bool {
+    ["gpgsig", "gpgsig-sha256", "mergetag"]
+        .iter()
+        .any(|field| commit.header_field_bytes(*field).is_ok())
+}
+
+/// Works out what rewriting `commit` needs that doesn't depend on its new
+/// parents: whether it touches `--pathspec`, and its scrubbed message.
+fn prepare_commit(
+    repo: &Repository,
+    mailmap: Option<&Mailmap>,
+    commit: &Commit,
+    tree: &Tree,
+    previous_tree: Option<&Tree>,
+    identity: &AnonymousIdentity,
+    options: &RewriteOptions,
+) -> Result<PreparedCommit> {
+    let touches_pathspec = match &options.pathspec {
+        Some(pattern) => touches_pathspec(repo, commit, tree, previous_tree, pattern)?,
+        None => false,
+    };
+    let replacement = options.replacement_for(
+        commit.id(),
+        &mailmapped(mailmap, &commit.author())?,
+        identity,
+    );
+    let message = match options.scrub_message(commit.message().unwrap_or(""), &replacement) {
+        Cow::Owned(message) => Some(message),
+        Cow::Borrowed(_) => None,
+    };
+    Ok(PreparedCommit {
+        touches_pathspec,
+        message,
+    })
+}
+
+/// Whether `commit` changes any path matching `pattern` relative to its
+/// first parent. A pattern also matches everything below a matching
+/// directory, so `secret` covers `secret/key.pem`. `previous_tree` is
+/// reused if it is the parent's tree, as it is along a linear history.
+fn touches_pathspec(
+    repo: &Repository,
+    commit: &Commit,
+    tree: &Tree,
+    previous_tree: Option<&Tree>,
+    pattern: &Pattern,
+) -> Result<bool> {
+    let parent_tree = match commit.parent(0) {
+        Ok(parent) => Some(cached_tree(repo, parent.tree_id(), previous_tree)?),
+        Err(_) => None,
+    };
+    let diff = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(tree), None)
+        .with_context(|| format!("Failed to diff commit {}", commit.id()))?;
+
+    let matches = |path: &Path| path.ancestors().any(|p| pattern.matches_path(p));
+    Ok(diff.deltas().any(|delta| {
+        [delta.old_file().path(), delta.new_file().path()]
+            .into_iter()
+            .flatten()
+            .any(matches)
+    }))
+}
+
+/// The tree `id`, taken from `cached` when it is that tree instead of being
+/// read from the object database again.
+fn cached_tree<'r>(repo: &'r Repository, id: Oid, cached: Option<&Tree<'r>>) -> Result<Tree<'r>> {
+    match cached.filter(|tree| tree.id() == id) {
+        Some(tree) => Ok(tree.clone()),
+        None => Ok(repo.find_tree(id)?),
+    }
+}
+
+/// `signature` as `mailmap` canonicalizes it, or unchanged without one.
+fn mailmapped(mailmap: Option<&Mailmap>, signature: &Signature) -> Result<Signature<'static>> {
+    match mailmap {
+        Some(mailmap) => Ok(mailmap.resolve_signature(signature)?),
+        None => Ok(signature.to_owned()),
+    }
+}
+
+/// A signature as `Name <email>`.
+fn signature_key(signature: &Signature) -> String {
+    format!(
+        "{} <{}>",
+        String::from_utf8_lossy(signature.name_bytes()),
+        String::from_utf8_lossy(signature.email_bytes())
+    )
+}
+
+fn oid_strings(oids: &[Oid]) -> Vec<String> {
+    oids.iter().map(Oid::to_string).collect()
+}
+
+/// `signature` with its date moved back to `now` if it lies in the future.
+fn clamp_to(signature: &Signature, now: Time) -> Result<Signature<'static>> {
+    let when = match signature.when().seconds() > now.seconds() {
+        true => Time::new(now.seconds(), signature.when().offset_minutes()),
+        false => signature.when(),
+    };
+    Ok(Signature::new(
+        &String::from_utf8_lossy(signature.name_bytes()),
+        &String::from_utf8_lossy(signature.email_bytes()),
+        &when,
+    )?)
+}
+
+/// The error a rewrite stops with on Ctrl-C, after `rewritten` commits.
+fn interrupted(pb: &ProgressBar, branch: &str, rewritten: usize) -> anyhow::Error {
+    pb.abandon_with_message("Interrupted");
+    anyhow::anyhow!("Interrupted after rewriting {rewritten} commits; {branch} was not moved")
+}
 
-        Ok(revwalk.collect::<Result<Vec<_>, _>>()?)
+/// The parents `parent` is replaced by in the rewritten history: its rewrite,
+/// itself when it lies outside the rewritten range, or whatever a skipped
+/// commit was re-attached to.
+fn resolve_parent(
+    new_commits: &HashMap<Oid, Oid>,
+    skipped: &HashMap<Oid, Vec<Oid>>,
+    parent: Oid,
+) -> Vec<Oid> {
+    match new_commits.get(&parent) {
+        Some(&new_oid) => vec![new_oid],
+        // Parents come before their children in the walk, so a parent that
+        // was neither rewritten nor skipped lies outside the rewritten range.
+        // It is kept as it is rather than dropped, which would re-root or
+        // flatten the commit.
+        None => skipped
+            .get(&parent)
+            .cloned()
+            .unwrap_or_else(|| vec![parent]),
     }
 }
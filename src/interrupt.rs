@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::lock;
+
+/// Exit status of a process killed by SIGINT, as a shell reports it.
+const INTERRUPTED_STATUS: i32 = 130;
+
+static DEFERRING: AtomicUsize = AtomicUsize::new(0);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs the Ctrl-C handler. Outside a `Deferral` Ctrl-C exits at once,
+/// as without a handler, after removing the repository lock; inside one it
+/// only sets a flag the rewrite loop checks between commits, and a second
+/// Ctrl-C exits anyway.
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if DEFERRING.load(Ordering::SeqCst) == 0 || INTERRUPTED.swap(true, Ordering::SeqCst) {
+            lock::release_held();
+            process::exit(INTERRUPTED_STATUS);
+        }
+    })
+    .context("Failed to install the Ctrl-C handler")
+}
+
+/// Whether Ctrl-C was pressed during a `Deferral`.
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Guard during which Ctrl-C is deferred to `requested` instead of exiting.
+/// Guards nest.
+#[derive(Debug)]
+pub struct Deferral(());
+
+impl Deferral {
+    pub fn start() -> Self {
+        DEFERRING.fetch_add(1, Ordering::SeqCst);
+        Self(())
+    }
+}
+
+impl Drop for Deferral {
+    fn drop(&mut self) {
+        DEFERRING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+pub const JOURNAL_FILE: &str = "git-anon-ops.json";
+
+/// A ref-moving run of `squash`, `push`, `clean`, `anonymize` or
+/// `rotate-identity`, recorded so `undo` can put the branches back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Operation {
+    pub operation: String,
+    pub branches: Vec<JournaledBranch>,
+    /// Backup branch the run created, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+    /// Tags `--include-tags` moved to the rewritten commits.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<JournaledTag>,
+    /// The working tree had uncommitted changes the user chose to keep, so a
+    /// rollback must not check the branches out over them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub dirty: bool,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournaledBranch {
+    pub name: String,
+    /// Where the branch pointed before the run; `None` if the run created it.
+    pub before: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournaledTag {
+    pub name: String,
+    /// The tag object, or commit for a lightweight tag, it pointed to before.
+    pub before: String,
+}
+
+/// The operation log in `.git/git-anon-ops.json`, oldest entry first.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn open(git_dir: &Path) -> Self {
+        Self {
+            path: git_dir.join(JOURNAL_FILE),
+        }
+    }
+
+    pub fn entries(&self) -> Result<Vec<Operation>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", self.path.display()));
+            }
+        };
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", self.path.display()))
+    }
+
+    pub fn append(&self, operation: Operation) -> Result<()> {
+        let mut entries = self.entries()?;
+        entries.push(operation);
+        self.write(&entries)
+    }
+
+    /// Adds the tags a run moved to its entry, the most recent one.
+    pub fn record_tags(&self, tags: Vec<JournaledTag>) -> Result<()> {
+        let mut entries = self.entries()?;
+        if let Some(last) = entries.last_mut() {
+            last.tags.extend(tags);
+        }
+        self.write(&entries)
+    }
+
+    /// Removes the most recent entry once it has been undone.
+    pub fn pop(&self) -> Result<Option<Operation>> {
+        let mut entries = self.entries()?;
+        let last = entries.pop();
+        self.write(&entries)?;
+        Ok(last)
+    }
+
+    fn write(&self, entries: &[Operation]) -> Result<()> {
+        let contents = serde_json::to_string_pretty(entries)?;
+        fs::write(&self.path, contents + "\n")
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
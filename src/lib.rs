@@ -1,14 +1,42 @@
 pub mod anonymize;
 pub mod config;
 pub mod git;
+pub mod interrupt;
+pub mod journal;
+pub mod lock;
+pub mod mapping;
+pub mod metadata;
+pub mod output;
+pub mod policy;
+pub mod profile;
+pub mod report;
+pub mod scrub;
+pub mod sign;
+pub mod strip;
+pub mod trace;
+pub mod warnings;
 
 use anyhow::Result;
+use git2::Oid;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::Path;
+use std::rc::Rc;
+
+use output::Reporter;
+use profile::Profiler;
+use report::Report;
+use scrub::ForbiddenIdentities;
+use sign::Signer;
+use trace::Tracer;
+use warnings::Warnings;
 
 #[derive(Debug, Clone)]
 pub struct AnonymousIdentity {
     pub name: String,
     pub email: String,
+    /// Key `--sign` signs rewritten commits with.
+    pub signing_key: Option<String>,
 }
 
 impl Default for AnonymousIdentity {
@@ -16,26 +44,258 @@ impl Default for AnonymousIdentity {
         Self {
             name: "Anonymous".to_string(),
             email: "anonymous@example.com".to_string(),
+            signing_key: None,
         }
     }
 }
 
+/// How the backup branch for a destructive operation is named and created.
+#[derive(Debug, Clone, Default)]
+pub struct BackupOptions {
+    /// Explicit branch name instead of the timestamped default.
+    pub name: Option<String>,
+    /// What to do when the backup branch or bundle already exists.
+    pub on_conflict: BackupConflict,
+    /// Save the original history to this bundle file instead of a branch.
+    pub bundle: Option<std::path::PathBuf>,
+    /// Keep no backup at all (`--no-backup`).
+    pub disabled: bool,
+}
+
+/// What to do when the backup branch (or bundle) name is already taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackupConflict {
+    /// Refuse to run.
+    #[default]
+    Abort,
+    /// Replace the existing backup.
+    Overwrite,
+    /// Append a numeric suffix until the name is free.
+    Rename,
+}
+
+/// Message of the squashed commit when neither `--message` nor the config's
+/// `squash_message` gives one.
+pub const DEFAULT_SQUASH_MESSAGE: &str = "Initial commit";
+
+/// Options for the `squash` operation.
+#[derive(Debug, Clone, Default)]
+pub struct SquashOptions {
+    /// Message template; `{date}`, `{branch}` and `{count}` are filled in.
+    pub message: Option<String>,
+    pub backup: BackupOptions,
+    /// Write the squashed commit to this branch instead of moving the current one.
+    pub into: Option<String>,
+    /// Keep this many recent commits (anonymized) on top of the squashed base.
+    pub keep_last: Option<usize>,
+    /// Only squash the commits after this revision, on top of it.
+    pub from: Option<String>,
+    /// How the squashed (and kept) commits are dated.
+    pub timestamps: git::TimestampStrategy,
+    /// `--strip` globs, applied after `.git-anon-ignore` to the squashed tree.
+    pub strip: Vec<String>,
+}
+
+/// Options for the `anonymize` operation.
+#[derive(Debug, Clone, Default)]
+pub struct AnonymizeOptions {
+    /// Branch to rewrite instead of the current one.
+    pub branch: Option<String>,
+    /// Only anonymize the commits after this revision.
+    pub since: Option<String>,
+    /// Only anonymize up to this revision; later commits keep their identity.
+    pub until: Option<String>,
+    /// Also rewrite the local reflogs, which otherwise keep the original identities.
+    pub reflog: Option<ReflogMode>,
+    pub backup: BackupOptions,
+}
+
+/// Options for the `push` operation.
+#[derive(Debug, Clone, Default)]
+pub struct PushOptions {
+    pub force: bool,
+    /// Only print how many commits would be anonymized.
+    pub count_only: bool,
+    /// Refuse to push if the remote already has the target branch.
+    pub check_remote_empty: bool,
+    /// Only anonymize commits after this tag instead of after the remote tracking branch.
+    pub since_tag: Option<String>,
+    /// Only anonymize the commits selected by this rev-list style range.
+    pub range: Option<String>,
+    /// Write the identities that would be replaced to this file and stop.
+    pub email_map_out: Option<std::path::PathBuf>,
+    /// Write the identity each commit would receive to this file and stop.
+    pub commit_map_out: Option<std::path::PathBuf>,
+    /// Keep the anonymized history on this local branch instead of rewriting the pushed one.
+    pub into: Option<String>,
+    /// Also rewrite the local reflogs, which otherwise keep the original identities.
+    pub reflog: Option<ReflogMode>,
+    /// Move tags on rewritten commits to their anonymized versions and push them.
+    pub include_tags: bool,
+    /// Anonymize and push every local branch, sharing one commit map.
+    pub all_branches: bool,
+    /// Identities of branches in a multi-branch push whose `branch_identities`
+    /// mapping differs from the run's identity.
+    pub branch_identities: HashMap<String, AnonymousIdentity>,
+    /// Further remotes that get the same anonymized refs as the main one.
+    pub also_to: Vec<String>,
+    /// Push to all remotes at once instead of one after another.
+    pub parallel_push: bool,
+    /// Push a tip that still has a real identity, with a warning, instead of
+    /// refusing (for `--range` and `--pathspec`).
+    pub allow_real_tip: bool,
+    /// Private remote the original branches are pushed to before anything
+    /// is rewritten; the push is aborted if that fails.
+    pub backup_remote: Option<String>,
+    pub rewrite: git::RewriteOptions,
+}
+
+/// How thoroughly `clean` collects the objects left behind by the rewrite.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GcMode {
+    /// `gc --prune=now --aggressive`: repack everything, slowest.
+    #[default]
+    Aggressive,
+    /// `gc --prune=now`: normal repack.
+    Default,
+    /// `prune --expire=now`: only drop unreachable loose objects, no repack.
+    Minimal,
+}
+
+impl GcMode {
+    pub fn git_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Aggressive => &["gc", "--prune=now", "--aggressive"],
+            Self::Default => &["gc", "--prune=now"],
+            Self::Minimal => &["prune", "--expire=now"],
+        }
+    }
+}
+
+/// How `push` and `anonymize` rewrite the reflogs of the rewritten branch and HEAD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReflogMode {
+    /// Replace entry identities and strip original names/emails from messages.
+    Scrub,
+    /// Replace entry identities and drop the messages entirely.
+    Clear,
+}
+
+/// Options for the `clean` operation.
+#[derive(Debug, Clone, Default)]
+pub struct CleanOptions {
+    /// Skip the garbage collection step.
+    pub no_gc: bool,
+    pub gc_mode: GcMode,
+    /// Rewrite names in AUTHORS/LICENSE-style files to the anonymous identity.
+    pub rewrite_metadata: bool,
+    /// Drop all stash entries, which otherwise keep the original identities.
+    pub drop_stashes: bool,
+    /// Move tags on the squashed commits to the new one.
+    pub include_tags: bool,
+    /// Message template for the squashed commit, like `SquashOptions::message`.
+    pub message: Option<String>,
+    /// `--strip` globs, like `SquashOptions::strip`.
+    pub strip: Vec<String>,
+    pub backup: BackupOptions,
+}
+
 pub struct GitAnon {
     pub repo_path: std::path::PathBuf,
     pub identity: AnonymousIdentity,
+    pub skip_corrupt: bool,
+    /// UTC offset in minutes for anonymous signatures; local time when unset.
+    pub tz_offset: Option<i32>,
+    /// Run `git fsck` before rewriting and refuse to start on a corrupt repository.
+    pub verify_objects: bool,
+    /// Warn about reachable blobs larger than this many bytes.
+    pub large_blob_threshold: Option<u64>,
+    /// Write the dry-run plan to this file.
+    pub plan_out: Option<std::path::PathBuf>,
+    /// Refuse to run unless the operation matches this reviewed plan.
+    pub plan_in: Option<std::path::PathBuf>,
+    /// Collects per-phase timings for `--profile`.
+    pub profiler: Rc<Profiler>,
+    /// Warnings emitted so far, for `--abort-on-warning`.
+    pub warnings: Rc<Warnings>,
+    /// Per-commit event log for `--trace`.
+    pub tracer: Rc<Tracer>,
+    /// Prose or `--json` output.
+    pub reporter: Reporter,
+    /// List individual paths (such as stripped files) instead of counting them.
+    pub verbose: bool,
+    /// Identities no rewritten commit may carry.
+    pub forbidden: ForbiddenIdentities,
+    /// SSH private key pushes authenticate with instead of the ssh-agent.
+    pub identity_file: Option<std::path::PathBuf>,
+    /// Signs the commits whose identity the operation rewrites, for `--sign`.
+    pub signer: Option<Signer>,
+    /// Git config `key=value` overrides that apply to this run only.
+    pub git_config: Vec<(String, String)>,
+    /// Write a JSON report of the run to this file.
+    pub report_out: Option<std::path::PathBuf>,
+    /// Print a compact JSON summary with the mapping digest after the run.
+    pub summary_json: bool,
+    /// The report being collected when `report_out` or `summary_json` is set.
+    pub report: RefCell<Option<Report>>,
 }
 
 impl GitAnon {
     pub fn new<P: AsRef<Path>>(repo_path: P, identity: AnonymousIdentity) -> Result<Self> {
         let repo_path = repo_path.as_ref().to_path_buf();
-
-        if !repo_path.join(".git").exists() {
-            anyhow::bail!("Not a git repository: {}", repo_path.display());
-        }
+        // Probe with libgit2 so worktrees (where `.git` is a file) and bare
+        // repositories are accepted, with the same error as everywhere else.
+        git::GitOps::open(&repo_path)?;
 
         Ok(Self {
             repo_path,
             identity,
+            skip_corrupt: false,
+            tz_offset: None,
+            verify_objects: false,
+            large_blob_threshold: None,
+            plan_out: None,
+            plan_in: None,
+            profiler: Rc::default(),
+            warnings: Rc::default(),
+            tracer: Rc::default(),
+            reporter: Reporter::default(),
+            verbose: false,
+            forbidden: ForbiddenIdentities::default(),
+            identity_file: None,
+            signer: None,
+            git_config: Vec::new(),
+            report_out: None,
+            summary_json: false,
+            report: RefCell::default(),
         })
     }
+
+    /// Whether `--sign` is on when neither it nor `--no-sign` is given: when
+    /// git itself would sign commits in the repository.
+    pub fn signs_by_default(&self) -> Result<bool> {
+        self.open_git()?.signs_by_default()
+    }
+
+    pub(crate) fn collects_report(&self) -> bool {
+        self.report_out.is_some() || self.summary_json
+    }
+
+    pub(crate) fn record_mapping(&self, mapping: &HashMap<Oid, Oid>) {
+        if let Some(report) = self.report.borrow_mut().as_mut() {
+            report.set_mapping(mapping);
+        }
+    }
+
+    pub(crate) fn open_git(&self) -> Result<git::GitOps> {
+        git::GitOps::open(&self.repo_path)?
+            .with_skip_corrupt(self.skip_corrupt)
+            .with_tz_offset(self.tz_offset)
+            .with_profiler(Rc::clone(&self.profiler))
+            .with_warnings(Rc::clone(&self.warnings))
+            .with_tracer(Rc::clone(&self.tracer))
+            .with_signer(self.signer.clone())
+            .with_identity_file(self.identity_file.clone())
+            .with_config_overrides(&self.git_config)
+    }
 }
@@ -0,0 +1,93 @@
+use anyhow::{Context, Result, bail};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+pub const LOCK_FILE: &str = "git-anon.lock";
+
+/// Lock files this process holds, so the Ctrl-C handler, which exits without
+/// running destructors, can still remove them.
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Advisory per-repository lock held for the duration of a mutating
+/// operation, so two runs can't move the same refs at once. The lock file is
+/// removed when the guard is dropped, or by `release_held` on Ctrl-C.
+#[derive(Debug)]
+pub struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Creates `git-anon.lock` in `git_dir`, failing if another run holds it.
+    /// A lock left behind by a process that is no longer running is taken over.
+    pub fn acquire(git_dir: &Path) -> Result<Self> {
+        let path = git_dir.join(LOCK_FILE);
+        let mut file = match create(&path) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                let holder = holder.trim();
+                match holder.parse() {
+                    Ok(pid) if !is_running(pid) => {
+                        let _ = fs::remove_file(&path);
+                        create(&path)
+                    }
+                    _ => bail!(
+                        "Another git-anon operation is running on this repository{}. \
+                         If it is not, remove {}",
+                        if holder.is_empty() {
+                            String::new()
+                        } else {
+                            format!(" (pid {holder})")
+                        },
+                        path.display()
+                    ),
+                }
+            }
+            file => file,
+        }
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+        writeln!(file, "{}", std::process::id())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        held().push(path.clone());
+        Ok(Self { path })
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        held().retain(|path| *path != self.path);
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Removes every lock file this process still holds.
+pub fn release_held() {
+    for path in held().drain(..) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn held() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    HELD.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn create(path: &Path) -> std::io::Result<fs::File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Whether a process with `pid` exists. Where that can't be told, the
+/// holder is assumed to be running.
+#[cfg(unix)]
+fn is_running(pid: libc::pid_t) -> bool {
+    // Signal 0 only checks that the process exists and may be signalled;
+    // EPERM means it exists but belongs to someone else.
+    let status = unsafe { libc::kill(pid, 0) };
+    status == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
@@ -1,11 +1,34 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use colored::Colorize;
-use dialoguer::{Confirm, Input};
+use dialoguer::{Confirm, Input, Select};
+use glob::Pattern;
+use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use git_anon::{AnonymousIdentity, GitAnon, config::Config};
+use git_anon::config::{
+    Config, DEFAULT_IDENTITY, Identity, IdentitySource, ResolvedIdentity, env_identity,
+    validate_identity,
+};
+use git_anon::git::{
+    AuthorDatePolicy, CommitterDatePolicy, GitOps, PseudonymStrategy, RewriteOptions,
+    TimestampStrategy,
+};
+use git_anon::interrupt;
+use git_anon::mapping::{AuthorMap, CommitMap};
+use git_anon::output::Reporter;
+use git_anon::policy::{Policy, check_required_domain};
+use git_anon::profile::Profiler;
+use git_anon::scrub::TrailerMode;
+use git_anon::trace::Tracer;
+use git_anon::warnings::Warnings;
+use git_anon::{
+    AnonymizeOptions, AnonymousIdentity, BackupConflict, BackupOptions, CleanOptions, GcMode,
+    GitAnon, PushOptions, ReflogMode, SquashOptions,
+};
 
 #[derive(Parser)]
 #[command(
@@ -30,14 +53,203 @@ struct Cli {
 
     #[arg(short, long, help = "Show what would be done without making changes")]
     dry_run: bool,
+
+    #[arg(long, help = "Log and skip unreadable commits instead of failing")]
+    skip_corrupt: bool,
+
+    #[arg(
+        long,
+        help = "Check the object database with git fsck before rewriting anything"
+    )]
+    verify_objects: bool,
+
+    #[arg(
+        long,
+        value_name = "OFFSET",
+        value_parser = parse_tz,
+        allow_hyphen_values = true,
+        help = "Timezone offset for anonymous commits, e.g. +0200 (default: local)"
+    )]
+    tz: Option<i32>,
+
+    #[arg(
+        long,
+        overrides_with = "no_sign",
+        help = "Sign rewritten commits with the identity's signing_key (GPG or SSH, see [signing] in the config; default: git's commit.gpgsign)"
+    )]
+    sign: bool,
+
+    #[arg(
+        long,
+        overrides_with = "sign",
+        help = "Leave rewritten commits unsigned even if commit.gpgsign is set"
+    )]
+    no_sign: bool,
+
+    #[arg(
+        long = "git-config",
+        value_name = "KEY=VALUE",
+        value_parser = parse_git_config,
+        help = "Git config override for this run only (repeatable)"
+    )]
+    git_config: Vec<(String, String)>,
+
+    #[arg(long, help = "Print a timing breakdown of each phase")]
+    profile: bool,
+
+    #[arg(long, help = "Exit with an error if any warning was emitted")]
+    abort_on_warning: bool,
+
+    #[arg(long, help = "Fail if a configured identity mapping does not resolve")]
+    verify_identity: bool,
+
+    #[arg(
+        long,
+        value_name = "COMMIT",
+        help = "Use the author identity of an existing commit"
+    )]
+    identity_from_commit: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = parse_size,
+        help = "Warn about reachable blobs larger than SIZE (e.g. 512K, 10M)"
+    )]
+    warn_large_blobs: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "dry_run",
+        help = "Write the dry-run plan as JSON to FILE"
+    )]
+    plan_out: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Only run if the operation matches a plan written by --plan-out"
+    )]
+    plan_in: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Write a JSON report of what the run did to FILE"
+    )]
+    report_out: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Print a compact JSON summary with a digest of the commit mapping"
+    )]
+    summary_json: bool,
+
+    #[arg(
+        long,
+        help = "Print the result as one JSON object on stdout; prose goes to stderr"
+    )]
+    json: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-",
+        help = "Write a JSON-lines log of every rewritten commit to FILE (stderr if omitted)"
+    )]
+    trace: Option<PathBuf>,
 }
 
+// Parsed once per run, so the size of the `Push` variant does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     #[command(about = "Squash all commits into a single anonymous commit")]
     Squash {
         #[arg(short, long, help = "Commit message for the squashed commit")]
         message: Option<String>,
+
+        #[arg(long, help = "Name for the backup branch")]
+        backup_name: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with = "backup_name",
+            help = "Save the original history to a bundle file instead of a backup branch"
+        )]
+        backup_bundle: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = BackupConflict::Abort,
+            help = "What to do if the backup branch or bundle already exists"
+        )]
+        on_conflict: BackupConflict,
+
+        #[arg(
+            short,
+            long,
+            conflicts_with = "on_conflict",
+            help = "Overwrite an existing backup branch or bundle (same as --on-conflict overwrite)"
+        )]
+        force: bool,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["backup_name", "backup_bundle", "on_conflict", "force"],
+            help = "Don't keep a backup of the original history"
+        )]
+        no_backup: bool,
+
+        #[arg(
+            long,
+            value_name = "BRANCH",
+            help = "Write the squashed commit to a new branch, leaving the current one untouched"
+        )]
+        into: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Keep the last N commits (anonymized) on top of the squashed base"
+        )]
+        keep_last: Option<u64>,
+
+        #[arg(
+            long,
+            value_name = "REV",
+            conflicts_with = "keep_last",
+            help = "Only squash the commits after REV, on top of it (REV and its history are kept)"
+        )]
+        from: Option<String>,
+
+        #[arg(
+            long,
+            help = "Date the squashed commit like the original tip instead of now"
+        )]
+        preserve_dates: bool,
+
+        #[arg(
+            long,
+            value_name = "STRATEGY",
+            conflicts_with = "preserve_dates",
+            help = "Date the squashed commit: preserve, now, jitter:<secs> or fixed:<RFC 3339 date>"
+        )]
+        timestamp_strategy: Option<TimestampStrategy>,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            conflicts_with = "keep_last",
+            help = "Leave paths matching GLOB out of the squashed tree, like a .git-anon-ignore line (repeatable)"
+        )]
+        strip: Vec<String>,
     },
 
     #[command(about = "Push to remote with anonymized commits")]
@@ -45,15 +257,404 @@ enum Commands {
         #[arg(help = "Remote name to push to")]
         remote: String,
 
-        #[arg(help = "Branch to push")]
-        branch: Option<String>,
+        #[arg(help = "Branches to push (default: the current branch)")]
+        branches: Vec<String>,
 
         #[arg(short, long, help = "Force push")]
         force: bool,
+
+        #[arg(
+            long,
+            value_name = "TAG",
+            help = "Only anonymize commits after this tag"
+        )]
+        since_tag: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "RANGE",
+            allow_hyphen_values = true,
+            help = "Only anonymize commits selected by a rev-list range (e.g. 'A..B', '^A B')"
+        )]
+        range: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "BRANCH",
+            help = "Keep the anonymized history on a new local branch instead of rewriting this one"
+        )]
+        into: Option<String>,
+
+        #[arg(long, help = "Keep original authors and anonymize only the committer")]
+        keep_authors: bool,
+
+        #[arg(
+            long,
+            help = "Print how many commits would be anonymized and exit without rewriting"
+        )]
+        count_only: bool,
+
+        #[arg(long, help = "Abort if the remote already has the target branch")]
+        check_remote_empty: bool,
+
+        #[arg(
+            long,
+            help = "Push a branch or tag whose tip keeps a real identity (with --range or --pathspec), with a warning"
+        )]
+        allow_real_tip: bool,
+
+        #[arg(
+            long,
+            help = "Move author/committer dates that lie in the future back to now"
+        )]
+        clamp_future_dates: bool,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Write the identities that would be replaced to FILE for review, then stop"
+        )]
+        email_map_out: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Replace identities as mapped in FILE (as written by --email-map-out)"
+        )]
+        author_map: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Write the identity each commit would receive to FILE for editing, then stop"
+        )]
+        commit_map_out: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            help = "Give each commit the identity listed for its OID in FILE (as written by --commit-map-out)"
+        )]
+        commit_map: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            value_parser = parse_pathspec,
+            help = "Only anonymize commits that touch paths matching this glob"
+        )]
+        pathspec: Option<Pattern>,
+
+        #[arg(
+            long,
+            value_enum,
+            value_name = "MODE",
+            help = "Also rewrite local reflogs, which keep original identities"
+        )]
+        reflog: Option<ReflogMode>,
+
+        #[arg(
+            long,
+            visible_alias = "tags",
+            help = "Move tags on anonymized commits to the rewritten commits and push them too"
+        )]
+        include_tags: bool,
+
+        #[arg(
+            long,
+            visible_alias = "all",
+            conflicts_with_all = [
+                "branches",
+                "into",
+                "count_only",
+                "check_remote_empty",
+                "since_tag",
+                "range",
+                "email_map_out",
+                "commit_map_out",
+            ],
+            help = "Anonymize and push every local branch, rewriting shared commits once"
+        )]
+        all_branches: bool,
+
+        #[arg(
+            long,
+            value_name = "REMOTE",
+            help = "Also push the anonymized refs to REMOTE (repeatable); the rewrite still starts after the first remote's tracking branch"
+        )]
+        also_to: Vec<String>,
+
+        #[arg(
+            long,
+            requires = "also_to",
+            help = "Push to all remotes concurrently instead of one after another"
+        )]
+        parallel_push: bool,
+
+        #[arg(
+            long,
+            help = "Push the original history to the config's backup_remote first, and abort unless that succeeds"
+        )]
+        force_backup_push_first: bool,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "SSH private key to push with instead of the ssh-agent's keys"
+        )]
+        identity_file: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_enum,
+            value_name = "TRAILERS",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "replace",
+            help = "Replace emails in messages and anonymize (or remove) trailers like Signed-off-by"
+        )]
+        scrub_messages: Option<TrailerMode>,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = PseudonymStrategy::Single,
+            help = "Replace everyone with one identity, or each contributor with a stable contributor-<hash> alias"
+        )]
+        pseudonyms: PseudonymStrategy,
+
+        #[arg(
+            long,
+            help = "Resolve identities through .mailmap first, so one person's several emails get one replacement"
+        )]
+        use_mailmap: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = AuthorDatePolicy::Now,
+            help = "Date for anonymized authors: the rewrite time or the original date"
+        )]
+        author_date: AuthorDatePolicy,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = CommitterDatePolicy::Now,
+            help = "Date for anonymized committers: the rewrite time or the original date"
+        )]
+        committer_date: CommitterDatePolicy,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["author_date", "committer_date"],
+            help = "Keep original author and committer dates (same as --author-date keep --committer-date keep)"
+        )]
+        preserve_dates: bool,
+
+        #[arg(
+            long,
+            value_name = "STRATEGY",
+            conflicts_with_all = ["author_date", "committer_date", "preserve_dates"],
+            help = "Date anonymized commits: preserve, now, jitter:<secs> (random offset, order kept) or fixed:<RFC 3339 date>"
+        )]
+        timestamp_strategy: Option<TimestampStrategy>,
+    },
+
+    #[command(about = "Anonymize a branch (or a range of its commits) in place, without pushing")]
+    Anonymize {
+        #[arg(help = "Branch to anonymize (default: the current branch)")]
+        branch: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "REV",
+            help = "Only anonymize the commits after REV"
+        )]
+        since: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "REV",
+            help = "Only anonymize up to REV (an ancestor of HEAD); later commits keep their identity"
+        )]
+        until: Option<String>,
+
+        #[arg(
+            long,
+            value_enum,
+            value_name = "MODE",
+            help = "Also rewrite local reflogs, which keep original identities"
+        )]
+        reflog: Option<ReflogMode>,
+
+        #[arg(long, help = "Name for the backup branch")]
+        backup_name: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with = "backup_name",
+            help = "Save the original history to a bundle file instead of a backup branch"
+        )]
+        backup_bundle: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = BackupConflict::Abort,
+            help = "What to do if the backup branch or bundle already exists"
+        )]
+        on_conflict: BackupConflict,
+
+        #[arg(
+            short,
+            long,
+            conflicts_with = "on_conflict",
+            help = "Overwrite an existing backup branch or bundle (same as --on-conflict overwrite)"
+        )]
+        force: bool,
     },
 
     #[command(about = "Fully clean and anonymize repository")]
-    Clean,
+    Clean {
+        #[arg(long, help = "Skip the garbage collection step")]
+        no_gc: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = GcMode::Aggressive,
+            conflicts_with = "no_gc",
+            help = "How thoroughly to garbage collect after the rewrite"
+        )]
+        gc_mode: GcMode,
+
+        #[arg(long, help = "Drop all stash entries (stashed work is lost)")]
+        drop_stashes: bool,
+
+        #[arg(long, help = "Replace names in AUTHORS/LICENSE-style files")]
+        rewrite_metadata: bool,
+
+        #[arg(long, help = "Move tags on the squashed commits to the new commit")]
+        include_tags: bool,
+
+        #[arg(long, help = "Name for the backup branch")]
+        backup_name: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with = "backup_name",
+            help = "Save the original history to a bundle file instead of a backup branch"
+        )]
+        backup_bundle: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = BackupConflict::Abort,
+            help = "What to do if the backup branch or bundle already exists"
+        )]
+        on_conflict: BackupConflict,
+
+        #[arg(
+            short,
+            long,
+            conflicts_with = "on_conflict",
+            help = "Overwrite an existing backup branch or bundle (same as --on-conflict overwrite)"
+        )]
+        force: bool,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["backup_name", "backup_bundle", "on_conflict", "force"],
+            help = "Don't keep a backup of the original history"
+        )]
+        no_backup: bool,
+
+        #[arg(
+            long,
+            value_name = "GLOB",
+            help = "Leave paths matching GLOB out of the squashed tree, like a .git-anon-ignore line (repeatable)"
+        )]
+        strip: Vec<String>,
+    },
+
+    #[command(about = "Reset the current branch to a backup branch")]
+    Restore {
+        #[arg(
+            long,
+            help = "Backup branch to restore from (prompts when several exist)"
+        )]
+        branch: Option<String>,
+    },
+
+    #[command(about = "Undo the most recent squash, push, clean, anonymize or rotate-identity")]
+    Undo,
+
+    #[command(about = "Check that no real identity remains in the history")]
+    Verify {
+        #[arg(
+            long,
+            help = "Scan every ref instead of only HEAD (backup branches excepted)"
+        )]
+        all_refs: bool,
+
+        #[arg(long, help = "Also scan the taggers and messages of annotated tags")]
+        tags: bool,
+    },
+
+    #[command(about = "Show whether the current branch is anonymized and ready to push")]
+    Status,
+
+    #[command(about = "List backup branches, newest first")]
+    Backups {
+        #[arg(long, help = "Delete all but the newest --keep backups")]
+        prune: bool,
+
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 3,
+            requires = "prune",
+            help = "Number of backups --prune keeps"
+        )]
+        keep: usize,
+    },
+
+    #[command(about = "Replace an old anonymous identity with the current one across history")]
+    RotateIdentity {
+        #[arg(help = "Email of the identity to replace")]
+        old_email: String,
+
+        #[arg(long, help = "Name for the backup branch")]
+        backup_name: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "FILE",
+            conflicts_with = "backup_name",
+            help = "Save the original history to a bundle file instead of a backup branch"
+        )]
+        backup_bundle: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = BackupConflict::Abort,
+            help = "What to do if the backup branch or bundle already exists"
+        )]
+        on_conflict: BackupConflict,
+
+        #[arg(
+            short,
+            long,
+            conflicts_with = "on_conflict",
+            help = "Overwrite an existing backup branch or bundle (same as --on-conflict overwrite)"
+        )]
+        force: bool,
+    },
 
     #[command(about = "Manage configuration")]
     Config {
@@ -74,6 +675,32 @@ enum ConfigAction {
 
         #[arg(help = "Email for anonymous identity")]
         email: Option<String>,
+
+        #[arg(
+            long = "as",
+            value_name = "IDENTITY",
+            help = "Define or update the named identity IDENTITY instead of the default"
+        )]
+        identity: Option<String>,
+
+        #[arg(
+            long,
+            help = "Store the identity in the OS keyring instead of the config file"
+        )]
+        keyring: bool,
+
+        #[arg(
+            long,
+            value_name = "KEY",
+            help = "GPG key ID or SSH key path that --sign uses for this identity"
+        )]
+        signing_key: Option<String>,
+    },
+
+    #[command(about = "List configured identities and the mappings that use them")]
+    ListIdentities {
+        #[arg(long, help = "Print machine-readable JSON")]
+        json: bool,
     },
 
     #[command(about = "Add or update remote configuration")]
@@ -86,63 +713,648 @@ enum ConfigAction {
 
         #[arg(help = "Identity to use for this remote")]
         identity: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "SSH private key to push to this remote with"
+        )]
+        identity_file: Option<String>,
+    },
+
+    #[command(
+        about = "Remove a remote configuration",
+        visible_alias = "unset-remote"
+    )]
+    RemoveRemote {
+        #[arg(help = "Remote alias to remove")]
+        alias: String,
+    },
+
+    #[command(about = "Remove a named identity")]
+    RemoveIdentity {
+        #[arg(help = "Identity to remove")]
+        name: String,
+
+        #[arg(
+            long,
+            help = "Remove it even if remotes or branches use it; they fall back to the default identity"
+        )]
+        force: bool,
+    },
+
+    #[command(about = "Write the active configuration to a file for sharing")]
+    Export {
+        #[arg(help = "File to write (prints to stdout when omitted)")]
+        path: Option<PathBuf>,
+
+        #[arg(
+            long,
+            requires = "path",
+            help = "Overwrite the file if it already exists"
+        )]
+        force: bool,
+    },
+
+    #[command(about = "Apply a shared configuration file")]
+    Import {
+        #[arg(help = "Config file to import")]
+        path: PathBuf,
+
+        #[arg(
+            long,
+            conflicts_with = "replace",
+            help = "Merge into the active configuration (the default)"
+        )]
+        merge: bool,
+
+        #[arg(
+            long,
+            help = "Replace the active configuration instead of merging into it"
+        )]
+        replace: bool,
+    },
+
+    #[command(about = "Check a configuration file for errors without applying it")]
+    Validate {
+        #[arg(help = "Config file to check (defaults to the active configuration)")]
+        path: Option<PathBuf>,
     },
 }
 
+/// Whether `identity` has a signing key, without revealing the key.
+fn signing_key_state(identity: &AnonymousIdentity) -> &'static str {
+    match identity.signing_key {
+        Some(_) => "set",
+        None => "unset",
+    }
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    interrupt::install()?;
+    let warnings = Rc::new(Warnings::default());
+    let reporter = match cli.json {
+        true => Reporter::Json,
+        false => Reporter::Human,
+    };
 
     let repo_path = cli
         .repo
         .unwrap_or_else(|| env::current_dir().expect("Failed to get current directory"));
 
     match cli.command {
-        Commands::Config { action } => handle_config(action),
+        Commands::Config { action } => {
+            handle_config(action, cli.yes, cli.dry_run, reporter, &warnings)?;
+            if cli.abort_on_warning {
+                warnings.check()?;
+            }
+            Ok(())
+        }
         _ => {
-            let config = Config::load().context("Failed to load configuration")?;
-            let identity = get_identity_for_command(&config, &cli.command)?;
-            let git_anon = GitAnon::new(repo_path, identity)?;
+            let mut config = with_load_warnings(
+                Config::load().context("Failed to load configuration")?,
+                &warnings,
+            );
+            if let Some(url) = config.policy_url.clone() {
+                let loaded = Policy::load(&url)?;
+                if let Some(reason) = loaded.cache_fallback {
+                    warnings.warn(reason);
+                }
+                loaded.policy.apply(&mut config)?;
+            }
+            let resolved = match &cli.identity_from_commit {
+                Some(rev) => {
+                    let (oid, identity) = GitOps::open(&repo_path)?.identity_from_commit(rev)?;
+                    ResolvedIdentity {
+                        identity,
+                        source: IdentitySource::Commit(oid.to_string()[..8].to_string()),
+                    }
+                }
+                None => get_identity_for_command(&config, &cli.command, &repo_path)?,
+            };
+            let resolved = match resolved.fallback_reason() {
+                Some(reason) if cli.verify_identity => {
+                    anyhow::bail!("Identity verification failed: {reason}");
+                }
+                Some(_) if !cli.yes && io::stdin().is_terminal() => {
+                    pick_identity(&mut config, resolved, cli.dry_run, reporter)?
+                }
+                Some(reason) => {
+                    warnings.warn(format!("{reason}; using the default identity"));
+                    resolved
+                }
+                None => resolved,
+            };
+            let identity = resolved.identity;
+            validate_identity(&identity.name, &identity.email).with_context(|| {
+                format!(
+                    "Invalid identity ({}); fix it with `git-anon config set-identity`",
+                    resolved.source
+                )
+            })?;
+            check_required_domain(&config, &identity)?;
+            // `push --count-only` prints nothing but the count, for scripts.
+            if !matches!(
+                cli.command,
+                Commands::Push {
+                    count_only: true,
+                    ..
+                }
+            ) {
+                reporter.say(format_args!(
+                    "Using identity: {} <{}> ({})",
+                    identity.name.green(),
+                    identity.email.green(),
+                    resolved.source
+                ));
+            }
+            let mut git_anon = GitAnon::new(repo_path, identity)?;
+            git_anon.skip_corrupt = cli.skip_corrupt;
+            git_anon.reporter = reporter;
+            git_anon.verbose = cli.verbose;
+            git_anon.verify_objects = cli.verify_objects;
+            git_anon.tz_offset = cli.tz;
+            git_anon.git_config = cli.git_config;
+            if cli.sign {
+                git_anon.signer = Some(config.signer(&git_anon.identity)?);
+            } else if !cli.no_sign && git_anon.signs_by_default()? {
+                match config.signer(&git_anon.identity) {
+                    Ok(signer) => git_anon.signer = Some(signer),
+                    Err(_) => warnings.warn(format!(
+                        "commit.gpgsign is set but {} has no signing_key; rewritten commits are left unsigned (pass --no-sign to silence this)",
+                        git_anon.identity.email
+                    )),
+                }
+            }
+            git_anon.forbidden = config.forbidden_identities()?;
+            git_anon.large_blob_threshold = cli.warn_large_blobs;
+            git_anon.plan_out = cli.plan_out;
+            git_anon.plan_in = cli.plan_in;
+            git_anon.profiler = Rc::new(Profiler::new(cli.profile || cli.report_out.is_some()));
+            git_anon.report_out = cli.report_out.clone();
+            git_anon.summary_json = cli.summary_json;
+            if let Some(path) = &cli.trace {
+                git_anon.tracer = Rc::new(Tracer::open(path)?);
+            }
+            git_anon.warnings = Rc::clone(&warnings);
+            let branch_identities = match &cli.identity_from_commit {
+                Some(_) => HashMap::new(),
+                None => push_branch_identities(
+                    &config,
+                    &cli.command,
+                    &git_anon.repo_path,
+                    &git_anon.identity,
+                    cli.verify_identity,
+                    &warnings,
+                )?,
+            };
+            if git_anon.signer.is_some()
+                && branch_identities
+                    .values()
+                    .any(|other| other.signing_key != git_anon.identity.signing_key)
+            {
+                anyhow::bail!(
+                    "The branches map to identities with different signing keys, which one signed push can't use; push them separately"
+                );
+            }
 
-            match cli.command {
-                Commands::Squash { message } => git_anon.squash(message, cli.yes, cli.dry_run),
+            let result = match cli.command {
+                Commands::Squash {
+                    message,
+                    backup_name,
+                    backup_bundle,
+                    on_conflict,
+                    force,
+                    no_backup,
+                    into,
+                    keep_last,
+                    from,
+                    preserve_dates,
+                    timestamp_strategy,
+                    strip,
+                } => git_anon.squash(
+                    cli.yes,
+                    cli.dry_run,
+                    &SquashOptions {
+                        message: message.or_else(|| config.squash_message.clone()),
+                        backup: BackupOptions {
+                            disabled: backup_disabled(
+                                no_backup,
+                                config.no_backup,
+                                &backup_name,
+                                &backup_bundle,
+                            ),
+                            name: backup_name,
+                            on_conflict: backup_conflict(force, on_conflict),
+                            bundle: backup_bundle,
+                        },
+                        into,
+                        keep_last: keep_last.map(|n| n as usize),
+                        from,
+                        timestamps: match preserve_dates {
+                            true => TimestampStrategy::Preserve,
+                            false => timestamp_strategy.unwrap_or_default(),
+                        },
+                        strip,
+                    },
+                ),
                 Commands::Push {
                     remote,
+                    branches,
+                    force,
+                    since_tag,
+                    range,
+                    into,
+                    keep_authors,
+                    check_remote_empty,
+                    allow_real_tip,
+                    count_only,
+                    clamp_future_dates,
+                    email_map_out,
+                    author_map,
+                    commit_map_out,
+                    commit_map,
+                    pathspec,
+                    reflog,
+                    include_tags,
+                    all_branches,
+                    also_to,
+                    parallel_push,
+                    force_backup_push_first,
+                    scrub_messages,
+                    pseudonyms,
+                    use_mailmap,
+                    author_date,
+                    committer_date,
+                    preserve_dates,
+                    timestamp_strategy,
+                    identity_file,
+                } => {
+                    git_anon.identity_file = identity_file.or_else(|| {
+                        config
+                            .remotes
+                            .get(&remote)
+                            .and_then(|remote| remote.identity_file.as_deref())
+                            .map(expand_home)
+                    });
+                    git_anon.push(
+                        &remote,
+                        branches,
+                        cli.yes,
+                        cli.dry_run,
+                        &PushOptions {
+                            force,
+                            count_only,
+                            check_remote_empty,
+                            since_tag,
+                            range,
+                            email_map_out,
+                            commit_map_out,
+                            into,
+                            reflog,
+                            include_tags,
+                            all_branches,
+                            branch_identities,
+                            also_to,
+                            parallel_push,
+                            allow_real_tip,
+                            backup_remote: match force_backup_push_first {
+                                true => Some(config.backup_remote.clone().context(
+                                    "--force-backup-push-first needs backup_remote set in the config",
+                                )?),
+                                false => None,
+                            },
+                            rewrite: RewriteOptions {
+                                keep_authors,
+                                pathspec,
+                                clamp_future_dates,
+                                author_date: match preserve_dates {
+                                    true => AuthorDatePolicy::Keep,
+                                    false => author_date,
+                                },
+                                committer_date: match preserve_dates {
+                                    true => CommitterDatePolicy::Keep,
+                                    false => committer_date,
+                                },
+                                timestamps: timestamp_strategy,
+                                message_scrubs: config.scrub_patterns()?,
+                                pseudonyms,
+                                use_mailmap,
+                                pseudonym_salt: config
+                                    .pseudonym_salt
+                                    .clone()
+                                    .unwrap_or_else(random_salt),
+                                identity_scrub: scrub_messages
+                                    .map(|trailers| config.identity_scrubber(trailers))
+                                    .transpose()?,
+                                author_map: match &author_map {
+                                    Some(path) => AuthorMap::read(path)?.replacements()?,
+                                    None => HashMap::new(),
+                                },
+                                commit_map: match &commit_map {
+                                    Some(path) => CommitMap::read(path)?.identities()?,
+                                    None => HashMap::new(),
+                                },
+                                ..RewriteOptions::default()
+                            },
+                        },
+                    )
+                }
+                Commands::Anonymize {
                     branch,
+                    since,
+                    until,
+                    reflog,
+                    backup_name,
+                    backup_bundle,
+                    on_conflict,
+                    force,
+                } => git_anon.anonymize(
+                    cli.yes,
+                    cli.dry_run,
+                    &AnonymizeOptions {
+                        branch,
+                        since,
+                        until,
+                        reflog,
+                        backup: BackupOptions {
+                            name: backup_name,
+                            on_conflict: backup_conflict(force, on_conflict),
+                            bundle: backup_bundle,
+                            disabled: false,
+                        },
+                    },
+                ),
+                Commands::Clean {
+                    no_gc,
+                    gc_mode,
+                    drop_stashes,
+                    rewrite_metadata,
+                    include_tags,
+                    backup_name,
+                    backup_bundle,
+                    on_conflict,
+                    force,
+                    no_backup,
+                    strip,
+                } => git_anon.clean(
+                    cli.yes,
+                    cli.dry_run,
+                    &CleanOptions {
+                        no_gc,
+                        gc_mode,
+                        drop_stashes,
+                        rewrite_metadata,
+                        include_tags,
+                        message: config.squash_message.clone(),
+                        strip,
+                        backup: BackupOptions {
+                            disabled: backup_disabled(
+                                no_backup,
+                                config.no_backup,
+                                &backup_name,
+                                &backup_bundle,
+                            ),
+                            name: backup_name,
+                            on_conflict: backup_conflict(force, on_conflict),
+                            bundle: backup_bundle,
+                        },
+                    },
+                ),
+                Commands::RotateIdentity {
+                    old_email,
+                    backup_name,
+                    backup_bundle,
+                    on_conflict,
                     force,
-                } => git_anon.push(&remote, branch, force, cli.dry_run),
-                Commands::Clean => git_anon.clean(cli.yes, cli.dry_run),
+                } => git_anon.rotate_identity(
+                    &old_email,
+                    cli.yes,
+                    cli.dry_run,
+                    &BackupOptions {
+                        name: backup_name,
+                        on_conflict: backup_conflict(force, on_conflict),
+                        bundle: backup_bundle,
+                        disabled: false,
+                    },
+                ),
+                Commands::Restore { branch } => {
+                    git_anon.restore(cli.yes, cli.dry_run, branch.as_deref())
+                }
+                Commands::Undo => git_anon.undo(cli.yes, cli.dry_run),
+                Commands::Verify { all_refs, tags } => {
+                    let mut known: Vec<_> = config
+                        .identity_names()
+                        .into_iter()
+                        .filter_map(|name| config.get_identity(name))
+                        .collect();
+                    known.push(git_anon.identity.clone());
+                    let scrubber = config.identity_scrubber(TrailerMode::default())?;
+                    git_anon.verify(all_refs, tags, &known, &scrubber)
+                }
+                Commands::Status => git_anon.status(),
+                Commands::Backups { prune, keep } => {
+                    git_anon.backups(cli.yes, cli.dry_run, prune, keep)
+                }
                 Commands::Config { .. } => unreachable!(),
+            };
+            if cli.profile {
+                git_anon.profiler.report(reporter);
+            }
+            result?;
+
+            if let Some(path) = &cli.report_out {
+                let mut report = git_anon.report.take().unwrap_or_default();
+                report.set_timings(&git_anon.profiler.phases());
+                report.warnings = warnings.messages();
+                report.write(path)?;
+                reporter.say(format_args!(
+                    "Report written to {}",
+                    path.display().to_string().cyan()
+                ));
+            }
+            if cli.summary_json {
+                let report = git_anon.report.take().unwrap_or_default();
+                // Under --json, stdout is reserved for the outcome object.
+                reporter.say(serde_json::to_string(&report.summary())?);
+            }
+
+            if cli.abort_on_warning || config.abort_on_warning {
+                warnings.check()?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Expands a leading `~/` in a path read from the config file, which no
+/// shell has seen.
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn get_identity_for_command(
+    config: &Config,
+    command: &Commands,
+    repo_path: &Path,
+) -> Result<ResolvedIdentity> {
+    let remote = match command {
+        Commands::Push { remote, .. } => Some(remote.as_str()),
+        _ => None,
+    };
+    if let Some(resolved) = env_identity(remote)? {
+        return Ok(resolved);
+    }
+
+    if let Commands::Push { remote, .. } = command
+        && config.remotes.contains_key(remote)
+    {
+        return Ok(config.resolve_remote_identity(remote));
+    }
+
+    if config.branch_identities.is_empty() {
+        return Ok(ResolvedIdentity {
+            identity: config.default_identity(),
+            source: IdentitySource::Default,
+        });
+    }
+
+    // Further branches of a multi-branch push get their own identities from
+    // `push_branch_identities`; the first one names the run's identity.
+    Ok(match command_branches(command, repo_path)?.first() {
+        Some(branch) => config.resolve_branch_identity(branch),
+        None => ResolvedIdentity {
+            identity: config.default_identity(),
+            source: IdentitySource::Default,
+        },
+    })
+}
+
+/// The branches `command` rewrites, in the order their identities are
+/// looked up.
+fn command_branches(command: &Commands, repo_path: &Path) -> Result<Vec<String>> {
+    Ok(match command {
+        Commands::Push {
+            all_branches: true, ..
+        } => GitOps::open(repo_path)?.local_branches()?,
+        Commands::Push { branches, .. } if !branches.is_empty() => branches.clone(),
+        Commands::Anonymize {
+            branch: Some(branch),
+            ..
+        } => vec![branch.clone()],
+        _ => vec![GitOps::open(repo_path)?.current_branch()?],
+    })
+}
+
+/// The identities `branch_identities` gives the branches of a multi-branch
+/// push after the first, where they differ from `identity`. Empty when the
+/// environment or a remote mapping sets one identity for the whole push.
+fn push_branch_identities(
+    config: &Config,
+    command: &Commands,
+    repo_path: &Path,
+    identity: &AnonymousIdentity,
+    verify_identity: bool,
+    warnings: &Warnings,
+) -> Result<HashMap<String, AnonymousIdentity>> {
+    let mut identities = HashMap::new();
+    let Commands::Push { remote, .. } = command else {
+        return Ok(identities);
+    };
+    if config.branch_identities.is_empty()
+        || config.remotes.contains_key(remote)
+        || env_identity(Some(remote))?.is_some()
+    {
+        return Ok(identities);
+    }
+    for branch in command_branches(command, repo_path)?.into_iter().skip(1) {
+        let resolved = config.resolve_branch_identity(&branch);
+        match resolved.fallback_reason() {
+            Some(reason) if verify_identity => {
+                anyhow::bail!("Identity verification failed: {reason}");
             }
+            Some(reason) => warnings.warn(format!("{reason}; using the default identity")),
+            None => {}
+        }
+        let other = resolved.identity;
+        if (&other.name, &other.email) == (&identity.name, &identity.email) {
+            continue;
         }
+        validate_identity(&other.name, &other.email).with_context(|| {
+            format!(
+                "Invalid identity for {branch} ({}); fix it with `git-anon config set-identity`",
+                resolved.source
+            )
+        })?;
+        check_required_domain(config, &other)?;
+        identities.insert(branch, other);
     }
+    Ok(identities)
 }
 
-fn get_identity_for_command(config: &Config, command: &Commands) -> Result<AnonymousIdentity> {
-    match command {
-        Commands::Push { remote, .. } => Ok(config.get_remote_identity(remote)),
-        _ => Ok(AnonymousIdentity {
-            name: config.anonymous_identity.name.clone(),
-            email: config.anonymous_identity.email.clone(),
-        }),
+/// Reports problems noticed while loading `config` as warnings.
+fn with_load_warnings(config: Config, warnings: &Warnings) -> Config {
+    if let Some(error) = &config.keyring_error {
+        warnings.warn(error.as_str());
     }
+    config
 }
 
-fn handle_config(action: ConfigAction) -> Result<()> {
+fn handle_config(
+    action: ConfigAction,
+    no_confirm: bool,
+    dry_run: bool,
+    reporter: Reporter,
+    warnings: &Warnings,
+) -> Result<()> {
     match action {
         ConfigAction::Show => {
-            let config = Config::load()?;
+            let config = with_load_warnings(Config::load()?, warnings);
             let config_path = Config::config_path()?;
+            if reporter.is_json() {
+                println!(
+                    "{}",
+                    serde_json::to_string(&serde_json::json!({
+                        "path": config_path,
+                        "config": config,
+                    }))?
+                );
+                return Ok(());
+            }
 
             println!(
                 "Configuration file: {}",
                 config_path.display().to_string().cyan()
             );
             println!();
-            println!("Anonymous identity:");
+            if config.anonymous_identity.keyring {
+                println!("Anonymous identity (keyring-backed):");
+            } else {
+                println!("Anonymous identity:");
+            }
             println!("  Name:  {}", config.anonymous_identity.name.green());
             println!("  Email: {}", config.anonymous_identity.email.green());
             println!();
 
+            if !config.identities.is_empty() {
+                println!("Named identities:");
+                for name in config.identity_names().into_iter().skip(1) {
+                    let identity = &config.identities[name];
+                    println!(
+                        "  {}: {} <{}>{}",
+                        name.yellow(),
+                        identity.name.green(),
+                        identity.email.green(),
+                        if identity.keyring { " (keyring)" } else { "" }
+                    );
+                }
+                println!();
+            }
+
             if !config.remotes.is_empty() {
                 println!("Remotes:");
                 for (alias, remote_config) in &config.remotes {
@@ -156,56 +1368,165 @@ fn handle_config(action: ConfigAction) -> Result<()> {
             }
         }
 
-        ConfigAction::SetIdentity { name, email } => {
-            let mut config = Config::load()?;
+        ConfigAction::SetIdentity {
+            name,
+            email,
+            identity,
+            keyring,
+            signing_key,
+        } => {
+            let mut config = with_load_warnings(load_config(dry_run)?, warnings);
+            let key = identity.unwrap_or_else(|| DEFAULT_IDENTITY.to_string());
+            // A new named identity starts from the default's values.
+            let current = match config.identity_mut(&key) {
+                Some(current) => current.clone(),
+                None => Identity::default(),
+            };
+            let label = match key.as_str() {
+                DEFAULT_IDENTITY => "anonymous identity".to_string(),
+                _ => format!("identity '{key}'"),
+            };
 
             let is_interactive = name.is_none() || email.is_none();
 
             let name = name.unwrap_or_else(|| {
                 Input::new()
                     .with_prompt("Anonymous name")
-                    .default(config.anonymous_identity.name.clone())
+                    .default(current.name.clone())
                     .interact_text()
-                    .unwrap_or_else(|_| config.anonymous_identity.name.clone())
+                    .unwrap_or_else(|_| current.name.clone())
             });
 
             let email = email.unwrap_or_else(|| {
                 Input::new()
                     .with_prompt("Anonymous email")
-                    .default(config.anonymous_identity.email.clone())
+                    .default(current.email.clone())
                     .interact_text()
-                    .unwrap_or_else(|_| config.anonymous_identity.email.clone())
+                    .unwrap_or_else(|_| current.email.clone())
             });
 
             validate_identity(&name, &email)?;
 
-            show_identity_changes(&config.anonymous_identity, &name, &email);
+            show_identity_changes(&current, &name, &email);
+
+            if dry_run {
+                println!("{}", "[DRY RUN] Configuration not written".blue().bold());
+                return Ok(());
+            }
 
-            if is_interactive && !confirm_changes("Update anonymous identity?")? {
+            if is_interactive && !confirm_changes(&format!("Update {label}?"))? {
                 println!("Cancelled.");
                 return Ok(());
             }
 
-            config.anonymous_identity.name = name;
-            config.anonymous_identity.email = email;
+            let in_keyring = match config.identity_mut(&key) {
+                Some(stored) => {
+                    stored.name = name;
+                    stored.email = email;
+                    if signing_key.is_some() {
+                        stored.signing_key = signing_key;
+                    }
+                    stored.keyring
+                }
+                None => {
+                    let identity = Identity {
+                        name,
+                        email,
+                        keyring: false,
+                        signing_key,
+                    };
+                    config.identities.insert(key.clone(), identity);
+                    false
+                }
+            };
+            if keyring || in_keyring {
+                config.store_identity_in_keyring(&key)?;
+            }
             config.save()?;
 
-            println!("{} Updated anonymous identity", "✓".green());
+            if keyring || in_keyring {
+                println!("{} Updated {label} in the keyring", "✓".green());
+            } else {
+                println!("{} Updated {label}", "✓".green());
+            }
+        }
+
+        ConfigAction::ListIdentities { json } => {
+            let json = json || reporter.is_json();
+            let config = with_load_warnings(Config::load()?, warnings);
+            let identities: Vec<_> = config
+                .identity_names()
+                .into_iter()
+                .filter_map(|name| {
+                    let identity = config.get_identity(name)?;
+                    Some((name, identity, config.identity_references(name)))
+                })
+                .collect();
+
+            if json {
+                let entries: Vec<_> = identities
+                    .iter()
+                    .map(|(name, identity, references)| {
+                        serde_json::json!({
+                            "name": name,
+                            "identity": {
+                                "name": identity.name,
+                                "email": identity.email,
+                            },
+                            "signing_key": signing_key_state(identity),
+                            "used_by": references,
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+                return Ok(());
+            }
+
+            for (name, identity, references) in &identities {
+                println!(
+                    "{}: {} <{}>",
+                    name.yellow(),
+                    identity.name.green(),
+                    identity.email.green()
+                );
+                println!("  signing key: {}", signing_key_state(identity));
+                if references.is_empty() {
+                    println!("  used by: {}", "default only".dimmed());
+                } else {
+                    println!("  used by: {}", references.join(", ").blue());
+                }
+            }
         }
 
         ConfigAction::AddRemote {
             alias,
             remote_name,
             identity,
+            identity_file,
         } => {
-            let mut config = Config::load()?;
-            config.remotes.insert(
-                alias.clone(),
-                git_anon::config::RemoteConfig {
-                    name: remote_name,
-                    identity: identity.unwrap_or_else(|| "anonymous_identity".to_string()),
-                },
-            );
+            let mut config = with_load_warnings(load_config(dry_run)?, warnings);
+            let remote = git_anon::config::RemoteConfig {
+                name: remote_name,
+                identity: identity.unwrap_or_else(|| DEFAULT_IDENTITY.to_string()),
+                identity_file,
+            };
+            if config.get_identity(&remote.identity).is_none() {
+                anyhow::bail!(
+                    "Unknown identity '{}' (configured: {}). Define it first with \
+                     `git-anon config set-identity <name> <email> --as {}`",
+                    remote.identity,
+                    config.identity_names().join(", "),
+                    remote.identity
+                );
+            }
+
+            if dry_run {
+                show_remote_changes(&alias, config.remotes.get(&alias), &remote);
+                println!("{}", "[DRY RUN] Configuration not written".blue().bold());
+                return Ok(());
+            }
+
+            config.remotes.insert(alias.clone(), remote);
             config.save()?;
 
             println!(
@@ -214,22 +1535,309 @@ fn handle_config(action: ConfigAction) -> Result<()> {
                 alias.yellow()
             );
         }
+
+        ConfigAction::RemoveRemote { alias } => {
+            let mut config = with_load_warnings(load_config(dry_run)?, warnings);
+            let Some(remote) = config.remotes.get(&alias) else {
+                anyhow::bail!(
+                    "No remote configuration named '{alias}' (configured: {})",
+                    config
+                        .remotes
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            };
+            println!(
+                "Remote {}: {} (identity: {}) -> removed",
+                alias.yellow(),
+                remote.name.yellow(),
+                remote.identity.yellow()
+            );
+            println!();
+
+            if dry_run {
+                println!("{}", "[DRY RUN] Configuration not written".blue().bold());
+                return Ok(());
+            }
+            if !no_confirm && !confirm_changes(&format!("Remove remote '{alias}'?"))? {
+                println!("Cancelled.");
+                return Ok(());
+            }
+
+            config.remotes.remove(&alias);
+            config.save()?;
+            println!(
+                "{} Removed remote configuration: {}",
+                "✓".green(),
+                alias.yellow()
+            );
+        }
+
+        ConfigAction::RemoveIdentity { name, force } => {
+            let mut config = with_load_warnings(load_config(dry_run)?, warnings);
+            let Some(identity) = config.get_identity(&name) else {
+                anyhow::bail!(
+                    "Unknown identity '{name}' (configured: {})",
+                    config.identity_names().join(", ")
+                );
+            };
+            if name == DEFAULT_IDENTITY {
+                anyhow::bail!(
+                    "The default identity can't be removed; change it with `git-anon config set-identity`"
+                );
+            }
+            let references = config.identity_references(&name);
+            if !references.is_empty() && !force {
+                anyhow::bail!(
+                    "Identity '{name}' is still used by {}; remap them first or pass --force",
+                    references.join(", ")
+                );
+            }
+            println!(
+                "Identity {}: {} <{}> -> removed",
+                name.yellow(),
+                identity.name.yellow(),
+                identity.email.yellow()
+            );
+            if !references.is_empty() {
+                warnings.warn(format!(
+                    "{} will use the default identity instead",
+                    references.join(", ")
+                ));
+            }
+            println!();
+
+            if dry_run {
+                println!("{}", "[DRY RUN] Configuration not written".blue().bold());
+                return Ok(());
+            }
+            if !no_confirm && !confirm_changes(&format!("Remove identity '{name}'?"))? {
+                println!("Cancelled.");
+                return Ok(());
+            }
+
+            config.remove_identity(&name)?;
+            config.save()?;
+            println!("{} Removed identity {}", "✓".green(), name.yellow());
+        }
+
+        ConfigAction::Export { path: None, .. } => {
+            print!(
+                "{}",
+                with_load_warnings(Config::load()?, warnings).to_toml()?
+            );
+        }
+
+        ConfigAction::Export {
+            path: Some(path),
+            force,
+        } => {
+            if path.exists() && !force {
+                anyhow::bail!(
+                    "{} already exists. Use --force to overwrite it.",
+                    path.display()
+                );
+            }
+
+            with_load_warnings(Config::load()?, warnings).save_to(&path)?;
+            println!(
+                "{} Exported configuration to {}",
+                "✓".green(),
+                path.display().to_string().cyan()
+            );
+        }
+
+        ConfigAction::Import { path, replace, .. } => {
+            let imported = with_load_warnings(Config::load_from(&path)?, warnings);
+            let (errors, lints) = imported.validate();
+            for lint in lints {
+                warnings.warn(lint);
+            }
+            for error in &errors {
+                println!("  {} {error}", "error:".red());
+            }
+            if !errors.is_empty() {
+                anyhow::bail!(
+                    "Refusing to import {}: {} error(s)",
+                    path.display(),
+                    errors.len()
+                );
+            }
+
+            let current = with_load_warnings(load_config(dry_run)?, warnings);
+            let config = if replace {
+                imported
+            } else {
+                let mut merged = current.clone();
+                merged.merge(imported);
+                merged
+            };
+
+            show_identity_changes(
+                &current.anonymous_identity,
+                &config.anonymous_identity.name,
+                &config.anonymous_identity.email,
+            );
+            println!(
+                "Remotes: {} -> {}, branch identities: {} -> {}",
+                current.remotes.len(),
+                config.remotes.len(),
+                current.branch_identities.len(),
+                config.branch_identities.len()
+            );
+            println!();
+            show_setting_changes(&current, &config);
+            let mut aliases: Vec<&String> = current.remotes.keys().collect();
+            aliases.sort();
+            for alias in aliases {
+                let existing = &current.remotes[alias];
+                match config.remotes.get(alias) {
+                    Some(new) if new.name != existing.name || new.identity != existing.identity => {
+                        show_remote_changes(alias, Some(existing), new)
+                    }
+                    Some(_) => {}
+                    None => println!("Remote {}: {}", alias.yellow(), "removed".red()),
+                }
+            }
+
+            if dry_run {
+                println!("{}", "[DRY RUN] Configuration not written".blue().bold());
+                return Ok(());
+            }
+
+            let prompt = if replace {
+                "Replace the active configuration?"
+            } else {
+                "Merge into the active configuration?"
+            };
+            if !no_confirm && !confirm_changes(prompt)? {
+                println!("Cancelled.");
+                return Ok(());
+            }
+
+            if let Some(backup_path) = Config::backup_active()? {
+                println!(
+                    "Previous configuration saved to {}",
+                    backup_path.display().to_string().yellow()
+                );
+            }
+            config.save()?;
+            println!(
+                "{} Imported configuration from {}",
+                "✓".green(),
+                path.display().to_string().cyan()
+            );
+        }
+
+        ConfigAction::Validate { path } => {
+            let path = match path {
+                Some(path) => path,
+                None => Config::config_path()?,
+            };
+            println!("Validating {}", path.display().to_string().cyan());
+
+            let config = with_load_warnings(Config::load_from(&path)?, warnings);
+            let (errors, lints) = config.validate();
+
+            for lint in lints {
+                warnings.warn(lint);
+            }
+            for error in &errors {
+                println!("  {} {error}", "error:".red());
+            }
+
+            if !errors.is_empty() {
+                anyhow::bail!("Configuration has {} error(s)", errors.len());
+            }
+            println!("{} Configuration is valid", "✓".green());
+        }
     }
 
     Ok(())
 }
 
-fn validate_identity(name: &str, email: &str) -> Result<()> {
-    if name.trim().is_empty() {
-        anyhow::bail!("Name cannot be empty");
+/// A per-run salt for pseudonyms when the config doesn't persist one.
+fn random_salt() -> String {
+    let bytes: [u8; 16] = rand::random();
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn backup_conflict(force: bool, on_conflict: BackupConflict) -> BackupConflict {
+    if force {
+        BackupConflict::Overwrite
+    } else {
+        on_conflict
+    }
+}
+
+/// Whether to skip the backup: with `--no-backup`, or with the config's
+/// `no_backup` unless a backup was asked for on the command line.
+fn backup_disabled(
+    no_backup: bool,
+    config_no_backup: bool,
+    name: &Option<String>,
+    bundle: &Option<PathBuf>,
+) -> bool {
+    no_backup || (config_no_backup && name.is_none() && bundle.is_none())
+}
+
+/// Parses a byte size with an optional binary `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1024),
+        Some((i, 'm' | 'M')) => (&value[..i], 1024 * 1024),
+        Some((i, 'g' | 'G')) => (&value[..i], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size: {value}"))
+}
+
+/// Parses a `+HHMM`/`-HH:MM` UTC offset into minutes.
+fn parse_tz(value: &str) -> Result<i32, String> {
+    let invalid = || format!("invalid timezone offset '{value}' (expected e.g. +0200 or -05:30)");
+    let (sign, rest) = match value.as_bytes().first() {
+        Some(b'+') => (1, &value[1..]),
+        Some(b'-') => (-1, &value[1..]),
+        _ => return Err(invalid()),
+    };
+    let digits = rest.replace(':', "");
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
     }
-    if email.trim().is_empty() {
-        anyhow::bail!("Email cannot be empty");
+
+    let hours: i32 = digits[..2].parse().map_err(|_| invalid())?;
+    let minutes: i32 = digits[2..].parse().map_err(|_| invalid())?;
+    if hours > 14 || minutes > 59 {
+        return Err(invalid());
     }
-    if !email.contains('@') {
-        anyhow::bail!("Email must contain @ symbol");
+    Ok(sign * (hours * 60 + minutes))
+}
+
+fn parse_git_config(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{value}'"))?;
+    let key = key.trim();
+    match key.split_once('.') {
+        Some((section, name)) if !section.is_empty() && !name.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!(
+            "invalid config key '{key}' (expected section.name, e.g. http.sslVerify)"
+        )),
     }
-    Ok(())
+}
+
+fn parse_pathspec(value: &str) -> Result<Pattern, String> {
+    Pattern::new(value.trim_end_matches('/')).map_err(|e| format!("invalid glob: {e}"))
 }
 
 fn show_identity_changes(current: &git_anon::config::Identity, new_name: &str, new_email: &str) {
@@ -243,6 +1851,191 @@ fn show_identity_changes(current: &git_anon::config::Identity, new_name: &str, n
     println!();
 }
 
+/// Prints the top-level settings an import changes.
+fn show_setting_changes(current: &Config, new: &Config) {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_string());
+    let list = |values: &[String]| match values.is_empty() {
+        true => "none".to_string(),
+        false => values.join(", "),
+    };
+    let signing = |config: &Config| {
+        let format = format!("{:?}", config.signing.format).to_lowercase();
+        match &config.signing.program {
+            Some(program) => format!("{format} ({program})"),
+            None => format,
+        }
+    };
+    let settings = [
+        (
+            "forbidden_identities",
+            list(&current.forbidden_identities),
+            list(&new.forbidden_identities),
+        ),
+        (
+            "abort_on_warning",
+            current.abort_on_warning.to_string(),
+            new.abort_on_warning.to_string(),
+        ),
+        (
+            "no_backup",
+            current.no_backup.to_string(),
+            new.no_backup.to_string(),
+        ),
+        (
+            "backup_remote",
+            optional(&current.backup_remote),
+            optional(&new.backup_remote),
+        ),
+        (
+            "policy_url",
+            optional(&current.policy_url),
+            optional(&new.policy_url),
+        ),
+        (
+            "pseudonym_salt",
+            optional(&current.pseudonym_salt),
+            optional(&new.pseudonym_salt),
+        ),
+        (
+            "squash_message",
+            optional(&current.squash_message),
+            optional(&new.squash_message),
+        ),
+        ("signing", signing(current), signing(new)),
+    ];
+    let mut changed = false;
+    for (setting, current, new) in settings {
+        if current != new {
+            println!("{}: {} -> {}", setting, current.yellow(), new.green());
+            changed = true;
+        }
+    }
+    if changed {
+        println!();
+    }
+}
+
+/// Loads the active config, without creating it on a dry run.
+fn load_config(dry_run: bool) -> Result<Config> {
+    if dry_run {
+        Config::load_or_default()
+    } else {
+        Config::load()
+    }
+}
+
+fn show_remote_changes(
+    alias: &str,
+    current: Option<&git_anon::config::RemoteConfig>,
+    new: &git_anon::config::RemoteConfig,
+) {
+    match current {
+        Some(current) => println!(
+            "Remote {}: {} (identity: {}) -> {} (identity: {})",
+            alias.yellow(),
+            current.name.yellow(),
+            current.identity.yellow(),
+            new.name.green(),
+            new.identity.green()
+        ),
+        None => println!(
+            "Remote {}: new -> {} (identity: {})",
+            alias.yellow(),
+            new.name.green(),
+            new.identity.green()
+        ),
+    }
+    println!();
+}
+
+/// Asks which identity to use when a remote or branch mapping names one
+/// that doesn't exist, instead of silently falling back to the default.
+/// Defining the missing identity saves it, so the mapping resolves next time.
+fn pick_identity(
+    config: &mut Config,
+    resolved: ResolvedIdentity,
+    dry_run: bool,
+    reporter: Reporter,
+) -> Result<ResolvedIdentity> {
+    let IdentitySource::Fallback { mapping, missing } = resolved.source.clone() else {
+        return Ok(resolved);
+    };
+    let names: Vec<String> = config
+        .identity_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let mut items: Vec<String> = names
+        .iter()
+        .filter_map(|name| {
+            let identity = config.get_identity(name)?;
+            Some(format!("{name}: {} <{}>", identity.name, identity.email))
+        })
+        .collect();
+    items.push(format!("Define '{missing}' now"));
+
+    let choice = Select::new()
+        .with_prompt(format!(
+            "{mapping} references unknown identity '{missing}'. Use which identity?"
+        ))
+        .items(&items)
+        .default(0)
+        .interact()?;
+    if let Some(name) = names.get(choice) {
+        let identity = config
+            .get_identity(name)
+            .with_context(|| format!("Unknown identity '{name}'"))?;
+        return Ok(ResolvedIdentity {
+            identity,
+            source: IdentitySource::Chosen {
+                mapping,
+                name: name.clone(),
+            },
+        });
+    }
+
+    let name: String = Input::new().with_prompt("Anonymous name").interact_text()?;
+    let email: String = Input::new()
+        .with_prompt("Anonymous email")
+        .interact_text()?;
+    validate_identity(&name, &email)?;
+    let identity = Identity {
+        name,
+        email,
+        ..Identity::default()
+    };
+    if dry_run {
+        reporter.say(format_args!(
+            "{}",
+            format!("[DRY RUN] Identity '{missing}' not saved")
+                .blue()
+                .bold()
+        ));
+    } else {
+        // The loaded config may carry policy overrides; only add the identity
+        // to what is on disk.
+        let mut stored = Config::load()?;
+        stored.identities.insert(missing.clone(), identity.clone());
+        stored.save()?;
+        reporter.say(format_args!(
+            "{} Saved identity {}",
+            "✓".green(),
+            missing.yellow()
+        ));
+    }
+    config.identities.insert(missing.clone(), identity);
+    let identity = config
+        .get_identity(&missing)
+        .with_context(|| format!("Unknown identity '{missing}'"))?;
+    Ok(ResolvedIdentity {
+        identity,
+        source: IdentitySource::Chosen {
+            mapping,
+            name: missing,
+        },
+    })
+}
+
 fn confirm_changes(prompt: &str) -> Result<bool> {
     Ok(Confirm::new()
         .with_prompt(prompt)
@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use git2::Oid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::AnonymousIdentity;
+use crate::config::validate_identity;
+
+/// A reviewed description of what an operation is about to do, written by
+/// `--dry-run --plan-out` and checked by `--plan-in`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plan {
+    pub operation: String,
+    pub branch: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    pub identity: PlannedIdentity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_branch: Option<String>,
+    pub commits: Vec<PlannedCommit>,
+    /// Distinct author and committer identities in `commits`.
+    #[serde(default)]
+    pub contributors: usize,
+    pub identity_mapping: Vec<IdentityMapping>,
+    #[serde(default)]
+    pub tags: Vec<PlannedTag>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedIdentity {
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedCommit {
+    pub oid: String,
+    pub author: String,
+    pub committer: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityMapping {
+    pub original: String,
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedTag {
+    pub name: String,
+    pub target: String,
+}
+
+impl Plan {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read plan file {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse plan file {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents + "\n")
+            .with_context(|| format!("Failed to write plan file {}", path.display()))
+    }
+
+    /// Describes how `current` departs from this reviewed plan. The backup
+    /// branch is not compared because the run reuses the planned name.
+    pub fn differences(&self, current: &Plan) -> Vec<String> {
+        let mut diffs = Vec::new();
+
+        if self.operation != current.operation {
+            diffs.push(format!(
+                "operation is {} but the plan is for {}",
+                current.operation, self.operation
+            ));
+        }
+        if self.branch != current.branch {
+            diffs.push(format!(
+                "branch is {} but the plan is for {}",
+                current.branch, self.branch
+            ));
+        }
+        if self.remote != current.remote {
+            diffs.push("target remote differs from the plan".to_string());
+        }
+        if self.identity != current.identity {
+            diffs.push(format!(
+                "identity is {} <{}> but the plan uses {} <{}>",
+                current.identity.name,
+                current.identity.email,
+                self.identity.name,
+                self.identity.email
+            ));
+        }
+        if self.commits != current.commits {
+            diffs.push(format!(
+                "{} commits would be rewritten but the plan lists {}",
+                current.commits.len(),
+                self.commits.len()
+            ));
+        }
+        if self.tags != current.tags {
+            diffs.push("tags differ from the plan".to_string());
+        }
+
+        diffs
+    }
+}
+
+/// Discovered identities and their hand-picked replacements, written by
+/// `--email-map-out` and read back by `--author-map`. Stored as JSON when the
+/// file ends in `.json`, TOML otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorMap {
+    #[serde(default, rename = "identity")]
+    pub identities: Vec<IdentityMapping>,
+}
+
+impl AuthorMap {
+    /// An author map listing `originals` with blank replacements to fill in.
+    pub fn template<'a>(originals: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            identities: originals
+                .into_iter()
+                .map(|original| IdentityMapping {
+                    original: original.to_string(),
+                    replacement: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read author map {}", path.display()))?;
+        let parsed = if is_json(path) {
+            serde_json::from_str(&contents).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(&contents).map_err(anyhow::Error::from)
+        };
+        parsed.with_context(|| format!("Failed to parse author map {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = if is_json(path) {
+            serde_json::to_string_pretty(self)? + "\n"
+        } else {
+            format!(
+                "# Fill in a `Name <email>` replacement for each identity.\n\
+                 # Blank replacements use the anonymous identity.\n\n{}",
+                toml::to_string_pretty(self)?
+            )
+        };
+        fs::write(path, contents)
+            .with_context(|| format!("Failed to write author map {}", path.display()))
+    }
+
+    /// The filled-in replacements, keyed by the original `Name <email>`.
+    pub fn replacements(&self) -> Result<HashMap<String, AnonymousIdentity>> {
+        let mut replacements = HashMap::new();
+        for mapping in &self.identities {
+            let replacement = mapping.replacement.trim();
+            if replacement.is_empty() {
+                continue;
+            }
+
+            let identity = parse_identity(replacement)
+                .with_context(|| format!("Invalid replacement for {}", mapping.original))?;
+            replacements.insert(mapping.original.clone(), identity);
+        }
+        Ok(replacements)
+    }
+}
+
+/// The exact identity each commit should receive, keyed by original commit
+/// OID. Written by `--commit-map-out`, edited, and read back by `--commit-map`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitMap {
+    #[serde(default, rename = "commit")]
+    pub commits: Vec<CommitIdentity>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitIdentity {
+    pub oid: String,
+    /// The original author, for the reviewer's reference only.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub original: String,
+    pub identity: String,
+}
+
+impl CommitMap {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read commit map {}", path.display()))?;
+        let parsed = if is_json(path) {
+            serde_json::from_str(&contents).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(&contents).map_err(anyhow::Error::from)
+        };
+        parsed.with_context(|| format!("Failed to parse commit map {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = if is_json(path) {
+            serde_json::to_string_pretty(self)? + "\n"
+        } else {
+            format!(
+                "# Edit the `Name <email>` identity each commit will receive.\n\n{}",
+                toml::to_string_pretty(self)?
+            )
+        };
+        fs::write(path, contents)
+            .with_context(|| format!("Failed to write commit map {}", path.display()))
+    }
+
+    /// The identities keyed by commit OID.
+    pub fn identities(&self) -> Result<HashMap<Oid, AnonymousIdentity>> {
+        let mut identities = HashMap::new();
+        for entry in &self.commits {
+            let oid = Oid::from_str(entry.oid.trim())
+                .with_context(|| format!("Invalid commit OID '{}' in commit map", entry.oid))?;
+            let identity = parse_identity(entry.identity.trim())
+                .with_context(|| format!("Invalid identity for commit {}", entry.oid))?;
+            if identities.insert(oid, identity).is_some() {
+                anyhow::bail!(
+                    "Commit {} is listed more than once in the commit map",
+                    entry.oid
+                );
+            }
+        }
+        Ok(identities)
+    }
+}
+
+/// Parses and validates a `Name <email>` identity.
+fn parse_identity(value: &str) -> Result<AnonymousIdentity> {
+    let (name, email) = value
+        .strip_suffix('>')
+        .and_then(|rest| rest.rsplit_once('<'))
+        .map(|(name, email)| (name.trim(), email.trim()))
+        .with_context(|| format!("Expected 'Name <email>', got '{value}'"))?;
+    validate_identity(name, email)?;
+    Ok(AnonymousIdentity {
+        name: name.to_string(),
+        email: email.to_string(),
+        signing_key: None,
+    })
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
@@ -0,0 +1,94 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+use crate::AnonymousIdentity;
+
+/// Files that conventionally name the people behind a project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataKind {
+    /// `AUTHORS`, `CONTRIBUTORS`, `MAINTAINERS`: every line names someone.
+    Authors,
+    /// `LICENSE`, `COPYING`, `COPYRIGHT`, `NOTICE`: only copyright lines do.
+    License,
+}
+
+/// A metadata file in the snapshot along with the lines that identify people.
+#[derive(Debug, Clone)]
+pub struct MetadataFile {
+    pub path: String,
+    pub kind: MetadataKind,
+    pub lines: Vec<String>,
+}
+
+pub fn classify(path: &str) -> Option<MetadataKind> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let stem = name.split('.').next().unwrap_or(name).to_ascii_uppercase();
+
+    match stem.as_str() {
+        "AUTHORS" | "CONTRIBUTORS" | "MAINTAINERS" => Some(MetadataKind::Authors),
+        "LICENSE" | "LICENCE" | "COPYING" | "COPYRIGHT" | "NOTICE" => Some(MetadataKind::License),
+        _ => None,
+    }
+}
+
+pub fn identifying_lines(kind: MetadataKind, content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| match kind {
+            MetadataKind::Authors => !line.is_empty() && !line.starts_with('#'),
+            MetadataKind::License => is_copyright_line(line),
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Rewrites the identifying parts of `content` to name `identity` instead.
+pub fn rewrite(kind: MetadataKind, content: &str, identity: &AnonymousIdentity) -> String {
+    match kind {
+        MetadataKind::Authors => format!("{} <{}>\n", identity.name, identity.email),
+        MetadataKind::License => {
+            let mut rewritten: String = content
+                .lines()
+                .map(|line| {
+                    if is_copyright_line(line.trim()) {
+                        rewrite_copyright_line(line, &identity.name)
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            if content.ends_with('\n') {
+                rewritten.push('\n');
+            }
+            rewritten
+        }
+    }
+}
+
+/// A copyright holder line: `Copyright (c) ...`, `© ...` or
+/// `Copyright <year>[-<year>] <name>`. License prose that merely starts with
+/// the word ("copyright notice that is included in...") doesn't match.
+static COPYRIGHT_HOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)^(?:©|copyright\s*(?:\(c\)|©)|copyright\s+\d{4}(?:\s*[-–,]\s*\d{4})*,?\s+\S)")
+        .unwrap()
+});
+
+fn is_copyright_line(line: &str) -> bool {
+    COPYRIGHT_HOLDER.is_match(line)
+}
+
+/// Keeps everything up to the last year on the line and replaces the holder.
+fn rewrite_copyright_line(line: &str, name: &str) -> String {
+    let bytes = line.as_bytes();
+    let year_end = (0..bytes.len().saturating_sub(3))
+        .rev()
+        .find(|&i| bytes[i..i + 4].iter().all(u8::is_ascii_digit))
+        .map(|i| i + 4);
+
+    match year_end {
+        Some(end) => format!("{} {name}", &line[..end]),
+        None => format!("Copyright (c) {name}"),
+    }
+}
@@ -0,0 +1,57 @@
+use serde::Serialize;
+use std::fmt::Display;
+
+/// How a command reports what it did: colored prose, or a single JSON object
+/// for scripts (`--json`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reporter {
+    #[default]
+    Human,
+    Json,
+}
+
+impl Reporter {
+    /// Prints a line of prose. Under `--json` it goes to stderr, so stdout
+    /// carries nothing but the JSON object.
+    pub fn say(self, line: impl Display) {
+        match self {
+            Self::Human => println!("{line}"),
+            Self::Json => eprintln!("{line}"),
+        }
+    }
+
+    /// Prints the result of an operation; only `--json` has anything to add
+    /// to the prose already printed.
+    pub fn emit(self, outcome: &Outcome) {
+        if self == Self::Json {
+            println!(
+                "{}",
+                serde_json::to_string(outcome).expect("outcome serializes")
+            );
+        }
+    }
+
+    pub fn is_json(self) -> bool {
+        self == Self::Json
+    }
+}
+
+/// The machine-readable result of `squash`, `push` or `clean`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Outcome {
+    pub operation: String,
+    pub branch: String,
+    /// `Name <email>` of the identity commits were rewritten with.
+    pub identity: String,
+    pub dry_run: bool,
+    /// Commits rewritten, or that would be on a dry run.
+    pub commits: usize,
+    /// Distinct identities collapsed into one by a squash.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributors: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// The backup branch or bundle holding the original history.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+}
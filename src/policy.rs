@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::AnonymousIdentity;
+use crate::config::{Config, Identity, RemoteConfig, ScrubConfig};
+use crate::scrub::TrailerMode;
+
+/// How long to wait for the policy server before falling back to the cache.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An organization's anonymization rules, fetched from `policy_url` and laid
+/// over the local config. Uses the config schema, with every key optional,
+/// plus `required_domains`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(alias = "default_identity")]
+    pub anonymous_identity: Option<Identity>,
+    #[serde(default)]
+    pub identities: HashMap<String, Identity>,
+    #[serde(default)]
+    pub remotes: HashMap<String, RemoteConfig>,
+    #[serde(default)]
+    pub branch_identities: HashMap<String, String>,
+    #[serde(default)]
+    pub scrub: ScrubConfig,
+    pub abort_on_warning: Option<bool>,
+    /// Email domains every anonymous identity must use.
+    #[serde(default)]
+    pub required_domains: Vec<String>,
+}
+
+/// A fetched policy, and why the cached copy was used if it was.
+#[derive(Debug)]
+pub struct LoadedPolicy {
+    pub policy: Policy,
+    pub cache_fallback: Option<String>,
+}
+
+impl Policy {
+    /// Fetches the policy at `url` (`http(s)://` or `file://`), caching it on
+    /// success and falling back to the cached copy when the fetch fails.
+    pub fn load(url: &str) -> Result<LoadedPolicy> {
+        let cache = cache_path()?;
+        match fetch(url) {
+            Ok(contents) => {
+                let policy = Self::parse(&contents, url)?;
+                // A failed cache write only costs the offline fallback.
+                let _ = fs::write(&cache, &contents);
+                Ok(LoadedPolicy {
+                    policy,
+                    cache_fallback: None,
+                })
+            }
+            Err(e) if cache.exists() => {
+                let contents = fs::read_to_string(&cache)
+                    .with_context(|| format!("Failed to read cached policy {}", cache.display()))?;
+                Ok(LoadedPolicy {
+                    policy: Self::parse(&contents, &cache.display().to_string())?,
+                    cache_fallback: Some(format!(
+                        "could not fetch policy from {url} ({e:#}); using the cached copy"
+                    )),
+                })
+            }
+            Err(e) => Err(e.context("No cached policy is available for offline use")),
+        }
+    }
+
+    pub fn parse(contents: &str, source: &str) -> Result<Self> {
+        serde_json::from_str(contents)
+            .with_context(|| format!("Failed to parse policy from {source}"))
+    }
+
+    /// Lays the policy over `config`: keys the policy sets win, maps are
+    /// extended and scrub patterns appended. Fails if the result is invalid.
+    pub fn apply(self, config: &mut Config) -> Result<()> {
+        if let Some(identity) = self.anonymous_identity {
+            config.anonymous_identity = identity;
+        }
+        config.identities.extend(self.identities);
+        config.remotes.extend(self.remotes);
+        config.branch_identities.extend(self.branch_identities);
+        config.scrub.patterns.extend(self.scrub.patterns);
+        config.scrub.allow.extend(self.scrub.allow);
+        config.scrub.deny.extend(self.scrub.deny);
+        if let Some(abort_on_warning) = self.abort_on_warning {
+            config.abort_on_warning = abort_on_warning;
+        }
+        config.required_domains = self
+            .required_domains
+            .into_iter()
+            .map(|domain| domain.trim_start_matches('@').to_ascii_lowercase())
+            .collect();
+
+        config
+            .scrub_patterns()
+            .and_then(|_| config.identity_scrubber(TrailerMode::default()))
+            .context("Policy contains an invalid scrub pattern")?;
+        let (errors, _) = config.validate();
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "Configuration is invalid with the policy applied:\n  {}",
+                errors.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Fails unless `identity`'s email is in one of the policy's required domains.
+pub fn check_required_domain(config: &Config, identity: &AnonymousIdentity) -> Result<()> {
+    if config.required_domains.is_empty() {
+        return Ok(());
+    }
+    let domain = identity
+        .email
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_ascii_lowercase())
+        .unwrap_or_default();
+    if !config.required_domains.contains(&domain) {
+        anyhow::bail!(
+            "Identity {} <{}> is not allowed by the policy; emails must use one of: {}",
+            identity.name,
+            identity.email,
+            config.required_domains.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn fetch(url: &str) -> Result<String> {
+    if let Some(path) = url.strip_prefix("file://") {
+        return fs::read_to_string(Path::new(path))
+            .with_context(|| format!("Failed to read policy file {path}"));
+    }
+
+    ureq::get(url)
+        .timeout(FETCH_TIMEOUT)
+        .call()
+        .with_context(|| format!("Failed to fetch policy from {url}"))?
+        .into_string()
+        .with_context(|| format!("Failed to read policy from {url}"))
+}
+
+fn cache_path() -> Result<PathBuf> {
+    Ok(Config::config_path()?.with_file_name("policy-cache.json"))
+}
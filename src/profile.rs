@@ -0,0 +1,61 @@
+use crate::output::Reporter;
+use colored::Colorize;
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+/// Accumulates wall-clock time per named phase for `--profile`.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    enabled: bool,
+    phases: RefCell<Vec<(&'static str, Duration)>>,
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            phases: RefCell::default(),
+        }
+    }
+
+    /// Runs `f`, adding its duration to `phase` when profiling is enabled.
+    pub fn time<T>(&self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+        result
+    }
+
+    fn record(&self, phase: &'static str, elapsed: Duration) {
+        let mut phases = self.phases.borrow_mut();
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => phases.push((phase, elapsed)),
+        }
+    }
+
+    pub fn phases(&self) -> Vec<(&'static str, Duration)> {
+        self.phases.borrow().clone()
+    }
+
+    /// Prints the time spent per phase, on stderr under `--json`.
+    pub fn report(&self, reporter: Reporter) {
+        if !self.enabled {
+            return;
+        }
+
+        let phases = self.phases.borrow();
+        let total: Duration = phases.iter().map(|(_, elapsed)| *elapsed).sum();
+
+        reporter.say("");
+        reporter.say("Profile:".bold());
+        for (phase, elapsed) in phases.iter() {
+            reporter.say(format_args!("  {:<20} {:>10.3?}", phase, elapsed));
+        }
+        reporter.say(format_args!("  {:<20} {:>10.3?}", "total".bold(), total));
+    }
+}
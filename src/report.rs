@@ -0,0 +1,110 @@
+use anyhow::{Context, Result};
+use git2::Oid;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::mapping::Plan;
+
+/// What a run did, written by `--report-out` for record keeping or as a CI
+/// artifact.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub operation: String,
+    pub branch: String,
+    pub dry_run: bool,
+    pub commits_rewritten: usize,
+    pub identities_replaced: Vec<String>,
+    /// Tags that pointed at rewritten commits. Those not in `moved_tags`
+    /// still point at the original history.
+    pub tags: Vec<String>,
+    /// Tags `--include-tags` moved to the rewritten commits.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moved_tags: Vec<String>,
+    /// SHA-256 over the sorted original→new commit pairs; equal digests mean
+    /// two runs produced the same history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapping_digest: Option<String>,
+    pub timings: Vec<PhaseTiming>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PhaseTiming {
+    pub phase: String,
+    pub seconds: f64,
+}
+
+impl Report {
+    pub fn from_plan(plan: &Plan, dry_run: bool) -> Self {
+        Self {
+            operation: plan.operation.clone(),
+            branch: plan.branch.clone(),
+            dry_run,
+            commits_rewritten: plan.commits.len(),
+            identities_replaced: plan
+                .identity_mapping
+                .iter()
+                .map(|mapping| mapping.original.clone())
+                .collect(),
+            tags: plan.tags.iter().map(|tag| tag.name.clone()).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Records the outcome of a rewrite: how many commits it produced and the
+    /// digest of its mapping.
+    pub fn set_mapping(&mut self, mapping: &HashMap<Oid, Oid>) {
+        self.commits_rewritten = mapping.len();
+        self.mapping_digest = Some(mapping_digest(mapping));
+    }
+
+    /// The compact `--summary-json` view: counts and the digest only.
+    pub fn summary(&self) -> serde_json::Value {
+        serde_json::json!({
+            "operation": self.operation,
+            "branch": self.branch,
+            "dry_run": self.dry_run,
+            "commits_rewritten": self.commits_rewritten,
+            "identities_replaced": self.identities_replaced.len(),
+            "mapping_digest": self.mapping_digest,
+        })
+    }
+
+    pub fn set_timings(&mut self, phases: &[(&str, Duration)]) {
+        self.timings = phases
+            .iter()
+            .map(|(phase, elapsed)| PhaseTiming {
+                phase: phase.to_string(),
+                seconds: elapsed.as_secs_f64(),
+            })
+            .collect();
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read report {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse report {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(path, contents + "\n")
+            .with_context(|| format!("Failed to write report {}", path.display()))
+    }
+}
+
+pub fn mapping_digest(mapping: &HashMap<Oid, Oid>) -> String {
+    let mut pairs: Vec<_> = mapping.iter().collect();
+    pairs.sort();
+
+    let mut hasher = Sha256::new();
+    for (original, new) in pairs {
+        hasher.update(format!("{original} {new}\n"));
+    }
+    format!("{:x}", hasher.finalize())
+}
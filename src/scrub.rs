@@ -0,0 +1,167 @@
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::LazyLock;
+
+use crate::AnonymousIdentity;
+
+/// Trailer keys whose value names a person.
+const IDENTITY_TRAILERS: &[&str] = &[
+    "co-authored-by",
+    "signed-off-by",
+    "reviewed-by",
+    "acked-by",
+    "tested-by",
+    "reported-by",
+    "suggested-by",
+    "helped-by",
+    "cc",
+];
+
+static EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap());
+
+/// The email addresses mentioned in `text`.
+pub fn find_emails(text: &str) -> impl Iterator<Item = &str> {
+    EMAIL.find_iter(text).map(|email| email.as_str())
+}
+
+/// What `--scrub-messages` does with identity trailers such as `Signed-off-by`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TrailerMode {
+    /// Name the anonymous identity in them instead.
+    #[default]
+    Replace,
+    /// Drop them from the message.
+    Remove,
+}
+
+/// Real identities that must never come out of a rewrite
+/// (`forbidden_identities`). Each glob is matched, ignoring case, against the
+/// name, the email and `Name <email>`.
+#[derive(Debug, Clone, Default)]
+pub struct ForbiddenIdentities {
+    pub patterns: Vec<glob::Pattern>,
+}
+
+impl ForbiddenIdentities {
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, name: &str, email: &str) -> bool {
+        let signature = format!("{name} <{email}>");
+        [name, email, signature.as_str()]
+            .into_iter()
+            .any(|text| self.matches_text(text))
+    }
+
+    /// For an email address found on its own, such as in a message.
+    pub fn matches_email(&self, email: &str) -> bool {
+        self.matches_text(email)
+    }
+
+    fn matches_text(&self, text: &str) -> bool {
+        let options = glob::MatchOptions {
+            case_sensitive: false,
+            ..glob::MatchOptions::new()
+        };
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.matches_with(text, options))
+    }
+}
+
+/// Finds names and email addresses baked into commit messages.
+#[derive(Debug, Clone, Default)]
+pub struct IdentityScrubber {
+    pub trailers: TrailerMode,
+    /// Email addresses matching any of these are left alone.
+    pub allow: Vec<Regex>,
+    /// Anything matching these (typically real names, which can't be detected
+    /// on their own) is replaced with the anonymous name.
+    pub deny: Vec<Regex>,
+}
+
+impl IdentityScrubber {
+    /// Rewrites identity trailers and replaces every email address that isn't
+    /// allowed with `identity`'s. Other lines are kept byte for byte.
+    pub fn scrub<'a>(&self, message: &'a str, identity: &AnonymousIdentity) -> Cow<'a, str> {
+        let mut scrubbed = String::with_capacity(message.len());
+        let mut trailers_seen: Vec<String> = Vec::new();
+        let mut removed = false;
+
+        for line in message.split_inclusive('\n') {
+            let (content, ending) = match line.strip_suffix('\n') {
+                Some(content) => (content, "\n"),
+                None => (line, ""),
+            };
+
+            if let Some(key) = self.identity_trailer(content) {
+                if self.trailers == TrailerMode::Remove {
+                    removed = true;
+                    continue;
+                }
+                let trailer = format!("{key}: {} <{}>", identity.name, identity.email);
+                // Several co-authors collapse into the same anonymous line.
+                if !trailers_seen.contains(&trailer) {
+                    scrubbed.push_str(&trailer);
+                    scrubbed.push_str(ending);
+                    trailers_seen.push(trailer);
+                }
+                continue;
+            }
+
+            let content = EMAIL.replace_all(content, |caps: &regex::Captures| {
+                match self.allowed(&caps[0]) {
+                    true => caps[0].to_string(),
+                    false => identity.email.clone(),
+                }
+            });
+            let content = self.deny.iter().fold(content, |content, regex| {
+                match regex.replace_all(&content, regex::NoExpand(&identity.name)) {
+                    Cow::Owned(replaced) => Cow::Owned(replaced),
+                    Cow::Borrowed(_) => content,
+                }
+            });
+            scrubbed.push_str(&content);
+            scrubbed.push_str(ending);
+        }
+
+        if removed {
+            // Don't leave the blank line that separated the removed trailers.
+            let trimmed = scrubbed.trim_end_matches('\n').len();
+            if trimmed < scrubbed.len() {
+                scrubbed.truncate(trimmed);
+                if message.ends_with('\n') {
+                    scrubbed.push('\n');
+                }
+            }
+        }
+
+        match scrubbed == message {
+            true => Cow::Borrowed(message),
+            false => Cow::Owned(scrubbed),
+        }
+    }
+
+    /// The key of `line` if it is an identity trailer naming someone whose
+    /// email isn't allowed.
+    fn identity_trailer<'l>(&self, line: &'l str) -> Option<&'l str> {
+        let (key, value) = line.split_once(':')?;
+        let key = key.trim_end();
+        if !IDENTITY_TRAILERS.contains(&key.to_ascii_lowercase().as_str()) {
+            return None;
+        }
+        let mut emails = EMAIL
+            .find_iter(value)
+            .map(|email| email.as_str())
+            .peekable();
+        let allowed = emails.peek().is_some() && emails.all(|email| self.allowed(email));
+        (!allowed).then_some(key)
+    }
+
+    /// Whether `email` matches one of the `allow` regexes.
+    pub fn allowed(&self, email: &str) -> bool {
+        self.allow.iter().any(|regex| regex.is_match(email))
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Which kind of signature `--sign` produces, like git's `gpg.format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningFormat {
+    #[default]
+    Openpgp,
+    Ssh,
+}
+
+impl SigningFormat {
+    fn default_program(self) -> &'static str {
+        match self {
+            Self::Openpgp => "gpg",
+            Self::Ssh => "ssh-keygen",
+        }
+    }
+}
+
+/// Signs commit payloads with the anonymous identity's key, running the
+/// signing program the same way git does.
+#[derive(Debug, Clone)]
+pub struct Signer {
+    pub format: SigningFormat,
+    pub program: String,
+    /// A GPG key ID, or the path of an SSH private (or public, with an agent) key.
+    pub key: String,
+}
+
+impl Signer {
+    pub fn new(format: SigningFormat, program: Option<String>, key: String) -> Self {
+        Self {
+            format,
+            program: program.unwrap_or_else(|| format.default_program().to_string()),
+            key,
+        }
+    }
+
+    /// Returns the armored signature for `payload`.
+    pub fn sign(&self, payload: &str) -> Result<String> {
+        let mut command = Command::new(&self.program);
+        match self.format {
+            SigningFormat::Openpgp => command.args(["--status-fd=2", "-bsau", &self.key]),
+            SigningFormat::Ssh => command.args(["-Y", "sign", "-n", "git", "-f", &self.key]),
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run signing program '{}'", self.program))?;
+        child
+            .stdin
+            .take()
+            .context("Failed to open the signing program's stdin")?
+            .write_all(payload.as_bytes())?;
+
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{} failed to sign with key {}: {}",
+                self.program,
+                self.key,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        String::from_utf8(output.stdout).context("Signing program printed a non-UTF-8 signature")
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// File in the working tree listing paths to leave out of the squashed tree.
+pub const IGNORE_FILE: &str = ".git-anon-ignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Paths `squash` and `clean` drop from the tree they commit, in gitignore
+/// syntax: `#` comments, `!` to re-include, a trailing `/` for directories
+/// only, and a `/` at the start or in the middle anchoring the pattern to the
+/// repository root. The last matching rule wins.
+#[derive(Debug, Clone, Default)]
+pub struct StripRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl StripRules {
+    /// Reads `.git-anon-ignore` from `workdir` (if there is one) and adds the
+    /// `--strip` globs after it.
+    pub fn load(workdir: &Path, extra: &[String]) -> Result<Self> {
+        let path = workdir.join(IGNORE_FILE);
+        let mut rules = match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents)
+                .with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        for line in extra {
+            rules.push(line)?;
+        }
+        Ok(rules)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut rules = Self::default();
+        for line in contents.lines() {
+            rules.push(line)?;
+        }
+        Ok(rules)
+    }
+
+    /// Adds one gitignore-style line; blank lines and comments are skipped.
+    pub fn push(&mut self, line: &str) -> Result<()> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        let pattern =
+            Pattern::new(line).with_context(|| format!("Invalid strip pattern '{line}'"))?;
+        self.rules.push(Rule {
+            pattern,
+            negated,
+            dir_only,
+            anchored,
+        });
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether the file at `path` is stripped, by a rule matching it or one
+    /// of its directories. As in git, a file in a stripped directory can't
+    /// be re-included.
+    pub fn matches(&self, path: &str) -> bool {
+        let mut directory_end = 0;
+        while let Some(offset) = path[directory_end..].find('/') {
+            directory_end += offset;
+            if self.decide(&path[..directory_end], true) {
+                return true;
+            }
+            directory_end += 1;
+        }
+        self.decide(path, false)
+    }
+
+    fn decide(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                (is_dir || !rule.dir_only)
+                    && match rule.anchored {
+                        true => rule.pattern.matches_with(path, MATCH_OPTIONS),
+                        false => rule.pattern.matches_with(name, MATCH_OPTIONS),
+                    }
+            })
+            .is_some_and(|rule| !rule.negated)
+    }
+}
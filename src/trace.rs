@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Writes the `--trace` event log: one JSON object per line, in the order
+/// the events happen. Disabled tracers drop every event.
+#[derive(Default)]
+pub struct Tracer {
+    out: Option<RefCell<Box<dyn Write>>>,
+}
+
+impl Tracer {
+    /// A tracer writing to `path`, or to stderr when `path` is `-`.
+    pub fn open(path: &Path) -> Result<Self> {
+        let out: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(io::stderr())
+        } else {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create trace file {}", path.display()))?;
+            Box::new(BufWriter::new(file))
+        };
+        Ok(Self {
+            out: Some(RefCell::new(out)),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.out.is_some()
+    }
+
+    /// Records `event`, built lazily so disabled tracing costs nothing.
+    pub fn event(&self, event: impl FnOnce() -> Value) {
+        let Some(out) = &self.out else {
+            return;
+        };
+        let mut out = out.borrow_mut();
+        // Tracing is diagnostic; a failed write must not fail the rewrite.
+        let _ = writeln!(out, "{}", event());
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        if let Some(out) = &self.out {
+            let _ = out.borrow_mut().flush();
+        }
+    }
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("enabled", &self.enabled())
+            .finish()
+    }
+}
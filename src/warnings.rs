@@ -0,0 +1,36 @@
+use anyhow::Result;
+use colored::Colorize;
+use std::cell::RefCell;
+
+/// Collects the warnings printed during a run so `--abort-on-warning` can
+/// fail it once the operation has finished.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    messages: RefCell<Vec<String>>,
+}
+
+impl Warnings {
+    /// Prints `message` to stderr as a warning and records it.
+    pub fn warn(&self, message: impl Into<String>) {
+        let message = message.into();
+        eprintln!("{} {message}", "warning:".yellow());
+        self.messages.borrow_mut().push(message);
+    }
+
+    pub fn count(&self) -> usize {
+        self.messages.borrow().len()
+    }
+
+    pub fn messages(&self) -> Vec<String> {
+        self.messages.borrow().clone()
+    }
+
+    /// Fails if any warning was recorded.
+    pub fn check(&self) -> Result<()> {
+        match self.count() {
+            0 => Ok(()),
+            1 => anyhow::bail!("Aborting: 1 warning was emitted (--abort-on-warning)"),
+            n => anyhow::bail!("Aborting: {n} warnings were emitted (--abort-on-warning)"),
+        }
+    }
+}
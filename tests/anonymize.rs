@@ -0,0 +1,202 @@
+mod common;
+
+use common::{ANONYMOUS, REAL, TestRepo};
+
+const EARLIER: &str = "Earlier Anon <earlier@anon.example.org>";
+
+#[test]
+fn identity_from_commit_reuses_its_author() {
+    let repo = TestRepo::new();
+    let published = repo.commit_as("a.txt", "a", EARLIER);
+    repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "--identity-from-commit", &published])
+        .args(["anonymize", "--since", &published])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [EARLIER, EARLIER]);
+    assert_eq!(repo.committers("main")[0], EARLIER);
+}
+
+#[test]
+fn identity_from_commit_must_exist() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+
+    repo.git_anon()
+        .args([
+            "--yes",
+            "--identity-from-commit",
+            "no-such-rev",
+            "anonymize",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("no-such-rev"));
+    assert_eq!(repo.authors("main"), [REAL]);
+}
+
+#[test]
+fn backup_bundle_keeps_the_original_history_off_the_ref_graph() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let tip = repo.commit("b.txt", "b");
+    let bundle = repo.scratch("original.bundle");
+
+    repo.git_anon()
+        .args(["--yes", "anonymize", "--backup-bundle"])
+        .arg(&bundle)
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [ANONYMOUS, ANONYMOUS]);
+    assert_eq!(repo.git(&["branch", "--list"]), "* main");
+
+    let restored = repo.scratch("restored");
+    repo.git(&[
+        "clone",
+        "--quiet",
+        bundle.to_str().unwrap(),
+        restored.to_str().unwrap(),
+    ]);
+    let original = "refs/remotes/origin/main";
+    assert_eq!(repo.git_in(&restored, &["rev-parse", original]), tip);
+    assert_eq!(
+        repo.git_in(&restored, &["log", "--format=%an <%ae>", original]),
+        format!("{REAL}\n{REAL}")
+    );
+}
+
+#[test]
+fn on_conflict_decides_what_happens_to_an_existing_backup() {
+    for mode in ["abort", "overwrite", "rename"] {
+        let repo = TestRepo::new();
+        let first = repo.commit("a.txt", "a");
+        let tip = repo.commit("b.txt", "b");
+        repo.git(&["branch", "saved", &first]);
+
+        let run = repo
+            .git_anon()
+            .args(["--yes", "anonymize", "--backup-name", "saved"])
+            .args(["--on-conflict", mode])
+            .assert();
+
+        match mode {
+            "abort" => {
+                run.failure().stderr(predicates::str::contains(
+                    "Backup branch 'saved' already exists",
+                ));
+                assert_eq!(repo.rev("main"), tip);
+                assert_eq!(repo.rev("saved"), first);
+            }
+            "overwrite" => {
+                run.success();
+                assert_eq!(repo.rev("saved"), tip);
+            }
+            _ => {
+                run.success();
+                assert_eq!(repo.rev("saved"), first);
+                assert_eq!(repo.rev("saved-2"), tip);
+            }
+        }
+    }
+}
+
+#[test]
+fn merge_across_the_since_boundary_keeps_its_original_parent() {
+    let repo = TestRepo::new();
+    let root = repo.commit("a.txt", "a");
+    repo.git(&["checkout", "--quiet", "-b", "side"]);
+    let side = repo.commit("side.txt", "side");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.commit("b.txt", "b");
+    repo.git(&["merge", "--quiet", "--no-ff", "--no-edit", "side"]);
+
+    repo.git_anon()
+        .args(["--yes", "anonymize", "--since", &side])
+        .assert()
+        .success();
+
+    assert_eq!(repo.log("main", "%H").len(), 4);
+    assert_eq!(repo.rev("main^2"), side);
+    assert_eq!(repo.rev("main^1^"), root);
+    assert_eq!(repo.authors("main")[0], ANONYMOUS);
+    assert_eq!(repo.authors("main^1"), [ANONYMOUS, REAL]);
+}
+
+#[test]
+fn reflog_clear_drops_original_identities_from_reflogs() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "anonymize", "--reflog", "clear"])
+        .assert()
+        .success();
+
+    for refname in ["main", "HEAD"] {
+        let reflog = repo.git(&["reflog", "show", "--format=%gn <%ge>|%gs", refname]);
+        assert!(!reflog.contains("Jane Doe"), "{refname}: {reflog}");
+        assert!(
+            reflog.lines().all(|entry| entry == format!("{ANONYMOUS}|")),
+            "{refname}: {reflog}"
+        );
+    }
+}
+
+#[test]
+fn commits_after_until_keep_their_identity() {
+    let repo = TestRepo::new();
+    let first = repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+    let until = repo.commit("c.txt", "c");
+    repo.commit("d.txt", "d");
+
+    repo.git_anon()
+        .args(["--yes", "anonymize", "--since", &first, "--until", &until])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [REAL, ANONYMOUS, ANONYMOUS, REAL]);
+    assert_eq!(repo.rev("main~3"), first);
+    assert_ne!(repo.rev("main~1"), until);
+}
+
+#[test]
+fn until_must_be_an_ancestor_of_the_branch() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["checkout", "--quiet", "-b", "side"]);
+    let side = repo.commit("side.txt", "side");
+    repo.git(&["checkout", "--quiet", "main"]);
+    let tip = repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "anonymize", "--until", &side])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!(
+            "--until {side} is not an ancestor of main"
+        )));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn since_must_be_an_ancestor_of_until() {
+    let repo = TestRepo::new();
+    let first = repo.commit("a.txt", "a");
+    let second = repo.commit("b.txt", "b");
+    let tip = repo.commit("c.txt", "c");
+
+    repo.git_anon()
+        .args(["--yes", "anonymize", "--since", &second, "--until", &first])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!(
+            "--since {second} is not an ancestor of {first}"
+        )));
+    assert_eq!(repo.rev("main"), tip);
+}
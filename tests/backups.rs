@@ -0,0 +1,136 @@
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn backups_are_listed_newest_first_and_pruned() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    for branch in [
+        "backup-main-1700000100",
+        "backup-feature-x-1700000300",
+        "pre-clean-backup-1700000200",
+        "backup-notes",
+    ] {
+        repo.git(&["branch", branch]);
+    }
+
+    let output = repo.git_anon().arg("backups").output().unwrap();
+    let listed: Vec<String> = common::stdout(&output)
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("* "))
+        .map(|line| line.split_once(", made").unwrap().0.to_string())
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            "backup-feature-x-1700000300  feature-x backup",
+            "pre-clean-backup-1700000200  pre-clean backup",
+            "backup-main-1700000100  main backup",
+        ]
+    );
+
+    repo.git_anon()
+        .args(["--yes", "backups", "--prune", "--keep", "1"])
+        .assert()
+        .success();
+    assert_eq!(
+        repo.git(&["branch", "--list", "--format=%(refname:short)"]),
+        "backup-feature-x-1700000300\nbackup-notes\nmain"
+    );
+}
+
+#[test]
+fn branches_without_a_backup_timestamp_are_not_pruned() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    for branch in [
+        "backup-release-1",
+        "backup-main-12",
+        "backup-main-1700000100-2",
+    ] {
+        repo.git(&["branch", branch]);
+    }
+
+    repo.git_anon()
+        .args(["--yes", "backups", "--prune", "--keep", "0"])
+        .assert()
+        .success();
+    assert_eq!(
+        repo.git(&["branch", "--list", "--format=%(refname:short)"]),
+        "backup-main-12\nbackup-release-1\nmain"
+    );
+}
+
+#[test]
+fn restore_resets_the_branch_to_the_named_backup() {
+    let repo = TestRepo::new();
+    let saved = repo.commit("a.txt", "a");
+    repo.git(&["branch", "saved"]);
+    repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "restore", "--branch", "saved"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.rev("main"), saved);
+    assert!(repo.git(&["status", "--porcelain"]).is_empty());
+    assert!(!repo.path.join("b.txt").exists());
+    assert_eq!(repo.rev("saved"), saved);
+}
+
+#[test]
+fn restore_asks_for_a_branch_when_several_backups_exist() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["branch", "backup-main-1700000100"]);
+    repo.git(&["branch", "backup-main-1700000200"]);
+    let tip = repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "restore"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "2 backup branches found (backup-main-1700000200, backup-main-1700000100); pass --branch to choose one",
+        ));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn restore_dry_run_changes_nothing() {
+    let repo = TestRepo::new();
+    let saved = repo.commit("a.txt", "a");
+    repo.git(&["branch", "backup-main-1700000100"]);
+    let tip = repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--dry-run", "restore"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(format!(
+            "main would be reset to {saved}"
+        )));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn restore_refuses_a_dirty_tree() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["branch", "saved"]);
+    let tip = repo.commit("b.txt", "b");
+    repo.write("b.txt", "changed");
+
+    repo.git_anon()
+        .args(["--yes", "restore", "--branch", "saved"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Uncommitted changes detected"));
+    assert_eq!(repo.rev("main"), tip);
+    assert_eq!(
+        std::fs::read_to_string(repo.path.join("b.txt")).unwrap(),
+        "changed"
+    );
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::{ANONYMOUS, TestRepo};
+
+fn has_object(repo: &TestRepo, oid: &str) -> bool {
+    repo.git_ok(&["cat-file", "-e", oid])
+}
+
+#[test]
+fn no_gc_leaves_the_original_objects() {
+    let repo = TestRepo::new();
+    let original = repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "clean", "--no-gc", "--no-backup"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+    assert!(has_object(&repo, &original));
+}
+
+#[test]
+fn gc_removes_the_original_objects() {
+    let repo = TestRepo::new();
+    let original = repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "clean", "--no-backup"])
+        .assert()
+        .success();
+
+    assert!(!has_object(&repo, &original));
+}
+
+/// Puts a `git` in front of the real one that logs its arguments to the
+/// returned file.
+fn log_git_calls(repo: &TestRepo) -> std::path::PathBuf {
+    let log = repo.scratch("git.log");
+    repo.install(
+        "git",
+        &format!(
+            "#!/bin/sh\necho \"$@\" >> '{}'\nexec /usr/bin/git \"$@\"\n",
+            log.display()
+        ),
+    );
+    log
+}
+
+#[test]
+fn gc_mode_picks_the_gc_arguments() {
+    for (mode, expected, unexpected) in [
+        ("minimal", "prune --expire=now", "gc"),
+        ("default", "gc --prune=now", "--aggressive"),
+        (
+            "aggressive",
+            "gc --prune=now --aggressive",
+            "prune --expire",
+        ),
+    ] {
+        let repo = TestRepo::new();
+        repo.commit("a.txt", "a");
+        let log = log_git_calls(&repo);
+
+        repo.git_anon()
+            .args(["--yes", "clean", "--no-backup", "--gc-mode", mode])
+            .assert()
+            .success();
+
+        let calls = std::fs::read_to_string(log).unwrap();
+        let cleanup: Vec<&str> = calls
+            .lines()
+            // Skip the `-C <workdir>` every call starts with.
+            .filter_map(|call| call.splitn(3, ' ').nth(2))
+            .filter(|call| call.starts_with("gc") || call.starts_with("prune"))
+            .collect();
+        assert_eq!(cleanup, [expected], "--gc-mode {mode}");
+        assert!(!cleanup[0].contains(unexpected));
+    }
+}
+
+#[test]
+fn stashes_are_reported_and_dropped_only_on_request() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    std::fs::write(repo.path.join("a.txt"), "work in progress").unwrap();
+    repo.git(&["stash", "--quiet"]);
+    let unanonymized = "1 stash entries keep the original identities and are not anonymized";
+
+    repo.git_anon()
+        .arg("status")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(unanonymized));
+
+    repo.git_anon()
+        .args(["--yes", "clean", "--no-backup", "--no-gc"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(unanonymized));
+    assert!(repo.git_ok(&["rev-parse", "--verify", "--quiet", "refs/stash"]));
+
+    repo.git_anon()
+        .args(["--yes", "clean", "--no-backup", "--no-gc", "--drop-stashes"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("1 stash entries will be dropped"));
+    assert!(!repo.git_ok(&["rev-parse", "--verify", "--quiet", "refs/stash"]));
+}
+
+#[test]
+fn ctrl_c_during_gc_releases_the_lock() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let started = repo.scratch("gc-started");
+    repo.install(
+        "git",
+        &format!(
+            "#!/bin/sh\ncase \" $* \" in *' gc '*) touch '{}'; exec sleep 10;; esac\n\
+             exec /usr/bin/git \"$@\"\n",
+            started.display()
+        ),
+    );
+
+    let mut clean = repo
+        .git_anon_process()
+        .args(["--yes", "clean", "--no-backup"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !started.exists() {
+        assert!(std::time::Instant::now() < deadline, "gc never started");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    let lock = repo.path.join(".git/git-anon.lock");
+    assert!(lock.exists());
+    std::process::Command::new("kill")
+        .args(["-INT", &clean.id().to_string()])
+        .status()
+        .unwrap();
+
+    assert_eq!(clean.wait().unwrap().code(), Some(130));
+    assert!(!lock.exists());
+}
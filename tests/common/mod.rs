@@ -0,0 +1,321 @@
+//! A throwaway repository the integration tests build history in and run
+//! git-anon against, isolated from the user's git and git-anon config.
+
+#![allow(dead_code)]
+
+use assert_cmd::Command;
+use std::cell::Cell;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use tempfile::TempDir;
+
+/// The identity test commits are made with unless they name another.
+pub const REAL: &str = "Jane Doe <jane@corp.example>";
+/// The identity git-anon's default config rewrites commits to.
+pub const ANONYMOUS: &str = "Anonymous <anonymous@example.com>";
+
+/// Commit dates start here and move a minute per commit, so histories are
+/// ordered and reproducible.
+const EPOCH: i64 = 1_700_000_000;
+
+pub struct TestRepo {
+    /// Holds the repository, `home` and any remotes; removed on drop.
+    dir: TempDir,
+    pub path: PathBuf,
+    /// `$HOME` and `$XDG_CONFIG_HOME` of every command, so no user config leaks in.
+    home: PathBuf,
+    commits: Cell<i64>,
+}
+
+impl TestRepo {
+    /// A repository with `main` checked out and no commits.
+    pub fn new() -> Self {
+        Self::init(&[])
+    }
+
+    /// A bare repository with `main` as HEAD and no commits.
+    pub fn bare() -> Self {
+        Self::init(&["--bare"])
+    }
+
+    fn init(flags: &[&str]) -> Self {
+        let dir = TempDir::new().expect("create temp dir");
+        let path = dir.path().join("repo");
+        let home = dir.path().join("home");
+        fs::create_dir_all(&path).unwrap();
+        fs::create_dir_all(&home).unwrap();
+        let repo = Self {
+            dir,
+            path,
+            home,
+            commits: Cell::new(0),
+        };
+        repo.git(&[&["init", "--quiet", "--initial-branch=main"], flags].concat());
+        // Tags and other objects git makes itself are by the real identity too.
+        let (name, email) = split_identity(REAL);
+        repo.git(&["config", "user.name", name]);
+        repo.git(&["config", "user.email", email]);
+        repo
+    }
+
+    /// Runs git in the repository, failing the test if it fails. Returns
+    /// stdout without the trailing newline.
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = self
+            .git_command(&self.path)
+            .args(args)
+            .output()
+            .expect("run git");
+        assert!(
+            output.status.success(),
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim_end()
+            .to_string()
+    }
+
+    /// Whether git succeeds in the repository, for commands expected to fail.
+    pub fn git_ok(&self, args: &[&str]) -> bool {
+        self.git_command(&self.path)
+            .args(args)
+            .output()
+            .expect("run git")
+            .status
+            .success()
+    }
+
+    fn git_command(&self, dir: &Path) -> process::Command {
+        let mut command = process::Command::new("git");
+        command.arg("-C").arg(dir);
+        self.isolate(&mut command);
+        command
+    }
+
+    fn isolate(&self, command: &mut process::Command) {
+        command
+            .env("HOME", &self.home)
+            .env("XDG_CONFIG_HOME", self.home.join(".config"))
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("GIT_ANON_NAME")
+            .env_remove("GIT_ANON_EMAIL")
+            .env_remove("GIT_DIR")
+            .env_remove("GIT_WORK_TREE");
+    }
+
+    /// Writes `file` and commits it as `REAL`.
+    pub fn commit(&self, file: &str, content: &str) -> String {
+        self.commit_as(file, content, REAL)
+    }
+
+    /// Writes `file` and commits it with `identity` (`Name <email>`) as both
+    /// author and committer. Returns the new commit's OID.
+    pub fn commit_as(&self, file: &str, content: &str, identity: &str) -> String {
+        let n = self.commits.get() + 1;
+        self.commits.set(n);
+        self.commit_at(file, content, identity, EPOCH + n * 60)
+    }
+
+    /// Like `commit_as`, dated `seconds` since the epoch.
+    pub fn commit_at(&self, file: &str, content: &str, identity: &str, seconds: i64) -> String {
+        self.write(file, content);
+        self.commit_staged(identity, identity, seconds, &format!("Change {file}"))
+    }
+
+    /// Writes `file` and commits it with different author and committer,
+    /// as a rebased or applied patch has.
+    pub fn commit_by(&self, file: &str, content: &str, author: &str, committer: &str) -> String {
+        let n = self.commits.get() + 1;
+        self.commits.set(n);
+        self.write(file, content);
+        self.commit_staged(author, committer, EPOCH + n * 60, &format!("Change {file}"))
+    }
+
+    /// Writes `file` and stages it.
+    pub fn write(&self, file: &str, content: &str) {
+        let path = self.path.join(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, content).unwrap();
+        self.git(&["add", "--", file]);
+    }
+
+    /// Commits whatever is staged, with `message`.
+    pub fn commit_staged(
+        &self,
+        author: &str,
+        committer: &str,
+        seconds: i64,
+        message: &str,
+    ) -> String {
+        let (author_name, author_email) = split_identity(author);
+        let (committer_name, committer_email) = split_identity(committer);
+        let date = format!("@{seconds} +0000");
+        let output = self
+            .git_command(&self.path)
+            .args([
+                "commit",
+                "--quiet",
+                "--allow-empty",
+                "--no-gpg-sign",
+                "-m",
+                message,
+            ])
+            .env("GIT_AUTHOR_NAME", author_name)
+            .env("GIT_AUTHOR_EMAIL", author_email)
+            .env("GIT_COMMITTER_NAME", committer_name)
+            .env("GIT_COMMITTER_EMAIL", committer_email)
+            .env("GIT_AUTHOR_DATE", &date)
+            .env("GIT_COMMITTER_DATE", &date)
+            .output()
+            .expect("run git commit");
+        assert!(
+            output.status.success(),
+            "git commit failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        self.rev("HEAD")
+    }
+
+    /// The commit `rev` resolves to.
+    pub fn rev(&self, rev: &str) -> String {
+        self.git(&["rev-parse", &format!("{rev}^{{commit}}")])
+    }
+
+    /// `Name <email>` of the author of each commit in `rev`'s history,
+    /// newest first.
+    pub fn authors(&self, rev: &str) -> Vec<String> {
+        self.log(rev, "%an <%ae>")
+    }
+
+    /// `Name <email>` of the committer of each commit in `rev`'s history,
+    /// newest first.
+    pub fn committers(&self, rev: &str) -> Vec<String> {
+        self.log(rev, "%cn <%ce>")
+    }
+
+    /// `git log --format=<format> <rev>`, one entry per commit.
+    pub fn log(&self, rev: &str, format: &str) -> Vec<String> {
+        let log = self.git(&["log", &format!("--format={format}"), rev]);
+        log.lines().map(str::to_string).collect()
+    }
+
+    /// git-anon run in this repository, with its config in the test home.
+    pub fn git_anon(&self) -> Command {
+        Command::from_std(self.git_anon_process())
+    }
+
+    /// `git_anon` as a plain process, for tests that signal it while it runs.
+    pub fn git_anon_process(&self) -> process::Command {
+        let mut command = process::Command::new(assert_cmd::cargo::cargo_bin("git-anon"));
+        self.isolate(&mut command);
+        command.env("PATH", self.search_path());
+        command.arg("--repo").arg(&self.path);
+        command
+    }
+
+    /// Installs an executable `script` as `name` in a directory that comes
+    /// first on git-anon's `PATH`, to stub out the programs it runs.
+    pub fn install(&self, name: &str, script: &str) {
+        let bin = self.scratch("bin");
+        fs::create_dir_all(&bin).unwrap();
+        let path = bin.join(name);
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    fn search_path(&self) -> String {
+        let inherited = std::env::var("PATH").unwrap_or_default();
+        format!("{}:{inherited}", self.scratch("bin").display())
+    }
+
+    /// Creates an unencrypted ed25519 key pair `name`/`name.pub` in the scratch
+    /// directory and returns the private key's path.
+    pub fn ssh_keygen(&self, name: &str) -> PathBuf {
+        let key = self.scratch(name);
+        let status = process::Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"])
+            .arg(&key)
+            .status()
+            .expect("run ssh-keygen");
+        assert!(status.success());
+        key
+    }
+
+    /// The loose object file of `oid`, for tests that damage the repository.
+    pub fn object_path(&self, oid: &str) -> PathBuf {
+        self.path
+            .join(".git/objects")
+            .join(&oid[..2])
+            .join(&oid[2..])
+    }
+
+    /// Where git-anon reads its config from.
+    pub fn config_path(&self) -> PathBuf {
+        self.home.join(".config/git-anon/config.toml")
+    }
+
+    /// Replaces git-anon's config with `toml`.
+    pub fn write_config(&self, toml: &str) {
+        let path = self.config_path();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, toml).unwrap();
+    }
+
+    /// A file path inside the test's temporary directory, outside the repository.
+    pub fn scratch(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Adds an empty bare repository as remote `name` and returns its path.
+    pub fn add_remote(&self, name: &str) -> PathBuf {
+        let path = self.scratch(&format!("{name}.git"));
+        let output = self
+            .git_command(self.dir.path())
+            .args(["init", "--quiet", "--bare", "--initial-branch=main"])
+            .arg(&path)
+            .output()
+            .expect("run git init");
+        assert!(output.status.success(), "git init --bare failed");
+        self.git(&["remote", "add", name, path.to_str().unwrap()]);
+        path
+    }
+
+    /// Runs git in another repository (such as a remote) with the same isolation.
+    pub fn git_in(&self, dir: &Path, args: &[&str]) -> String {
+        let output = self.git_command(dir).args(args).output().expect("run git");
+        assert!(
+            output.status.success(),
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim_end()
+            .to_string()
+    }
+}
+
+fn split_identity(identity: &str) -> (&str, &str) {
+    identity
+        .strip_suffix('>')
+        .and_then(|identity| identity.split_once(" <"))
+        .expect("identity in 'Name <email>' form")
+}
+
+/// stdout of a finished git-anon run, asserting it succeeded.
+pub fn stdout(output: &process::Output) -> String {
+    assert!(
+        output.status.success(),
+        "git-anon failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
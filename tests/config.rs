@@ -0,0 +1,329 @@
+mod common;
+
+use common::TestRepo;
+use git_anon::config::{Config, DEFAULT_IDENTITY, Identity, RemoteConfig, validate_email};
+use std::fs;
+
+#[test]
+fn import_merges_top_level_settings() {
+    let repo = TestRepo::new();
+    repo.write_config(
+        r#"
+version = 2
+forbidden_identities = ["*@corp.example"]
+squash_message = "Snapshot"
+"#,
+    );
+    let team = repo.scratch("team.toml");
+    fs::write(
+        &team,
+        r#"
+version = 2
+forbidden_identities = ["Jane Doe"]
+abort_on_warning = true
+backup_remote = "private"
+pseudonym_salt = "team"
+
+[signing]
+format = "ssh"
+"#,
+    )
+    .unwrap();
+
+    repo.git_anon()
+        .args(["--yes", "config", "import"])
+        .arg(&team)
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("backup_remote: none -> private"));
+
+    let merged: toml::Value =
+        toml::from_str(&fs::read_to_string(repo.config_path()).unwrap()).expect("merged config");
+    let forbidden: Vec<&str> = merged["forbidden_identities"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_str().unwrap())
+        .collect();
+    assert_eq!(forbidden, ["*@corp.example", "Jane Doe"]);
+    assert_eq!(merged["abort_on_warning"].as_bool(), Some(true));
+    assert_eq!(merged["backup_remote"].as_str(), Some("private"));
+    assert_eq!(merged["pseudonym_salt"].as_str(), Some("team"));
+    assert_eq!(merged["squash_message"].as_str(), Some("Snapshot"));
+    assert_eq!(merged["signing"]["format"].as_str(), Some("ssh"));
+}
+
+#[test]
+fn unreadable_keyring_identity_is_a_warning_not_an_error() {
+    let repo = TestRepo::new();
+    repo.write_config(
+        r#"
+version = 2
+
+[identities.vault]
+keyring = true
+"#,
+    );
+
+    repo.git_anon()
+        .env("DBUS_SESSION_BUS_ADDRESS", "unix:path=/nonexistent")
+        .args(["config", "list-identities"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("could not read vault ("))
+        .stderr(predicates::str::contains(
+            "unavailable until the keyring is reachable",
+        ));
+}
+
+#[test]
+fn dry_run_config_mutators_write_nothing() {
+    let repo = TestRepo::new();
+    repo.git_anon()
+        .args([
+            "--dry-run",
+            "config",
+            "set-identity",
+            "Ghost",
+            "ghost@anon.example.org",
+        ])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Configuration not written"));
+    assert!(!repo.config_path().exists());
+
+    let config = r#"version = 2
+
+[remotes.public]
+name = "origin"
+identity = "anonymous_identity"
+"#;
+    repo.write_config(config);
+    for action in [
+        &["set-identity", "Ghost", "ghost@anon.example.org"][..],
+        &["add-remote", "mirror", "mirror"],
+        &["remove-remote", "public"],
+    ] {
+        repo.git_anon()
+            .args(["--yes", "--dry-run", "config"])
+            .args(action)
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("Configuration not written"));
+        assert_eq!(fs::read_to_string(repo.config_path()).unwrap(), config);
+    }
+}
+
+/// Points git-anon's config at a policy file holding `policy` and returns
+/// the file.
+fn with_policy(repo: &TestRepo, policy: &str) -> std::path::PathBuf {
+    let path = repo.scratch("policy.json");
+    fs::write(&path, policy).unwrap();
+    repo.write_config(&format!(
+        "version = 2\npolicy_url = \"file://{}\"\n",
+        path.display()
+    ));
+    path
+}
+
+#[test]
+fn policy_url_overlays_the_config_and_is_cached() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let policy = with_policy(
+        &repo,
+        r#"{"anonymous_identity": {"name": "Org Anon", "email": "anon@org.example"}}"#,
+    );
+
+    repo.git_anon().args(["--yes", "squash"]).assert().success();
+    assert_eq!(repo.authors("main"), ["Org Anon <anon@org.example>"]);
+
+    fs::remove_file(policy).unwrap();
+    repo.commit("b.txt", "b");
+    repo.git_anon()
+        .args(["--yes", "squash"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("using the cached copy"));
+    assert_eq!(repo.authors("main"), ["Org Anon <anon@org.example>"]);
+}
+
+#[test]
+fn policy_required_domains_reject_other_identities() {
+    let repo = TestRepo::new();
+    let tip = repo.commit("a.txt", "a");
+    with_policy(&repo, r#"{"required_domains": ["@org.example"]}"#);
+
+    repo.git_anon()
+        .args(["--yes", "squash"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Identity Anonymous <anonymous@example.com> is not allowed by the policy; \
+             emails must use one of: org.example",
+        ));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn policy_with_unknown_keys_is_rejected() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    with_policy(&repo, r#"{"anonymous_identiy": {}}"#);
+
+    repo.git_anon()
+        .args(["--yes", "squash"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Failed to parse policy from file://",
+        ))
+        .stderr(predicates::str::contains("anonymous_identiy"));
+}
+
+#[test]
+fn v1_config_is_migrated_and_kept_as_a_backup() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let v1 = r#"
+[default_identity]
+name = "Old Anon"
+email = "old@anon.example.org"
+
+[remotes.public]
+name = "origin"
+identity = "default_identity"
+"#;
+    repo.write_config(v1);
+
+    repo.git_anon()
+        .args(["--yes", "squash"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("from config version 1 to 2"));
+
+    assert_eq!(repo.authors("main"), ["Old Anon <old@anon.example.org>"]);
+    let backup = repo.config_path().with_extension("toml.v1");
+    assert_eq!(fs::read_to_string(backup).unwrap(), v1);
+    let migrated: toml::Table = fs::read_to_string(repo.config_path())
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert_eq!(migrated["version"].as_integer(), Some(2));
+    assert_eq!(
+        migrated["anonymous_identity"]["email"].as_str(),
+        Some("old@anon.example.org")
+    );
+    assert!(!migrated.contains_key("default_identity"));
+    assert_eq!(
+        migrated["remotes"]["public"]["identity"].as_str(),
+        Some("anonymous_identity")
+    );
+}
+
+#[test]
+fn newer_config_version_is_refused() {
+    let repo = TestRepo::new();
+    repo.write_config("version = 99\n");
+
+    repo.git_anon()
+        .args(["config", "show"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "config version 99 is newer than this git-anon supports",
+        ));
+}
+
+#[test]
+fn saved_config_reloads_unchanged() {
+    let repo = TestRepo::new();
+    let mut config = Config::default();
+    config.anonymous_identity.name = "Saved Anon".to_string();
+    config.anonymous_identity.email = "saved@anon.example.org".to_string();
+    config.identities.insert(
+        "work".to_string(),
+        Identity {
+            name: "Work Anon".to_string(),
+            email: "work@anon.example.org".to_string(),
+            keyring: false,
+            signing_key: None,
+        },
+    );
+    config.remotes.insert(
+        "public".to_string(),
+        RemoteConfig {
+            name: "origin".to_string(),
+            identity: "work".to_string(),
+            identity_file: None,
+        },
+    );
+    config
+        .branch_identities
+        .insert("release/*".to_string(), DEFAULT_IDENTITY.to_string());
+    let path = repo.scratch("saved.toml");
+
+    config.save_to(&path).unwrap();
+    let reloaded = Config::load_from(&path).unwrap();
+
+    // Compare as tables; the maps serialize in HashMap order.
+    let table = |config: &Config| config.to_toml().unwrap().parse::<toml::Table>().unwrap();
+    assert_eq!(table(&reloaded), table(&config));
+    let identity = reloaded.get_identity(DEFAULT_IDENTITY).unwrap();
+    assert_eq!(identity.email, "saved@anon.example.org");
+}
+
+#[test]
+fn default_identity_key_is_read_as_the_anonymous_identity() {
+    let repo = TestRepo::new();
+    let path = repo.scratch("aliased.toml");
+    fs::write(
+        &path,
+        "version = 2\n\n[default_identity]\nname = \"Alias\"\nemail = \"alias@anon.example.org\"\n",
+    )
+    .unwrap();
+
+    let config = Config::load_from(&path).unwrap();
+    assert_eq!(config.anonymous_identity.name, "Alias");
+    assert_eq!(config.anonymous_identity.email, "alias@anon.example.org");
+}
+
+#[test]
+fn malformed_emails_are_rejected() {
+    for (email, reason) in [
+        ("", "cannot be empty"),
+        ("a@", "needs a domain"),
+        ("@b.example", "needs a name before the @"),
+        ("a@localhost", "needs a domain"),
+        ("a@b..example", "needs a domain"),
+        ("a@b@c.example", "single @"),
+        ("a b@c.example", "cannot contain spaces"),
+        ("no-at.example", "must contain @"),
+    ] {
+        let error = validate_email(email).unwrap_err().to_string();
+        assert!(error.contains(reason), "{email:?}: {error}");
+    }
+    validate_email("anon+tag@mail.example.org").unwrap();
+}
+
+#[test]
+fn set_identity_and_stored_identities_share_the_validation() {
+    let repo = TestRepo::new();
+    repo.git_anon()
+        .args(["--yes", "config", "set-identity", "Anon", "anon@"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("needs a domain"));
+    assert!(
+        !fs::read_to_string(repo.config_path())
+            .unwrap()
+            .contains("anon@\"")
+    );
+
+    repo.write_config("version = 2\n\n[identities.bad]\nname = \"Bad\"\nemail = \"@b.example\"\n");
+    repo.git_anon()
+        .args(["config", "validate"])
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("needs a name before the @"));
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::{ANONYMOUS, TestRepo};
+use std::fs;
+
+/// A three-commit history whose root commit object is gone.
+fn missing_root() -> (TestRepo, String) {
+    let repo = TestRepo::new();
+    let root = repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+    repo.commit("c.txt", "c");
+    fs::remove_file(repo.object_path(&root)).unwrap();
+    (repo, root)
+}
+
+#[test]
+fn missing_object_fails_the_walk() {
+    let (repo, _) = missing_root();
+
+    repo.git_anon()
+        .args(["--yes", "anonymize"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("try --skip-corrupt"));
+}
+
+#[test]
+fn skip_corrupt_rewrites_the_readable_commits() {
+    let (repo, root) = missing_root();
+
+    repo.git_anon()
+        .args(["--yes", "--skip-corrupt", "anonymize"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(&root));
+
+    assert_eq!(repo.authors("main"), [ANONYMOUS, ANONYMOUS]);
+}
+
+#[test]
+fn verify_objects_refuses_a_damaged_object_before_rewriting() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let tip = repo.commit("b.txt", "b");
+    let blob = repo.object_path(&repo.git(&["rev-parse", "HEAD:b.txt"]));
+    // Loose objects are read-only; replace the file rather than writing to it.
+    fs::remove_file(&blob).unwrap();
+    fs::write(&blob, "not a zlib stream").unwrap();
+
+    repo.git_anon()
+        .args(["--yes", "--verify-objects", "anonymize"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Object verification failed; repair the repository before rewriting it",
+        ));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn verify_objects_passes_an_intact_repository() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+
+    repo.git_anon()
+        .args(["--yes", "--verify-objects", "anonymize"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Object database is intact"));
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+}
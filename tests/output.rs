@@ -0,0 +1,135 @@
+mod common;
+
+use common::{REAL, TestRepo, stdout};
+use git_anon::report::Report;
+
+#[test]
+fn json_stdout_holds_only_the_outcome() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+
+    let output = repo
+        .git_anon()
+        .args(["--yes", "--json", "--profile", "--summary-json", "squash"])
+        .output()
+        .unwrap();
+    let stdout = stdout(&output);
+    let outcome: serde_json::Value = serde_json::from_str(&stdout).expect("one JSON object");
+    assert_eq!(outcome["operation"], "squash");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Profile:"));
+}
+
+#[test]
+fn non_repository_is_a_friendly_error() {
+    let repo = TestRepo::new();
+    std::fs::remove_dir_all(repo.path.join(".git")).unwrap();
+
+    for command in [&["status"][..], &["--yes", "squash"], &["--yes", "clean"]] {
+        repo.git_anon()
+            .args(command)
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(format!(
+                "Not a git repository: {}",
+                repo.path.display()
+            )));
+    }
+}
+
+#[test]
+fn report_out_round_trips() {
+    let repo = TestRepo::new();
+    repo.commit("big.bin", &"x".repeat(4096));
+    repo.commit("b.txt", "b");
+    repo.git(&["tag", "v1.0"]);
+    repo.add_remote("origin");
+    let path = repo.scratch("report.json");
+
+    repo.git_anon()
+        .args([
+            "--yes",
+            "--json",
+            "--warn-large-blobs",
+            "1K",
+            "--report-out",
+        ])
+        .arg(&path)
+        .args(["push", "origin", "--include-tags"])
+        .assert()
+        .success();
+
+    let report = Report::read(&path).unwrap();
+    assert_eq!(report.operation, "push");
+    assert_eq!(report.branch, "main");
+    assert_eq!(report.commits_rewritten, 2);
+    assert_eq!(report.identities_replaced, [REAL]);
+    assert_eq!(report.tags, ["v1.0"]);
+    assert_eq!(report.moved_tags, ["v1.0"]);
+    assert!(report.mapping_digest.is_some());
+    assert!(!report.timings.is_empty());
+    assert!(
+        report
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("larger than 1024 bytes"))
+    );
+
+    let copy = repo.scratch("copy.json");
+    report.write(&copy).unwrap();
+    assert_eq!(Report::read(&copy).unwrap(), report);
+}
+
+#[test]
+fn anonymize_report_lists_replaced_identities_and_tags() {
+    let repo = TestRepo::new();
+    let alice = "Alice <alice@corp.example>";
+    repo.commit_as("a.txt", "a", alice);
+    repo.commit("b.txt", "b");
+    repo.git(&["tag", "v1.0"]);
+    let path = repo.scratch("report.json");
+
+    repo.git_anon()
+        .args(["--yes", "--report-out"])
+        .arg(&path)
+        .arg("anonymize")
+        .assert()
+        .success();
+
+    let report = Report::read(&path).unwrap();
+    assert_eq!(report.operation, "anonymize");
+    assert_eq!(report.identities_replaced, [alice, REAL]);
+    assert_eq!(report.tags, ["v1.0"]);
+}
+
+/// The `--summary-json` mapping digest of pushing a two-commit history
+/// whose second file holds `content`.
+fn push_digest(content: &str) -> String {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.commit("b.txt", content);
+    repo.add_remote("origin");
+
+    let output = repo
+        .git_anon()
+        .args([
+            "--yes",
+            "--summary-json",
+            "push",
+            "origin",
+            "--preserve-dates",
+        ])
+        .output()
+        .unwrap();
+    let stdout = stdout(&output);
+    let summary = stdout.lines().find(|line| line.starts_with('{')).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(summary).unwrap();
+    assert_eq!(summary["commits_rewritten"], 2);
+    summary["mapping_digest"].as_str().unwrap().to_string()
+}
+
+#[test]
+fn identical_runs_have_identical_mapping_digests() {
+    assert_eq!(push_digest("b"), push_digest("b"));
+    assert_ne!(push_digest("b"), push_digest("other"));
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use common::{ANONYMOUS, REAL, TestRepo};
+use std::fs;
+
+#[test]
+fn reviewed_plan_round_trips() {
+    let repo = TestRepo::new();
+    let first = repo.commit("a.txt", "a");
+    repo.git(&["tag", "v1.0"]);
+    let second = repo.commit("b.txt", "b");
+    repo.add_remote("origin");
+    let plan = repo.scratch("plan.json");
+
+    repo.git_anon()
+        .args(["--dry-run", "--plan-out"])
+        .arg(&plan)
+        .args(["push", "origin"])
+        .assert()
+        .success();
+
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&plan).unwrap()).unwrap();
+    assert_eq!(written["operation"], "push");
+    let commits: Vec<&str> = written["commits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|commit| commit["oid"].as_str().unwrap())
+        .collect();
+    assert_eq!(commits, [second.as_str(), first.as_str()]);
+    assert_eq!(written["identity_mapping"][0]["original"], REAL);
+    assert_eq!(written["identity_mapping"][0]["replacement"], ANONYMOUS);
+    assert_eq!(written["tags"][0]["name"], "v1.0");
+
+    repo.git_anon()
+        .args(["--yes", "--plan-in"])
+        .arg(&plan)
+        .args(["push", "origin"])
+        .assert()
+        .success();
+    assert_eq!(repo.authors("main"), [ANONYMOUS, ANONYMOUS]);
+}
+
+#[test]
+fn stale_plan_is_refused() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.add_remote("origin");
+    let plan = repo.scratch("plan.json");
+
+    repo.git_anon()
+        .args(["--dry-run", "--plan-out"])
+        .arg(&plan)
+        .args(["push", "origin"])
+        .assert()
+        .success();
+    repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "--plan-in"])
+        .arg(&plan)
+        .args(["push", "origin"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "no longer matches the reviewed plan",
+        ));
+    assert_eq!(repo.authors("main"), [REAL, REAL]);
+}
+
+#[test]
+fn tags_of_trees_are_left_out_of_the_plan() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["tag", "v1.0"]);
+    repo.git(&["tag", "tree-tag", "HEAD^{tree}"]);
+    repo.add_remote("origin");
+    let plan = repo.scratch("plan.json");
+
+    repo.git_anon()
+        .args(["--dry-run", "--plan-out"])
+        .arg(&plan)
+        .args(["push", "origin", "--include-tags"])
+        .assert()
+        .success();
+
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&plan).unwrap()).unwrap();
+    let tags: Vec<&str> = written["tags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| tag["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(tags, ["v1.0"]);
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn dry_run_counts_distinct_identities() {
+    let repo = TestRepo::new();
+    repo.commit_as("a.txt", "a", "Ann <ann@corp.example>");
+    repo.commit_as("b.txt", "b", "Bob <bob@corp.example>");
+    repo.commit_as("c.txt", "c", "Ann <ann@corp.example>");
+    repo.commit_as("d.txt", "d", "Cid <cid@corp.example>");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--dry-run", "push", "origin"])
+        .args(["--pseudonyms", "deterministic"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "3 distinct identities would be replaced",
+        ));
+}
+
+#[test]
+fn colliding_pseudonyms_fail_the_run() {
+    let repo = TestRepo::new();
+    // With this salt both emails hash to contributor-00cb48c7.
+    repo.write_config("version = 2\npseudonym_salt = \"team\"\n");
+    repo.commit_as("a.txt", "a", "Dev <dev110623@corp.example>");
+    repo.commit_as("b.txt", "b", "Dev <dev142221@corp.example>");
+    let before = repo.rev("main");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--pseudonyms", "deterministic"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "would both become contributor-00cb48c7",
+        ));
+    assert_eq!(repo.rev("main"), before);
+}
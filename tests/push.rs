@@ -0,0 +1,1188 @@
+mod common;
+
+use common::{ANONYMOUS, REAL, TestRepo};
+use git_anon::mapping::{AuthorMap, CommitMap};
+use std::fs;
+
+#[test]
+fn a_stale_real_tip_is_refused_even_with_force() {
+    for force in [false, true] {
+        let repo = TestRepo::new();
+        let first = repo.commit("a.txt", "a");
+        let second = repo.commit("b.txt", "b");
+        repo.commit("c.txt", "c");
+        let remote = repo.add_remote("origin");
+
+        repo.git_anon()
+            .args(["--yes", "push", "origin", "--range"])
+            .arg(format!("{first}..{second}"))
+            .args(force.then_some("--force"))
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("Refusing to push main: its tip"))
+            .stderr(predicates::str::contains(format!(
+                "still has the real author {REAL}"
+            )));
+        assert!(
+            repo.git_in(&remote, &["branch", "--list"]).is_empty(),
+            "--force {force}"
+        );
+    }
+}
+
+#[test]
+fn a_refused_real_tip_leaves_branches_and_tags_as_they_were() {
+    let repo = TestRepo::new();
+    let first = repo.commit("a.txt", "a");
+    let second = repo.commit("b.txt", "b");
+    let tip = repo.commit("c.txt", "c");
+    repo.git(&["tag", "v1", &second]);
+    repo.git(&["tag", "-a", "-m", "Release", "v2", &tip]);
+    let v2 = repo.git(&["rev-parse", "refs/tags/v2"]);
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--include-tags", "--range"])
+        .arg(format!("{first}..{second}"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("the branches were rolled back"));
+
+    assert_eq!(repo.rev("main"), tip);
+    assert_eq!(repo.rev("refs/tags/v1"), second);
+    assert_eq!(repo.git(&["rev-parse", "refs/tags/v2"]), v2);
+    assert!(repo.git(&["status", "--porcelain"]).is_empty());
+}
+
+#[test]
+fn allow_real_tip_pushes_it_with_a_warning() {
+    let repo = TestRepo::new();
+    let first = repo.commit("a.txt", "a");
+    let second = repo.commit("b.txt", "b");
+    repo.commit("c.txt", "c");
+    let remote = repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--allow-real-tip", "--range"])
+        .arg(format!("{first}..{second}"))
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("warning: Pushing main: its tip"));
+
+    let pushed = repo.git_in(&remote, &["log", "--format=%an <%ae>", "main"]);
+    let pushed: Vec<&str> = pushed.lines().collect();
+    assert_eq!(pushed, [REAL, ANONYMOUS, REAL]);
+}
+
+#[test]
+fn allowed_real_tip_fails_the_run_under_abort_on_warning() {
+    let repo = TestRepo::new();
+    let first = repo.commit("a.txt", "a");
+    let second = repo.commit("b.txt", "b");
+    repo.commit("c.txt", "c");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "--abort-on-warning", "push", "origin"])
+        .args(["--allow-real-tip", "--range"])
+        .arg(format!("{first}..{second}"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--abort-on-warning"));
+}
+
+const WORK: &str = "Work Anon <work@anon.example.org>";
+
+fn with_work_branch(repo: &TestRepo) {
+    repo.write_config(
+        r#"
+version = 2
+
+[identities.work]
+name = "Work Anon"
+email = "work@anon.example.org"
+
+[branch_identities]
+work = "work"
+"#,
+    );
+}
+
+#[test]
+fn branches_mapping_to_different_identities_get_their_own() {
+    let repo = TestRepo::new();
+    with_work_branch(&repo);
+    repo.commit("a.txt", "a");
+    repo.git(&["checkout", "--quiet", "--orphan", "work"]);
+    repo.commit("b.txt", "b");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "main", "work"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+    assert_eq!(repo.authors("work"), [WORK]);
+}
+
+#[test]
+fn all_branches_share_the_rewrite_of_their_common_history() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let shared = repo.commit("b.txt", "b");
+    repo.git(&["checkout", "--quiet", "-b", "feature"]);
+    repo.commit("feature.txt", "feature");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.commit("main.txt", "main");
+    repo.git(&["branch", "backup-main-1700000000"]);
+    let remote = repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--all-branches"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [ANONYMOUS; 3]);
+    assert_eq!(repo.authors("feature"), [ANONYMOUS; 3]);
+    let rewritten = repo.rev("main~1");
+    assert_ne!(rewritten, shared);
+    assert_eq!(repo.rev("feature~1"), rewritten);
+    for branch in ["main", "feature"] {
+        assert_eq!(
+            repo.git_in(&remote, &["rev-parse", branch]),
+            repo.rev(branch)
+        );
+    }
+    assert_eq!(repo.authors("backup-main-1700000000"), [REAL; 3]);
+    assert_eq!(
+        repo.git_in(&remote, &["branch", "--list", "--format=%(refname:short)"]),
+        "feature\nmain"
+    );
+}
+
+#[test]
+fn shared_commit_with_conflicting_identities_is_refused() {
+    let repo = TestRepo::new();
+    with_work_branch(&repo);
+    repo.commit("a.txt", "a");
+    repo.git(&["branch", "work"]);
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "main", "work"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("map to different identities"));
+
+    assert_eq!(repo.authors("main"), [REAL]);
+}
+
+#[test]
+fn branch_wins_over_a_tag_with_the_same_name() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["tag", "main"]);
+    repo.commit("b.txt", "b");
+    let remote = repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin"])
+        .assert()
+        .success();
+
+    let pushed = repo.git_in(&remote, &["log", "--format=%an <%ae>", "refs/heads/main"]);
+    assert_eq!(pushed.lines().collect::<Vec<_>>(), [ANONYMOUS, ANONYMOUS]);
+}
+
+#[test]
+fn report_lists_tags_moved_by_include_tags() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["tag", "v1.0"]);
+    repo.add_remote("origin");
+    let report = repo.scratch("report.json");
+
+    repo.git_anon()
+        .args(["--yes", "--report-out"])
+        .arg(&report)
+        .args(["push", "origin", "--include-tags"])
+        .assert()
+        .success();
+
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(report).unwrap()).unwrap();
+    assert_eq!(report["tags"], serde_json::json!(["v1.0"]));
+    assert_eq!(report["moved_tags"], serde_json::json!(["v1.0"]));
+}
+
+#[test]
+fn branch_identities_match_globs_and_exact_names() {
+    let repo = TestRepo::new();
+    repo.write_config(
+        r#"
+version = 2
+
+[identities.work]
+name = "Work Anon"
+email = "work@anon.example.org"
+
+[branch_identities]
+"experimental/*" = "work"
+"experimental/stable" = "anonymous_identity"
+"#,
+    );
+    repo.commit("a.txt", "a");
+    repo.git(&["checkout", "--quiet", "-b", "experimental/x"]);
+    repo.commit("b.txt", "b");
+    repo.git(&["checkout", "--quiet", "--orphan", "experimental/stable"]);
+    repo.commit("c.txt", "c");
+    repo.add_remote("origin");
+
+    for branch in ["experimental/x", "experimental/stable"] {
+        repo.git(&["checkout", "--quiet", branch]);
+        repo.git_anon()
+            .args(["--yes", "push", "origin"])
+            .assert()
+            .success();
+    }
+
+    assert_eq!(repo.authors("experimental/x"), [WORK, WORK]);
+    assert_eq!(repo.authors("experimental/stable"), [ANONYMOUS]);
+}
+
+#[test]
+fn parallel_push_reaches_every_remote() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let origin = repo.add_remote("origin");
+    let mirror = repo.add_remote("mirror");
+
+    repo.git_anon()
+        .args([
+            "--yes",
+            "push",
+            "origin",
+            "--also-to",
+            "mirror",
+            "--parallel-push",
+        ])
+        .assert()
+        .success();
+
+    let tip = repo.rev("main");
+    for remote in [origin, mirror] {
+        assert_eq!(repo.git_in(&remote, &["rev-parse", "main"]), tip);
+    }
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+}
+
+#[test]
+fn backup_push_first_keeps_the_real_history_on_the_backup_remote() {
+    let repo = TestRepo::new();
+    repo.write_config("version = 2\nbackup_remote = \"private\"\n");
+    let real = repo.commit("a.txt", "a");
+    let origin = repo.add_remote("origin");
+    let private = repo.add_remote("private");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--force-backup-push-first"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.git_in(&private, &["rev-parse", "main"]), real);
+    assert_eq!(
+        repo.git_in(&origin, &["rev-parse", "main"]),
+        repo.rev("main")
+    );
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+}
+
+#[test]
+fn a_failed_backup_push_publishes_nothing() {
+    let repo = TestRepo::new();
+    repo.write_config("version = 2\nbackup_remote = \"private\"\n");
+    let real = repo.commit("a.txt", "a");
+    let origin = repo.add_remote("origin");
+    let missing = repo.scratch("missing.git");
+    repo.git(&["remote", "add", "private", missing.to_str().unwrap()]);
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--force-backup-push-first"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Backup push to private failed; nothing was anonymized or published",
+        ));
+
+    assert_eq!(repo.rev("main"), real);
+    assert!(!repo.git_ok(&[
+        "--git-dir",
+        origin.to_str().unwrap(),
+        "rev-parse",
+        "--verify",
+        "main"
+    ]));
+}
+
+#[test]
+fn since_tag_anonymizes_only_later_commits() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["tag", "-a", "v1.0", "-m", "Release"]);
+    repo.commit("b.txt", "b");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--since-tag", "v1.0"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [ANONYMOUS, REAL]);
+}
+
+#[test]
+fn since_tag_must_be_an_ancestor() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["checkout", "--quiet", "-b", "side"]);
+    repo.commit("b.txt", "b");
+    repo.git(&["tag", "v1.0"]);
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--since-tag", "v1.0"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("is not an ancestor of HEAD"));
+}
+
+#[test]
+fn unknown_transports_push_through_their_remote_helper() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let target = repo.add_remote("target");
+    let log = repo.scratch("helper.log");
+    // A minimal `connect` helper, like git-remote-rad, that logs each call.
+    repo.install(
+        "git-remote-stub",
+        &format!(
+            "#!/bin/sh\necho \"$2\" >> '{}'\nwhile read line; do\n  case \"$line\" in\n    capabilities) printf 'connect\\n\\n' ;;\n    'connect '*) printf '\\n'; exec ${{line#connect }} \"$2\" ;;\n  esac\ndone\n",
+            log.display()
+        ),
+    );
+    repo.git(&[
+        "remote",
+        "add",
+        "rad",
+        &format!("stub::{}", target.display()),
+    ]);
+
+    repo.git_anon()
+        .args(["--yes", "push", "rad"])
+        .assert()
+        .success();
+
+    assert!(
+        fs::read_to_string(log)
+            .unwrap()
+            .contains(target.to_str().unwrap())
+    );
+    assert_eq!(
+        repo.git_in(&target, &["rev-parse", "main"]),
+        repo.rev("main")
+    );
+}
+
+#[test]
+fn pushing_a_branch_that_is_not_checked_out_rewrites_its_own_history() {
+    let repo = TestRepo::new();
+    let base = repo.commit("a.txt", "a");
+    repo.git(&["checkout", "--quiet", "-b", "feature"]);
+    repo.commit("feature.txt", "feature");
+    repo.git(&["checkout", "--quiet", "main"]);
+    let main = repo.commit("main.txt", "main");
+    let remote = repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "feature"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.rev("main"), main);
+    assert_eq!(repo.authors("feature"), [ANONYMOUS, ANONYMOUS]);
+    assert_ne!(repo.rev("feature~1"), base);
+    let files = repo.git(&["ls-tree", "--name-only", "feature"]);
+    assert_eq!(files, "a.txt\nfeature.txt");
+    assert_eq!(
+        repo.git_in(&remote, &["rev-parse", "feature"]),
+        repo.rev("feature")
+    );
+}
+
+#[test]
+fn identity_file_reaches_ssh_as_one_argument() {
+    for from_config in [false, true] {
+        let repo = TestRepo::new();
+        repo.commit("a.txt", "a");
+        let key = repo.scratch("o'brien; touch pwned/id_key");
+        let log = repo.scratch("ssh.log");
+        repo.install(
+            "ssh",
+            &format!(
+                "#!/bin/sh\nprintf '%s\\n' \"$@\" > '{}'\nexit 1\n",
+                log.display()
+            ),
+        );
+        repo.git(&[
+            "remote",
+            "add",
+            "origin",
+            "ssh://git@example.invalid/repo.git",
+        ]);
+        if from_config {
+            repo.write_config(&format!(
+                r#"version = 2
+
+[remotes.origin]
+name = "origin"
+identity = "anonymous_identity"
+identity_file = "{}"
+"#,
+                key.display()
+            ));
+        }
+
+        let mut command = repo.git_anon();
+        command.args(["--yes", "push", "origin", "--check-remote-empty"]);
+        if !from_config {
+            command.arg("--identity-file").arg(&key);
+        }
+        command
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("git ls-remote origin failed"));
+
+        let args = fs::read_to_string(&log).unwrap();
+        let args: Vec<&str> = args.lines().collect();
+        assert_eq!(
+            args[..4],
+            ["-i", key.to_str().unwrap(), "-o", "IdentitiesOnly=yes"],
+            "from config: {from_config}"
+        );
+        assert!(!repo.path.join("pwned").exists());
+    }
+}
+
+#[test]
+fn push_into_keeps_the_anonymized_history_on_a_new_branch() {
+    let repo = TestRepo::new();
+    let tip = repo.commit("a.txt", "a");
+    let remote = repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--into", "public"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.rev("main"), tip);
+    assert_eq!(repo.authors("public"), [ANONYMOUS]);
+    assert_eq!(
+        repo.git_in(&remote, &["rev-parse", "main"]),
+        repo.rev("public")
+    );
+}
+
+#[test]
+fn keep_authors_credits_authors_and_replaces_committers() {
+    let repo = TestRepo::new();
+    let alice = "Alice <alice@corp.example>";
+    repo.commit_by("a.txt", "a", alice, REAL);
+    repo.commit("b.txt", "b");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--keep-authors"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [REAL, alice]);
+    assert_eq!(repo.committers("main"), [ANONYMOUS, ANONYMOUS]);
+}
+
+#[test]
+fn pathspec_anonymizes_only_commits_touching_it() {
+    let repo = TestRepo::new();
+    repo.commit("README", "a");
+    repo.commit("secret/key.txt", "b");
+    repo.commit("src/main.rs", "c");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--pathspec", "secret/*"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--allow-real-tip"));
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--pathspec", "secret/*"])
+        .arg("--allow-real-tip")
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [REAL, ANONYMOUS, REAL]);
+    assert_eq!(repo.git(&["show", "main:secret/key.txt"]), "b");
+}
+
+#[test]
+fn scrub_messages_leaves_commits_outside_the_pathspec_alone() {
+    let repo = TestRepo::new();
+    let signed_off = format!("Update\n\nSigned-off-by: {REAL}");
+    repo.write("secret/key.txt", "a");
+    repo.commit_staged(REAL, REAL, 1_700_000_000, &signed_off);
+    repo.write("README", "b");
+    repo.commit_staged(REAL, REAL, 1_700_000_100, &signed_off);
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--pathspec", "secret/*"])
+        .args(["--scrub-messages", "--allow-real-tip"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [REAL, ANONYMOUS]);
+    assert_eq!(
+        repo.log("main", "%(trailers:key=Signed-off-by,valueonly,separator=)"),
+        [REAL, ANONYMOUS]
+    );
+}
+
+#[test]
+fn tz_moves_kept_dates_to_the_offset_at_the_same_instant() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+    let instants = repo.log("main", "%at %ct");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args([
+            "--yes",
+            "--tz",
+            "+0530",
+            "push",
+            "origin",
+            "--preserve-dates",
+        ])
+        .assert()
+        .success();
+
+    assert_eq!(repo.log("main", "%at %ct"), instants);
+    for dates in repo.log("main", "%ai|%ci") {
+        let (author, committer) = dates.split_once('|').unwrap();
+        assert!(author.ends_with("+0530"), "{author}");
+        assert!(committer.ends_with("+0530"), "{committer}");
+    }
+}
+
+#[test]
+fn reflog_modes_remove_original_identities_from_reflogs() {
+    for (mode, message) in [
+        ("scrub", "commit (initial): Fix by Anonymous"),
+        ("clear", ""),
+    ] {
+        let repo = TestRepo::new();
+        repo.write("a.txt", "a");
+        repo.commit_staged(REAL, REAL, 1_700_000_000, "Fix by Jane Doe");
+        repo.add_remote("origin");
+
+        repo.git_anon()
+            .args(["--yes", "push", "origin", "--reflog", mode])
+            .assert()
+            .success();
+
+        let reflog = repo.git(&["reflog", "show", "--format=%gn <%ge>|%gs", "main"]);
+        assert!(!reflog.contains("Jane Doe"), "--reflog {mode}: {reflog}");
+        assert!(
+            !reflog.contains("jane@corp.example"),
+            "--reflog {mode}: {reflog}"
+        );
+        assert!(
+            reflog
+                .lines()
+                .any(|entry| entry == format!("{ANONYMOUS}|{message}")),
+            "--reflog {mode}: {reflog}"
+        );
+    }
+}
+
+#[test]
+fn reflog_scrub_applies_message_scrubbing_to_other_people_named() {
+    let repo = TestRepo::new();
+    repo.write_config(
+        r#"
+version = 2
+
+[scrub]
+deny = ['Alice Smith']
+
+[[scrub.patterns]]
+find = 'CORP-(\d+)'
+replace = '#$1'
+"#,
+    );
+    repo.write("a.txt", "a");
+    repo.commit_staged(
+        "Alice Smith <alice@corp.example>",
+        REAL,
+        1_700_000_000,
+        "Fix CORP-7 by Alice Smith <alice@corp.example>",
+    );
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args([
+            "--yes",
+            "push",
+            "origin",
+            "--reflog",
+            "scrub",
+            "--scrub-messages",
+        ])
+        .assert()
+        .success();
+
+    assert_eq!(
+        repo.log("main", "%s"),
+        ["Fix #7 by Anonymous <anonymous@example.com>"]
+    );
+    let reflog = repo.git(&["reflog", "show", "--format=%gn <%ge>|%gs", "main"]);
+    assert!(!reflog.contains("Alice"), "{reflog}");
+    assert!(!reflog.contains("alice@corp.example"), "{reflog}");
+    assert!(!reflog.contains("CORP-7"), "{reflog}");
+    assert!(
+        reflog.lines().any(|entry| entry
+            == format!(
+                "{ANONYMOUS}|commit (initial): Fix #7 by Anonymous <anonymous@example.com>"
+            )),
+        "{reflog}"
+    );
+}
+
+#[test]
+fn reflog_scrub_replaces_whole_names_and_emails_with_their_counterparts() {
+    let repo = TestRepo::new();
+    repo.write("a.txt", "a");
+    repo.commit_staged(
+        "Al <al@corp.example>",
+        "Al <al@corp.example>",
+        1_700_000_000,
+        "Fix install for Al, cc al@corp.example",
+    );
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--reflog", "scrub"])
+        .assert()
+        .success();
+
+    let reflog = repo.git(&["reflog", "show", "--format=%gn <%ge>|%gs", "main"]);
+    assert!(
+        reflog.lines().any(|entry| entry
+            == format!(
+                "{ANONYMOUS}|commit (initial): Fix install for Anonymous, cc anonymous@example.com"
+            )),
+        "{reflog}"
+    );
+}
+
+#[test]
+fn author_and_committer_date_policies_combine() {
+    for author in ["keep", "now"] {
+        for committer in ["keep", "now"] {
+            let repo = TestRepo::new();
+            repo.commit("a.txt", "a");
+            let original: i64 = repo.log("main", "%at")[0].parse().unwrap();
+            repo.add_remote("origin");
+
+            repo.git_anon()
+                .args(["--yes", "push", "origin"])
+                .args(["--author-date", author, "--committer-date", committer])
+                .assert()
+                .success();
+
+            let dates = repo.log("main", "%at %ct");
+            let (author_date, committer_date) = dates[0].split_once(' ').unwrap();
+            for (policy, date) in [(author, author_date), (committer, committer_date)] {
+                let date: i64 = date.parse().unwrap();
+                match policy {
+                    "keep" => assert_eq!(date, original, "{author}/{committer}"),
+                    _ => assert!(date > original, "{author}/{committer}"),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn scrub_patterns_apply_in_order_to_each_message() {
+    let repo = TestRepo::new();
+    repo.write_config(
+        r#"
+version = 2
+
+[[scrub.patterns]]
+find = 'CORP-(\d+)'
+replace = 'TICKET-$1'
+
+[[scrub.patterns]]
+find = 'TICKET-'
+replace = '#'
+
+[[scrub.patterns]]
+find = '[a-z0-9]+\.corp\.internal'
+replace = 'host'
+"#,
+    );
+    repo.write("a.txt", "a");
+    repo.commit_staged(
+        REAL,
+        REAL,
+        1_700_000_000,
+        "Fix CORP-12 on db1.corp.internal",
+    );
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.log("main", "%s"), ["Fix #12 on host"]);
+}
+
+#[test]
+fn invalid_scrub_pattern_names_the_pattern() {
+    let repo = TestRepo::new();
+    repo.write_config(
+        r#"
+version = 2
+
+[[scrub.patterns]]
+find = 'fine'
+
+[[scrub.patterns]]
+find = 'broken('
+"#,
+    );
+    repo.commit("a.txt", "a");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "scrub.patterns[1]: invalid regex 'broken('",
+        ));
+    assert_eq!(repo.authors("main"), [REAL]);
+}
+
+#[test]
+fn check_remote_empty_refuses_a_remote_with_the_branch() {
+    let repo = TestRepo::new();
+    let tip = repo.commit("a.txt", "a");
+    repo.add_remote("empty");
+    repo.add_remote("used");
+    repo.git(&["push", "--quiet", "used", "main"]);
+
+    repo.git_anon()
+        .args(["--yes", "push", "used", "--check-remote-empty"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "used already has a main branch; refusing to publish over existing history",
+        ));
+    assert_eq!(repo.rev("main"), tip);
+
+    repo.git_anon()
+        .args(["--yes", "push", "empty", "--check-remote-empty"])
+        .assert()
+        .success();
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+}
+
+#[test]
+fn range_accepts_rev_list_forms() {
+    for form in [
+        "{first}..{second}",
+        "^{first} {second}",
+        "{second} --not {first}",
+    ] {
+        let repo = TestRepo::new();
+        let first = repo.commit("a.txt", "a");
+        let second = repo.commit("b.txt", "b");
+        repo.commit("c.txt", "c");
+        repo.add_remote("origin");
+        let range = form.replace("{first}", &first).replace("{second}", &second);
+
+        repo.git_anon()
+            .args(["--yes", "push", "origin", "--allow-real-tip"])
+            .args(["--range", &range])
+            .assert()
+            .success();
+
+        assert_eq!(repo.authors("main"), [REAL, ANONYMOUS, REAL], "{form}");
+    }
+}
+
+#[test]
+fn symmetric_range_hides_every_merge_base_of_a_criss_cross() {
+    let repo = TestRepo::new();
+    repo.commit("base.txt", "base");
+    repo.git(&["branch", "other"]);
+    let a1 = repo.commit("a1.txt", "a1");
+    repo.git(&["checkout", "--quiet", "other"]);
+    let b1 = repo.commit("b1.txt", "b1");
+    repo.git(&["merge", "--quiet", "--no-edit", &a1]);
+    repo.commit("b3.txt", "b3");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.git(&["merge", "--quiet", "--no-edit", &b1]);
+    repo.commit("a3.txt", "a3");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--range", "main...other"])
+        .assert()
+        .success();
+
+    assert_eq!(
+        repo.log("main", "%an %s"),
+        [
+            "Anonymous Change a3.txt".to_string(),
+            format!("Anonymous Merge commit '{b1}'"),
+            "Jane Doe Change b1.txt".to_string(),
+            "Jane Doe Change a1.txt".to_string(),
+            "Jane Doe Change base.txt".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn invalid_ranges_are_rejected() {
+    let repo = TestRepo::new();
+    let tip = repo.commit("a.txt", "a");
+    repo.add_remote("origin");
+
+    for (range, reason) in [
+        ("^main", "no commits to include"),
+        ("nope..main", "unknown revision 'nope'"),
+        ("main --all", "unsupported option '--all'"),
+        ("main --not x..y", "'x..y' cannot follow --not"),
+        ("main --not x...y", "'x...y' cannot follow --not"),
+    ] {
+        repo.git_anon()
+            .args(["--yes", "push", "origin", "--range", range])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(format!(
+                "Invalid revision range '{range}': {reason}"
+            )));
+    }
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn email_map_out_lists_every_identity_for_author_map() {
+    let repo = TestRepo::new();
+    let alice = "Alice <alice@corp.example>";
+    let bob = "Bob <bob@corp.example>";
+    repo.commit("a.txt", "a");
+    repo.commit_by("b.txt", "b", alice, bob);
+    let tip = repo.commit_as("c.txt", "c", alice);
+    repo.add_remote("origin");
+    let path = repo.scratch("identities.toml");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--email-map-out"])
+        .arg(&path)
+        .assert()
+        .success();
+
+    assert_eq!(repo.rev("main"), tip);
+    let mut map = AuthorMap::read(&path).unwrap();
+    let originals: Vec<&str> = map
+        .identities
+        .iter()
+        .map(|mapping| mapping.original.as_str())
+        .collect();
+    assert_eq!(originals, [alice, bob, REAL]);
+    assert!(
+        map.identities
+            .iter()
+            .all(|mapping| mapping.replacement.is_empty())
+    );
+
+    map.identities[0].replacement = WORK.to_string();
+    map.write(&path).unwrap();
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--author-map"])
+        .arg(&path)
+        .assert()
+        .success();
+    assert_eq!(repo.authors("main"), [WORK, WORK, ANONYMOUS]);
+}
+
+#[test]
+fn branch_pointing_outside_refs_heads_pushes_its_target() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.git(&["update-ref", "refs/published/main", "main"]);
+    repo.git(&["symbolic-ref", "refs/heads/public", "refs/published/main"]);
+    let remote = repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "public"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("refs/published/main"), [ANONYMOUS]);
+    assert_eq!(
+        repo.git_in(&remote, &["rev-parse", "refs/heads/public"]),
+        repo.rev("refs/published/main")
+    );
+}
+
+#[test]
+fn commit_map_gives_each_commit_its_listed_identity() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let middle = repo.commit("b.txt", "b");
+    repo.commit("c.txt", "c");
+    repo.add_remote("origin");
+    let path = repo.scratch("commits.toml");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--commit-map-out"])
+        .arg(&path)
+        .assert()
+        .success();
+    let mut map = CommitMap::read(&path).unwrap();
+    assert_eq!(map.commits.len(), 3);
+    for entry in &mut map.commits {
+        assert_eq!(entry.identity, ANONYMOUS);
+        if entry.oid == middle {
+            entry.identity = WORK.to_string();
+        }
+    }
+    map.write(&path).unwrap();
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--commit-map"])
+        .arg(&path)
+        .assert()
+        .success();
+    assert_eq!(repo.authors("main"), [ANONYMOUS, WORK, ANONYMOUS]);
+}
+
+#[test]
+fn commit_map_with_commits_outside_the_push_is_refused() {
+    let repo = TestRepo::new();
+    let tip = repo.commit("a.txt", "a");
+    repo.add_remote("origin");
+    let path = repo.scratch("commits.toml");
+    let stranger = "0123456789abcdef0123456789abcdef01234567";
+    std::fs::write(
+        &path,
+        format!("[[commit]]\noid = \"{stranger}\"\nidentity = \"{WORK}\"\n"),
+    )
+    .unwrap();
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin", "--commit-map"])
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!(
+            "The commit map lists commits that are not being anonymized: {stranger}"
+        )));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn future_dated_commits_are_flagged_and_clamped_on_request() {
+    const FUTURE: i64 = 4_000_000_000;
+    for clamp in [false, true] {
+        let repo = TestRepo::new();
+        repo.commit("a.txt", "a");
+        repo.commit_at("b.txt", "b", REAL, FUTURE);
+        repo.add_remote("origin");
+
+        let mut push = repo.git_anon();
+        push.args(["--yes", "push", "origin", "--preserve-dates"]);
+        if clamp {
+            push.arg("--clamp-future-dates");
+        }
+        push.assert()
+            .success()
+            .stderr(predicates::str::contains(match clamp {
+                true => "1 commits were dated in the future; clamped to now",
+                false => "1 commits are dated in the future",
+            }));
+
+        let dates = repo.log("main", "%at %ct");
+        let (author, committer) = dates[0].split_once(' ').unwrap();
+        for date in [author, committer] {
+            let date: i64 = date.parse().unwrap();
+            assert_eq!(date == FUTURE, !clamp, "--clamp-future-dates {clamp}");
+        }
+    }
+}
+
+#[test]
+fn trace_logs_each_rewritten_commit_in_order() {
+    let repo = TestRepo::new();
+    let originals = [
+        repo.commit("a.txt", "a"),
+        repo.commit("b.txt", "b"),
+        repo.commit("c.txt", "c"),
+    ];
+    repo.add_remote("origin");
+    let trace = repo.scratch("trace.jsonl");
+
+    repo.git_anon()
+        .arg(format!("--trace={}", trace.display()))
+        .args(["--yes", "push", "origin"])
+        .assert()
+        .success();
+
+    let events: Vec<serde_json::Value> = fs::read_to_string(&trace)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .filter(|event: &serde_json::Value| event["event"] == "commit")
+        .collect();
+    let mut rewritten = repo.log("main", "%H");
+    rewritten.reverse();
+    assert_eq!(events.len(), 3);
+    for (i, event) in events.iter().enumerate() {
+        assert_eq!(event["old"], originals[i]);
+        assert_eq!(event["new"], rewritten[i]);
+        assert_eq!(event["author"], ANONYMOUS);
+        assert_eq!(
+            event["transforms"],
+            serde_json::json!(["author", "committer"])
+        );
+    }
+    assert_eq!(
+        events[2]["parents"],
+        serde_json::json!([{"old": originals[1], "new": [rewritten[1]]}])
+    );
+}
+
+#[test]
+fn count_only_counts_commits_past_the_remote_without_pushing() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let published = repo.commit("b.txt", "b");
+    let remote = repo.add_remote("origin");
+    repo.git(&["push", "--quiet", "origin", "main"]);
+    repo.commit("c.txt", "c");
+    let tip = repo.commit("d.txt", "d");
+
+    let output = repo
+        .git_anon()
+        .args(["push", "origin", "--count-only"])
+        .output()
+        .unwrap();
+    assert_eq!(common::stdout(&output).lines().last(), Some("2"));
+
+    let output = repo
+        .git_anon()
+        .args(["--json", "push", "origin", "--count-only"])
+        .output()
+        .unwrap();
+    let outcome: serde_json::Value = serde_json::from_str(&common::stdout(&output)).unwrap();
+    assert_eq!(outcome["commits"], 2);
+    assert_eq!(outcome["remote"], "origin");
+
+    assert_eq!(repo.rev("main"), tip);
+    assert_eq!(repo.git_in(&remote, &["rev-parse", "main"]), published);
+}
+
+#[test]
+fn timestamp_strategy_fixed_dates_every_commit_alike() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin"])
+        .args(["--timestamp-strategy", "fixed:2024-01-01T00:00:00Z"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.log("main", "%at %ct"), ["1704067200 1704067200"; 2]);
+}
+
+#[test]
+fn timestamp_strategy_jitter_stays_in_the_window_and_in_order() {
+    const WINDOW: i64 = 3600;
+    let repo = TestRepo::new();
+    for file in ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"] {
+        repo.commit(file, file);
+    }
+    let originals = repo.log("main", "%at");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "push", "origin"])
+        .args(["--timestamp-strategy", &format!("jitter:{WINDOW}")])
+        .assert()
+        .success();
+
+    let dates: Vec<i64> = repo
+        .log("main", "%at")
+        .iter()
+        .map(|date| date.parse().unwrap())
+        .collect();
+    for (date, original) in dates.iter().zip(&originals) {
+        let original: i64 = original.parse().unwrap();
+        assert!((date - original).abs() <= WINDOW, "{date} vs {original}");
+    }
+    // Newest first, so each date is no later than the one before it.
+    assert!(dates.windows(2).all(|pair| pair[0] >= pair[1]), "{dates:?}");
+}
+
+#[test]
+fn environment_identities_win_over_the_config() {
+    let repo = TestRepo::new();
+    with_work_branch(&repo);
+    repo.git(&["checkout", "--quiet", "-b", "work"]);
+    repo.commit("a.txt", "a");
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .env("GIT_ANON_NAME", "Env Anon")
+        .env("GIT_ANON_EMAIL", "env@anon.example.org")
+        .args(["--yes", "push", "origin"])
+        .assert()
+        .success();
+    assert_eq!(repo.authors("work"), ["Env Anon <env@anon.example.org>"]);
+
+    repo.commit("b.txt", "b");
+    repo.git_anon()
+        .env("GIT_ANON_NAME", "Env Anon")
+        .env("GIT_ANON_EMAIL", "env@anon.example.org")
+        .env("GIT_ANON_ORIGIN_NAME", "Origin Anon")
+        .env("GIT_ANON_ORIGIN_EMAIL", "origin@anon.example.org")
+        .args(["--yes", "push", "origin"])
+        .assert()
+        .success();
+    assert_eq!(
+        repo.authors("work")[0],
+        "Origin Anon <origin@anon.example.org>"
+    );
+}
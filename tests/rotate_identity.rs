@@ -0,0 +1,99 @@
+mod common;
+
+use common::{ANONYMOUS, REAL, TestRepo};
+
+const OLD: &str = "Old Anon <old@anon.example.org>";
+
+#[test]
+fn undo_reverts_a_rotation() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.commit_as("b.txt", "b", OLD);
+    let before = repo.rev("main");
+
+    repo.git_anon()
+        .args(["--yes", "rotate-identity", "old@anon.example.org"])
+        .assert()
+        .success();
+    assert_eq!(repo.authors("main"), [ANONYMOUS, REAL]);
+
+    repo.git_anon().args(["--yes", "undo"]).assert().success();
+    assert_eq!(repo.rev("main"), before);
+}
+
+#[test]
+fn rotation_to_a_forbidden_identity_is_rolled_back() {
+    let repo = TestRepo::new();
+    repo.write_config(
+        r#"
+version = 2
+forbidden_identities = ["anonymous@*"]
+"#,
+    );
+    repo.commit_as("a.txt", "a", OLD);
+    let before = repo.rev("main");
+
+    repo.git_anon()
+        .args(["--yes", "rotate-identity", "old@anon.example.org"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rolled back"));
+    assert_eq!(repo.rev("main"), before);
+}
+
+#[test]
+fn only_commits_with_the_old_identity_are_rewritten() {
+    let repo = TestRepo::new();
+    let key = repo.ssh_keygen("personal_ed25519");
+    repo.write("a.txt", "a");
+    repo.git(&[
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &format!("user.signingkey={}", key.display()),
+        "commit",
+        "--quiet",
+        "-S",
+        "-m",
+        "Signed change",
+    ]);
+    let first = repo.rev("main");
+    let old = repo.commit_as("b.txt", "b", OLD);
+    let last = repo.commit("c.txt", "c");
+
+    repo.git_anon()
+        .args(["--yes", "rotate-identity", "old@anon.example.org"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [REAL, ANONYMOUS, REAL]);
+    assert_eq!(repo.committers("main"), [REAL, ANONYMOUS, REAL]);
+    assert_eq!(repo.rev("main~2"), first);
+    assert!(
+        repo.git(&["cat-file", "commit", "main~2"])
+            .contains("gpgsig")
+    );
+    assert_ne!(repo.rev("main~1"), old);
+    assert_ne!(repo.rev("main"), last);
+    assert_eq!(
+        repo.git(&["rev-parse", "main^{tree}"]),
+        repo.git(&["rev-parse", &format!("{last}^{{tree}}")])
+    );
+}
+
+#[test]
+fn rotation_keeps_every_commit_date() {
+    let repo = TestRepo::new();
+    repo.commit_at("a.txt", "a", REAL, 1_700_000_000);
+    repo.commit_at("b.txt", "b", OLD, 1_700_000_100);
+    repo.commit_at("c.txt", "c", REAL, 1_700_000_200);
+    let before = repo.log("main", "%at %ct");
+
+    repo.git_anon()
+        .args(["--yes", "rotate-identity", "old@anon.example.org"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [REAL, ANONYMOUS, REAL]);
+    assert_eq!(repo.log("main", "%at %ct"), before);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::TestRepo;
+use std::fs;
+
+/// Gives the anonymous identity a fresh SSH signing key and returns the
+/// allowed-signers file that verifies it.
+fn ssh_signing_key(repo: &TestRepo) -> String {
+    let key = repo.ssh_keygen("anon_ed25519");
+    repo.write_config(&format!(
+        r#"
+version = 2
+
+[anonymous_identity]
+name = "Anonymous"
+email = "anonymous@example.com"
+signing_key = "{}"
+
+[signing]
+format = "ssh"
+"#,
+        key.display()
+    ));
+
+    let public = fs::read_to_string(key.with_extension("pub")).unwrap();
+    let allowed = repo.scratch("allowed_signers");
+    fs::write(&allowed, format!("anonymous@example.com {public}")).unwrap();
+    allowed.display().to_string()
+}
+
+/// Runs a signature check (`verify-commit`, `tag -v`) against `allowed`.
+fn verifies(repo: &TestRepo, allowed: &str, args: &[&str]) -> bool {
+    let allowed = format!("gpg.ssh.allowedSignersFile={allowed}");
+    repo.git_ok(&[&["-c", "gpg.format=ssh", "-c", &allowed], args].concat())
+}
+
+#[test]
+fn retargeted_annotated_tags_are_signed() {
+    let repo = TestRepo::new();
+    let allowed = ssh_signing_key(&repo);
+    repo.commit("a.txt", "a");
+    repo.git(&["tag", "-a", "v1.0", "-m", "Release"]);
+    repo.add_remote("origin");
+
+    repo.git_anon()
+        .args(["--yes", "--sign", "push", "origin", "--include-tags"])
+        .assert()
+        .success();
+
+    assert!(verifies(&repo, &allowed, &["tag", "-v", "v1.0"]));
+}
+
+#[test]
+fn commit_gpgsign_signs_by_default() {
+    let repo = TestRepo::new();
+    let allowed = ssh_signing_key(&repo);
+    repo.commit("a.txt", "a");
+    repo.git(&["config", "commit.gpgsign", "true"]);
+
+    repo.git_anon()
+        .args(["--yes", "anonymize"])
+        .assert()
+        .success();
+    assert!(verifies(&repo, &allowed, &["verify-commit", "main"]));
+
+    repo.commit("b.txt", "b");
+    repo.git_anon()
+        .args(["--yes", "--no-sign", "anonymize", "--since", "main~1"])
+        .assert()
+        .success();
+    assert!(!verifies(&repo, &allowed, &["verify-commit", "main"]));
+}
+
+#[test]
+fn rewritten_commits_drop_the_original_signature() {
+    let repo = TestRepo::new();
+    let personal = repo.ssh_keygen("personal_ed25519");
+    repo.write("a.txt", "a");
+    repo.git(&[
+        "-c",
+        "gpg.format=ssh",
+        "-c",
+        &format!("user.signingkey={}", personal.display()),
+        "commit",
+        "--quiet",
+        "-S",
+        "-m",
+        "Signed change",
+    ]);
+    assert!(repo.git(&["cat-file", "commit", "main"]).contains("gpgsig"));
+
+    repo.git_anon()
+        .args(["--yes", "anonymize"])
+        .assert()
+        .success();
+
+    let rewritten = repo.git(&["cat-file", "commit", "main"]);
+    assert!(!rewritten.contains("gpgsig"), "{rewritten}");
+    assert!(!rewritten.contains("SSH SIGNATURE"), "{rewritten}");
+    assert_eq!(repo.log("main", "%G?"), ["N"]);
+}
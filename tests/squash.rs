@@ -0,0 +1,260 @@
+mod common;
+
+use common::{ANONYMOUS, REAL, TestRepo};
+use predicates::prelude::*;
+
+#[test]
+fn case_collisions_fail_with_core_ignorecase() {
+    let repo = TestRepo::new();
+    repo.commit("File.txt", "upper");
+    let tip = repo.commit("file.txt", "lower");
+    repo.git(&["config", "core.ignorecase", "true"]);
+
+    repo.git_anon()
+        .args(["--yes", "squash", "--strip", "*.env"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "'File.txt' and 'file.txt' differ only in case",
+        ));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn case_variants_are_kept_without_core_ignorecase() {
+    let repo = TestRepo::new();
+    repo.commit("File.txt", "upper");
+    repo.commit("file.txt", "lower");
+    repo.git(&["config", "core.ignorecase", "false"]);
+
+    repo.git_anon()
+        .args(["--yes", "squash", "--strip", "*.env"])
+        .assert()
+        .success();
+    assert_eq!(
+        repo.git(&["ls-tree", "--name-only", "main"]),
+        "File.txt\nfile.txt"
+    );
+}
+
+#[test]
+fn squashed_snapshot_matches_the_original_tree() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    repo.commit("dir/b.txt", "b");
+    let tree = repo.git(&["rev-parse", "main^{tree}"]);
+
+    repo.git_anon()
+        .args(["--yes", "squash", "--backup-name", "original"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Snapshot tree verified identical",
+        ));
+
+    assert_eq!(repo.git(&["rev-parse", "main^{tree}"]), tree);
+    assert_eq!(repo.git(&["rev-parse", "original^{tree}"]), tree);
+    assert_eq!(repo.log("main", "%H").len(), 1);
+}
+
+#[test]
+fn into_leaves_the_current_branch_alone() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let tip = repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .args(["--yes", "squash", "--into", "public"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.rev("main"), tip);
+    assert_eq!(repo.authors("public"), [ANONYMOUS]);
+    assert_eq!(
+        repo.git(&["rev-parse", "public^{tree}"]),
+        repo.git(&["rev-parse", "main^{tree}"])
+    );
+}
+
+#[test]
+fn unborn_head_is_a_clean_error() {
+    let repo = TestRepo::new();
+
+    repo.git_anon()
+        .args(["--yes", "squash"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("no commits yet"))
+        .stderr(predicates::str::contains("panicked").not());
+}
+
+#[test]
+fn git_config_overrides_apply_for_the_run_only() {
+    let repo = TestRepo::new();
+    repo.commit("File.txt", "upper");
+    let tip = repo.commit("file.txt", "lower");
+    repo.git(&["config", "core.ignorecase", "false"]);
+
+    repo.git_anon()
+        .args(["--yes", "--git-config", "core.ignorecase=true"])
+        .args(["squash", "--strip", "*.env"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("differ only in case"));
+
+    assert_eq!(repo.rev("main"), tip);
+    assert_eq!(repo.git(&["config", "core.ignorecase"]), "false");
+}
+
+#[test]
+fn git_config_overrides_must_be_key_value_pairs() {
+    let repo = TestRepo::new();
+
+    for bad in ["core.ignorecase", "ignorecase=true"] {
+        repo.git_anon()
+            .args(["--git-config", bad, "squash"])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("--git-config"));
+    }
+}
+
+#[test]
+fn held_lock_refuses_a_second_operation() {
+    let repo = TestRepo::new();
+    let tip = repo.commit("a.txt", "a");
+    let lock = repo.path.join(".git/git-anon.lock");
+    // This test process stands in for a running git-anon.
+    let holder = std::process::id();
+    std::fs::write(&lock, format!("{holder}\n")).unwrap();
+
+    repo.git_anon()
+        .args(["--yes", "squash"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!(
+            "Another git-anon operation is running on this repository (pid {holder})"
+        )));
+    assert_eq!(repo.rev("main"), tip);
+    assert!(lock.exists());
+
+    std::fs::remove_file(&lock).unwrap();
+    repo.git_anon().args(["--yes", "squash"]).assert().success();
+    assert!(!lock.exists());
+}
+
+#[test]
+fn lock_of_an_exited_process_is_taken_over() {
+    let repo = TestRepo::new();
+    let tip = repo.commit("a.txt", "a");
+    let lock = repo.path.join(".git/git-anon.lock");
+    let mut exited = std::process::Command::new("true").spawn().unwrap();
+    exited.wait().unwrap();
+    std::fs::write(&lock, format!("{}\n", exited.id())).unwrap();
+
+    repo.git_anon().args(["--yes", "squash"]).assert().success();
+    assert_ne!(repo.rev("main"), tip);
+    assert!(!lock.exists());
+}
+
+#[test]
+fn keep_last_squashes_the_older_history_into_one_base() {
+    let repo = TestRepo::new();
+    for file in ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"] {
+        repo.commit(file, file);
+    }
+    let tree = repo.git(&["rev-parse", "main^{tree}"]);
+    let subjects = repo.log("main", "%s");
+
+    repo.git_anon()
+        .args(["--yes", "squash", "--keep-last", "2"])
+        .assert()
+        .success();
+
+    assert_eq!(repo.authors("main"), [ANONYMOUS; 3]);
+    assert_eq!(repo.log("main", "%s")[..2], subjects[..2]);
+    assert_eq!(repo.git(&["rev-parse", "main^{tree}"]), tree);
+}
+
+#[test]
+fn detached_head_asks_for_a_branch() {
+    let repo = TestRepo::new();
+    let first = repo.commit("a.txt", "a");
+    repo.commit("b.txt", "b");
+    repo.git(&["checkout", "--quiet", "--detach", &first]);
+
+    for command in ["squash", "anonymize"] {
+        repo.git_anon()
+            .args(["--yes", command])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(format!(
+                "HEAD is detached at {}; check out a branch first",
+                &first[..8]
+            )));
+    }
+    assert_eq!(repo.rev("HEAD"), first);
+}
+
+#[test]
+fn single_commit_history_squashes_to_one_commit() {
+    let repo = TestRepo::new();
+    let only = repo.commit("a.txt", "a");
+    let tree = repo.git(&["rev-parse", "main^{tree}"]);
+
+    repo.git_anon().args(["--yes", "squash"]).assert().success();
+
+    assert_ne!(repo.rev("main"), only);
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+    assert_eq!(repo.git(&["rev-parse", "main^{tree}"]), tree);
+}
+
+#[test]
+fn from_squashes_only_the_commits_after_it() {
+    let repo = TestRepo::new();
+    repo.commit("a.txt", "a");
+    let base = repo.commit("b.txt", "b");
+    repo.commit("c.txt", "c");
+    repo.commit("d.txt", "d");
+    let tree = repo.git(&["rev-parse", "main^{tree}"]);
+
+    repo.git_anon()
+        .args(["--yes", "squash", "--from", &base])
+        .assert()
+        .success();
+
+    assert_eq!(repo.rev("main~1"), base);
+    assert_eq!(repo.authors("main"), [ANONYMOUS, REAL, REAL]);
+    assert_eq!(repo.git(&["rev-parse", "main^{tree}"]), tree);
+
+    let tip = repo.rev("main");
+    repo.git_anon()
+        .args(["--yes", "squash", "--from", "main"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("No commits after main to squash"));
+    assert_eq!(repo.rev("main"), tip);
+}
+
+#[test]
+fn bare_repositories_can_be_squashed_and_anonymized() {
+    let repo = TestRepo::bare();
+    let tree = repo.git(&["mktree"]);
+    let root = repo.git(&["commit-tree", &tree, "-m", "Root"]);
+    repo.git(&["update-ref", "refs/heads/main", &root]);
+    let tip = repo.git(&["commit-tree", &tree, "-p", &root, "-m", "Second"]);
+    repo.git(&["update-ref", "refs/heads/main", &tip]);
+
+    repo.git_anon()
+        .args(["--yes", "anonymize"])
+        .assert()
+        .success();
+    assert_eq!(repo.authors("main"), [ANONYMOUS, ANONYMOUS]);
+
+    repo.git_anon()
+        .args(["--yes", "squash", "--no-backup"])
+        .assert()
+        .success();
+    assert_eq!(repo.authors("main"), [ANONYMOUS]);
+    assert_eq!(repo.git(&["rev-parse", "main^{tree}"]), tree);
+}
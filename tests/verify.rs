@@ -0,0 +1,70 @@
+mod common;
+
+use common::{ANONYMOUS, REAL, TestRepo};
+use predicates::str::contains;
+
+#[test]
+fn a_real_identity_fails_verification() {
+    let repo = TestRepo::new();
+    repo.commit_as("a.txt", "a", ANONYMOUS);
+    repo.commit("b.txt", "b");
+
+    repo.git_anon()
+        .arg("verify")
+        .assert()
+        .failure()
+        .stdout(contains(REAL))
+        .stderr(contains("1 real identities found in the history"));
+}
+
+#[test]
+fn an_anonymous_history_passes() {
+    let repo = TestRepo::new();
+    repo.commit_as("a.txt", "a", ANONYMOUS);
+    repo.commit_as("b.txt", "b", ANONYMOUS);
+
+    repo.git_anon()
+        .arg("verify")
+        .assert()
+        .success()
+        .stdout(contains("Scanned 2 commits"))
+        .stdout(contains("No real identities found"));
+}
+
+#[test]
+fn all_refs_scans_other_branches_but_not_backups() {
+    let repo = TestRepo::new();
+    repo.commit_as("a.txt", "a", ANONYMOUS);
+    repo.git(&["branch", "backup-main-1700000000"]);
+    repo.git(&["checkout", "--quiet", "-b", "feature"]);
+    let leak = repo.commit("b.txt", "b");
+    repo.git(&["checkout", "--quiet", "main"]);
+    repo.git(&["checkout", "--quiet", "backup-main-1700000000"]);
+    repo.commit("c.txt", "c");
+    repo.git(&["checkout", "--quiet", "main"]);
+
+    repo.git_anon().arg("verify").assert().success();
+    repo.git_anon()
+        .args(["verify", "--all-refs"])
+        .assert()
+        .failure()
+        .stdout(contains("Scanned 2 commits"))
+        .stdout(contains(format!("(first in commit {})", &leak[..8])))
+        .stderr(contains("1 real identities found in the history"));
+}
+
+#[test]
+fn tags_scans_annotated_tag_taggers() {
+    let repo = TestRepo::new();
+    repo.commit_as("a.txt", "a", ANONYMOUS);
+    repo.git(&["tag", "-a", "-m", "Release", "v1"]);
+
+    repo.git_anon().arg("verify").assert().success();
+    repo.git_anon()
+        .args(["verify", "--tags"])
+        .assert()
+        .failure()
+        .stdout(contains("and 1 annotated tags"))
+        .stdout(contains(REAL))
+        .stderr(contains("1 real identities found in the history"));
+}
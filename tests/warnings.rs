@@ -0,0 +1,70 @@
+mod common;
+
+use common::TestRepo;
+use predicates::prelude::*;
+
+#[test]
+fn large_blobs_are_reported_with_path_and_commit() {
+    let repo = TestRepo::new();
+    repo.commit("small.txt", "small");
+    let commit = repo.commit("big.bin", &"x".repeat(4096));
+
+    repo.git_anon()
+        .args(["--dry-run", "--warn-large-blobs", "1K", "squash"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(format!(
+            "big.bin (4096 bytes, introduced in {})",
+            &commit[..8]
+        )))
+        .stderr(predicates::str::contains("small.txt").not());
+}
+
+#[test]
+fn abort_on_warning_turns_a_warning_into_a_failure() {
+    let repo = TestRepo::new();
+    repo.commit("big.bin", &"x".repeat(4096));
+    let warn = ["--dry-run", "--warn-large-blobs", "1K"];
+
+    repo.git_anon().args(warn).arg("squash").assert().success();
+    repo.git_anon()
+        .args(warn)
+        .args(["--abort-on-warning", "squash"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Aborting: 1 warning was emitted (--abort-on-warning)",
+        ));
+
+    repo.write_config("version = 2\nabort_on_warning = true\n");
+    repo.git_anon().args(warn).arg("squash").assert().failure();
+}
+
+#[test]
+fn git_crypt_markers_are_reported() {
+    let repo = TestRepo::new();
+    repo.write(".git-crypt/keys/default/0/ABCDEF.gpg", "key");
+    repo.write(
+        ".gitattributes",
+        "secrets/** filter=git-crypt diff=git-crypt\n",
+    );
+    repo.commit("README", "readme");
+    repo.add_remote("origin");
+
+    for command in [&["clean"][..], &["push", "origin"]] {
+        repo.git_anon()
+            .arg("--dry-run")
+            .args(command)
+            .assert()
+            .success()
+            .stderr(predicates::str::contains(
+                "encrypted content is not anonymized",
+            ))
+            .stderr(predicates::str::contains(
+                ".git-crypt/ holds git-crypt key metadata",
+            ))
+            .stderr(predicates::str::contains(
+                ".gitattributes marks files for git-crypt",
+            ));
+    }
+}